@@ -0,0 +1,39 @@
+//! Times command-name completion against a `PATH` of 10,000 executables.
+//! Run with `cargo bench`; it fails if a completion takes a millisecond or
+//! more.
+
+use std::time::{Duration, Instant};
+
+use codecrafters_shell::shell::CommandCompleter;
+use rustyline::Context;
+use rustyline::completion::Completer;
+use rustyline::history::DefaultHistory;
+
+const COMMANDS: usize = 10_000;
+const ROUNDS: usize = 200;
+const BUDGET: Duration = Duration::from_millis(1);
+
+fn main() {
+    let names: Vec<String> = (0..COMMANDS)
+        .map(|idx| format!("{}-tool-{idx}", ["git", "kube", "cargo", "docker"][idx % 4]))
+        .collect();
+    let name_refs: Vec<&str> = names.iter().map(String::as_str).collect();
+    let completer = CommandCompleter::new(&name_refs);
+    let history = DefaultHistory::new();
+    let ctx = Context::new(&history);
+
+    for prefix in ["g", "kube-tool-1", "docker-tool-999", "x"] {
+        let mut times = Vec::with_capacity(ROUNDS);
+        let mut found = 0;
+        for _ in 0..ROUNDS {
+            let start = Instant::now();
+            let (_, candidates) = completer.complete(prefix, prefix.len(), &ctx).unwrap();
+            times.push(start.elapsed());
+            found = candidates.len();
+        }
+        times.sort();
+        let median = times[ROUNDS / 2];
+        println!("{prefix:<16} {found:>5} candidates  median {median:?}");
+        assert!(median < BUDGET, "completing {prefix:?} took {median:?}");
+    }
+}
@@ -1,4 +1,6 @@
 pub mod completer;
+pub mod highlight;
+pub mod lexer;
 mod meta;
 
 use std::{
@@ -11,27 +13,97 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::command::meta::MetaSymbolExpander;
+pub use crate::command::meta::MetaSymbolExpander;
 
 pub const BUILTIN_COMMAND_NAMES: &[&str] = &[
-    "exit", "echo", "type", "pwd", "cd", ">", "1>", "2>", ">>", "1>>", "2>>",
+    "exit", "echo", "type", "pwd", "cd", "pushd", "popd", "dirs", "printf", "read", "source", ".",
+    "export", "history", "jobs", "fg", "bg", "wait", "disown", "trap", "set", "shopt", "complete",
+    "compgen", "hash", "rehash", ">", "1>", "2>", ">|", "1>|", "2>|", ">>", "1>>", "2>>",
 ];
 
+/// Supplies the values of `$name` parameters while a line is being parsed.
+pub trait Lookup {
+    fn variable(&self, name: &str) -> Option<String>;
+
+    /// Called when an unset parameter is expanded, for `set -u`.
+    fn unset_variable(&self, _name: &str) {}
+
+    /// Where the executable `name` is, searching `PATH` for names without
+    /// a slash and taking the others relative to the working directory.
+    fn command_path(&self, name: &str) -> Option<PathBuf> {
+        Command::find_executable(name, &env::current_dir().unwrap_or_default())
+    }
+
+    /// The directory stack entry `~spec` refers to, such as `~1` or `~-0`.
+    fn directory_stack_entry(&self, _spec: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether `name` is a shell function, which runs in place of any
+    /// builtin or executable of that name.
+    fn is_function(&self, _name: &str) -> bool {
+        false
+    }
+}
+
+/// Resolves parameters from the process environment only.
+pub struct ProcessEnv;
+
+impl Lookup for ProcessEnv {
+    fn variable(&self, name: &str) -> Option<String> {
+        env::var(name).ok()
+    }
+}
+
 #[derive(Debug)]
 pub enum Command {
     Exit,
-    Echo(String),
+    Echo(Vec<String>),
+    Printf(Vec<String>),
+    Read(Vec<String>),
     Type(Vec<Command>),
-    Pwd,
-    Cd(PathBuf),
+    Pwd(Vec<String>),
+    Cd(Vec<String>),
+    Pushd(Vec<String>),
+    Popd(Vec<String>),
+    Dirs(Vec<String>),
+    Source {
+        file: String,
+        args: Vec<String>,
+    },
+    Export(Vec<String>),
+    History(Vec<String>),
+    Jobs(Vec<String>),
+    Fg(Vec<String>),
+    Bg(Vec<String>),
+    Wait(Vec<String>),
+    Disown(Vec<String>),
+    Trap(Vec<String>),
+    Set(Vec<String>),
+    Shopt(Vec<String>),
+    Complete(Vec<String>),
+    Compgen(Vec<String>),
+    Hash(Vec<String>),
+    Rehash,
+    Assign {
+        assignments: Vec<(String, String)>,
+        command: Option<Box<Command>>,
+    },
     EnviromentalModifier {
         stdout_redirect: Option<RedirectInfo>,
         stderr_redirect: Option<RedirectInfo>,
     },
     External {
+        /// The name it was run as, which becomes its `argv[0]`.
+        name: String,
         exec_path: PathBuf,
         args: Vec<String>,
     },
+    /// A call to a shell function.
+    Function {
+        name: String,
+        args: Vec<String>,
+    },
     None(String),
 }
 
@@ -39,117 +111,212 @@ pub enum Command {
 pub struct RedirectInfo {
     pub file_path: PathBuf,
     pub options: OpenOptions,
+    pub kind: RedirectKind,
+}
+
+/// How a redirection opens its file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    /// `>`, which `set -C` stops from overwriting existing files.
+    Truncate,
+    /// `>|`, which truncates even under `set -C`.
+    Force,
+    /// `>>`
+    Append,
+}
+
+impl RedirectInfo {
+    pub fn new(file_path: PathBuf, kind: RedirectKind) -> Self {
+        let mut options = OpenOptions::new();
+        match kind {
+            RedirectKind::Truncate | RedirectKind::Force => {
+                options.create(true).write(true).truncate(true)
+            }
+            RedirectKind::Append => options.create(true).append(true),
+        };
+        Self {
+            file_path,
+            options,
+            kind,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
 enum CommandPartial {
     Exit,
     Echo,
+    Printf,
+    Read,
     Type,
     Pwd,
     Cd,
+    Pushd,
+    Popd,
+    Dirs,
+    Source,
+    Export,
+    History,
+    Jobs,
+    Fg,
+    Bg,
+    Wait,
+    Disown,
+    Trap,
+    Set,
+    Shopt,
+    Complete,
+    Compgen,
+    Hash,
+    Rehash,
+    Assignment(String),
+    Function(String),
     StdOutRedirect,
+    StdOutRedirectForce,
     StdOutRedirectAppend,
     StdErrRedirect,
+    StdErrRedirectForce,
     StdErrRedirectAppend,
     Unknown(String),
 }
 
 impl CommandPartial {
+    /// Like `parse`, but the name of a shell function wins over builtins.
+    fn parse_command(input: &str, lookup: &dyn Lookup) -> CommandPartial {
+        if lookup.is_function(input) {
+            return Self::Function(input.to_string());
+        }
+        Self::parse(input)
+    }
+
     fn parse(input: &str) -> CommandPartial {
         match input {
             ">" | "1>" => Self::StdOutRedirect,
+            ">|" | "1>|" => Self::StdOutRedirectForce,
             ">>" | "1>>" => Self::StdOutRedirectAppend,
             "2>" => Self::StdErrRedirect,
+            "2>|" => Self::StdErrRedirectForce,
             "2>>" => Self::StdErrRedirectAppend,
             "exit" => Self::Exit,
             "echo" => Self::Echo,
+            "printf" => Self::Printf,
+            "read" => Self::Read,
             "type" => Self::Type,
             "pwd" => Self::Pwd,
             "cd" => Self::Cd,
+            "pushd" => Self::Pushd,
+            "popd" => Self::Popd,
+            "dirs" => Self::Dirs,
+            "source" | "." => Self::Source,
+            "export" => Self::Export,
+            "history" => Self::History,
+            "jobs" => Self::Jobs,
+            "fg" => Self::Fg,
+            "bg" => Self::Bg,
+            "wait" => Self::Wait,
+            "disown" => Self::Disown,
+            "trap" => Self::Trap,
+            "set" => Self::Set,
+            "shopt" => Self::Shopt,
+            "complete" => Self::Complete,
+            "compgen" => Self::Compgen,
+            "hash" => Self::Hash,
+            "rehash" => Self::Rehash,
+            other if split_assignment(other).is_some() => Self::Assignment(other.to_string()),
             other => Self::Unknown(other.to_string()),
         }
     }
 
-    fn can_be_chained_after(&self, other: &CommandPartial) -> bool {
-        match other {
-            _ => match self {
-                Self::StdErrRedirect
+    fn can_be_chained_after(&self, _other: &CommandPartial) -> bool {
+        self.is_redirect()
+    }
+
+    fn is_redirect(&self) -> bool {
+        matches!(
+            self,
+            Self::StdErrRedirect
+                | Self::StdErrRedirectForce
                 | Self::StdOutRedirect
+                | Self::StdOutRedirectForce
                 | Self::StdOutRedirectAppend
-                | Self::StdErrRedirectAppend => true,
-                _ => false,
-            },
-        }
+                | Self::StdErrRedirectAppend
+        )
     }
 
-    fn into_full(&self, args: &Vec<String>) -> Command {
+    fn to_full(&self, args: &[String], lookup: &dyn Lookup) -> Command {
         match self {
             Self::Exit => Command::Exit,
-            Self::Echo => Command::Echo(args.join(" ")),
-            Self::Pwd => Command::Pwd,
-            Self::Cd => Command::Cd(PathBuf::from(args.join(""))),
-            Self::StdOutRedirect => {
-                let mut options = OpenOptions::new();
-                options.create(true).write(true).truncate(true);
-
-                Command::EnviromentalModifier {
-                    stdout_redirect: Some(RedirectInfo {
-                        file_path: PathBuf::from(args.join("")),
-                        options,
-                    }),
-                    stderr_redirect: None,
-                }
-            }
-            Self::StdErrRedirect => {
-                let mut options = OpenOptions::new();
-                options.create(true).write(true).truncate(true);
-
-                Command::EnviromentalModifier {
-                    stdout_redirect: None,
-                    stderr_redirect: Some(RedirectInfo {
-                        file_path: PathBuf::from(args.join("")),
-                        options,
-                    }),
+            Self::Echo => Command::Echo(args.to_vec()),
+            Self::Printf => Command::Printf(args.to_vec()),
+            Self::Read => Command::Read(args.to_vec()),
+            Self::Pwd => Command::Pwd(args.to_vec()),
+            Self::Cd => Command::Cd(args.to_vec()),
+            Self::Pushd => Command::Pushd(args.to_vec()),
+            Self::Popd => Command::Popd(args.to_vec()),
+            Self::Dirs => Command::Dirs(args.to_vec()),
+            Self::Source => Command::Source {
+                file: args.first().cloned().unwrap_or_default(),
+                args: args.iter().skip(1).cloned().collect(),
+            },
+            Self::Export => Command::Export(args.to_vec()),
+            Self::History => Command::History(args.to_vec()),
+            Self::Jobs => Command::Jobs(args.to_vec()),
+            Self::Fg => Command::Fg(args.to_vec()),
+            Self::Bg => Command::Bg(args.to_vec()),
+            Self::Wait => Command::Wait(args.to_vec()),
+            Self::Disown => Command::Disown(args.to_vec()),
+            Self::Trap => Command::Trap(args.to_vec()),
+            Self::Set => Command::Set(args.to_vec()),
+            Self::Shopt => Command::Shopt(args.to_vec()),
+            Self::Complete => Command::Complete(args.to_vec()),
+            Self::Compgen => Command::Compgen(args.to_vec()),
+            Self::Hash => Command::Hash(args.to_vec()),
+            Self::Rehash => Command::Rehash,
+            Self::Assignment(word) => {
+                let mut assignments = Vec::new();
+                assignments.extend(split_assignment(word));
+                let mut rest = args.iter();
+                let mut command = None;
+                while let Some(arg) = rest.next() {
+                    if let Some(assignment) = split_assignment(arg) {
+                        assignments.push(assignment);
+                    } else {
+                        let remaining: Vec<String> = rest.cloned().collect();
+                        command = Some(Box::new(
+                            CommandPartial::parse_command(arg, lookup).to_full(&remaining, lookup),
+                        ));
+                        break;
+                    }
                 }
-            }
-            Self::StdOutRedirectAppend => {
-                let mut options = OpenOptions::new();
-                options.create(true).append(true);
-
-                Command::EnviromentalModifier {
-                    stdout_redirect: Some(RedirectInfo {
-                        file_path: PathBuf::from(args.join("")),
-                        options,
-                    }),
-                    stderr_redirect: None,
-                }
-            }
-            Self::StdErrRedirectAppend => {
-                let mut options = OpenOptions::new();
-                options.create(true).append(true);
-
-                Command::EnviromentalModifier {
-                    stdout_redirect: None,
-                    stderr_redirect: Some(RedirectInfo {
-                        file_path: PathBuf::from(args.join("")),
-                        options,
-                    }),
+                Command::Assign {
+                    assignments,
+                    command,
                 }
             }
+            Self::StdOutRedirect => Command::stdout_redirect(args, RedirectKind::Truncate),
+            Self::StdOutRedirectForce => Command::stdout_redirect(args, RedirectKind::Force),
+            Self::StdOutRedirectAppend => Command::stdout_redirect(args, RedirectKind::Append),
+            Self::StdErrRedirect => Command::stderr_redirect(args, RedirectKind::Truncate),
+            Self::StdErrRedirectForce => Command::stderr_redirect(args, RedirectKind::Force),
+            Self::StdErrRedirectAppend => Command::stderr_redirect(args, RedirectKind::Append),
             Self::Type => {
                 let inner_commands: Vec<Command> = args
                     .iter()
-                    .flat_map(|arg| Command::parse(arg).commands)
+                    .flat_map(|arg| Command::parse_with(arg, lookup).commands)
                     .collect();
                 Command::Type(inner_commands)
             }
+            Self::Function(name) => Command::Function {
+                name: name.clone(),
+                args: args.to_vec(),
+            },
             Self::Unknown(value) => {
-                let exec_path = Command::get_executable_path(value);
+                let exec_path = lookup.command_path(value);
                 if let Some(path) = exec_path {
                     Command::External {
+                        name: value.clone(),
                         exec_path: path,
-                        args: args.iter().map(|arg| String::from(arg)).collect(),
+                        args: args.to_vec(),
                     }
                 } else {
                     Command::None(value.clone())
@@ -161,8 +328,13 @@ impl CommandPartial {
 
 impl Command {
     pub fn parse(input: &str) -> CommandResult<'_> {
-        let trimmed_input = input.trim();
-        let tokens_iter = MetaSymbolExpander::new(trimmed_input.chars());
+        Self::parse_with(input, &ProcessEnv)
+    }
+
+    /// Parses `input`, resolving `$name` parameters through `lookup`.
+    pub fn parse_with<'a>(input: &'a str, lookup: &dyn Lookup) -> CommandResult<'a> {
+        let (trimmed_input, background) = split_background(input.trim());
+        let tokens_iter = MetaSymbolExpander::with_lookup(trimmed_input.chars(), lookup);
 
         let mut commands: Vec<Command> = Vec::with_capacity(10);
 
@@ -175,25 +347,31 @@ impl Command {
             stdout_redirect: None,
         });
 
+        let mut words = Vec::new();
+
         for token in tokens_iter {
             if current_partial.is_none() {
-                current_partial = Some(CommandPartial::parse(&token));
+                let partial = CommandPartial::parse_command(&token, lookup);
+                if !partial.is_redirect() {
+                    words.push(token);
+                }
+                current_partial = Some(partial);
                 continue;
             }
 
             let new_partial_cmd = CommandPartial::parse(&token);
+            if !new_partial_cmd.is_redirect() && !current_partial.as_ref().unwrap().is_redirect() {
+                words.push(token.clone());
+            }
             if new_partial_cmd.can_be_chained_after(current_partial.as_ref().unwrap()) {
                 match current_partial.as_ref().unwrap() {
-                    curr_partial @ CommandPartial::StdOutRedirect
-                    | curr_partial @ CommandPartial::StdErrRedirect
-                    | curr_partial @ CommandPartial::StdOutRedirectAppend
-                    | curr_partial @ CommandPartial::StdErrRedirectAppend => {
+                    curr_partial if curr_partial.is_redirect() => {
                         if let Some(Command::EnviromentalModifier {
                             stdout_redirect,
                             stderr_redirect,
                         }) = commands.get_mut(last_env_mod_index)
                         {
-                            let new_env_mod_cmd = curr_partial.into_full(&current_args);
+                            let new_env_mod_cmd = curr_partial.to_full(&current_args, lookup);
                             if let Command::EnviromentalModifier {
                                 stdout_redirect: new_stdout_redirect,
                                 stderr_redirect: new_stderr_redirect,
@@ -218,7 +396,7 @@ impl Command {
                         last_env_mod_index = commands.len() - 1;
                     }
                     _ => {
-                        commands.push(current_partial.unwrap().into_full(&current_args));
+                        commands.push(current_partial.unwrap().to_full(&current_args, lookup));
                     }
                 }
                 current_partial = Some(new_partial_cmd);
@@ -230,10 +408,7 @@ impl Command {
 
         if let Some(partial_cmd) = current_partial {
             match partial_cmd {
-                CommandPartial::StdErrRedirect
-                | CommandPartial::StdOutRedirect
-                | CommandPartial::StdErrRedirectAppend
-                | CommandPartial::StdOutRedirectAppend => {
+                ref partial_cmd if partial_cmd.is_redirect() => {
                     if let Some(Command::EnviromentalModifier {
                         stdout_redirect,
                         stderr_redirect,
@@ -242,7 +417,7 @@ impl Command {
                         if let Command::EnviromentalModifier {
                             stdout_redirect: new_stdout_redirect,
                             stderr_redirect: new_stderr_redirect,
-                        } = partial_cmd.into_full(&current_args)
+                        } = partial_cmd.to_full(&current_args, lookup)
                         {
                             if new_stdout_redirect.is_some() {
                                 *stdout_redirect = new_stdout_redirect;
@@ -258,7 +433,7 @@ impl Command {
                     }
                 }
                 _ => {
-                    commands.push(partial_cmd.into_full(&current_args));
+                    commands.push(partial_cmd.to_full(&current_args, lookup));
                 }
             }
         }
@@ -266,10 +441,42 @@ impl Command {
         CommandResult {
             input: trimmed_input,
             commands,
+            words,
+            background,
         }
     }
 
-    fn get_executable_path(input: &str) -> Option<PathBuf> {
+    fn stdout_redirect(args: &[String], kind: RedirectKind) -> Command {
+        Command::EnviromentalModifier {
+            stdout_redirect: Some(RedirectInfo::new(PathBuf::from(args.join("")), kind)),
+            stderr_redirect: None,
+        }
+    }
+
+    fn stderr_redirect(args: &[String], kind: RedirectKind) -> Command {
+        Command::EnviromentalModifier {
+            stdout_redirect: None,
+            stderr_redirect: Some(RedirectInfo::new(PathBuf::from(args.join("")), kind)),
+        }
+    }
+
+    /// Whether `name` would run something in `cwd`: a builtin or an
+    /// executable `find_executable` finds.
+    pub fn is_known_command(name: &str, cwd: &Path) -> bool {
+        BUILTIN_COMMAND_NAMES.contains(&name) || Command::find_executable(name, cwd).is_some()
+    }
+
+    /// Where the executable `name` is: a name with a slash is a path taken
+    /// relative to `cwd`, and any other name is searched for on `PATH`.
+    pub fn find_executable(name: &str, cwd: &Path) -> Option<PathBuf> {
+        if name.contains('/') {
+            let path = cwd.join(name);
+            return Command::is_executable(&path).then_some(path);
+        }
+        Command::get_executable_path(name)
+    }
+
+    pub fn get_executable_path(input: &str) -> Option<PathBuf> {
         let path = env::var_os("PATH").unwrap_or_default();
         for dir in env::split_paths(&path) {
             let exec_path = dir.join(input);
@@ -280,7 +487,7 @@ impl Command {
         None
     }
 
-    fn is_executable(path: &Path) -> bool {
+    pub fn is_executable(path: &Path) -> bool {
         fs::metadata(path)
             .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
             .unwrap_or(false)
@@ -291,9 +498,30 @@ impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Command::Exit => write!(f, "exit"),
-            Command::Pwd => write!(f, "pwd"),
+            Command::Pwd(_) => write!(f, "pwd"),
             Command::Echo(_) => write!(f, "echo"),
+            Command::Printf(_) => write!(f, "printf"),
+            Command::Read(_) => write!(f, "read"),
             Command::Cd(_) => write!(f, "cd"),
+            Command::Pushd(_) => write!(f, "pushd"),
+            Command::Popd(_) => write!(f, "popd"),
+            Command::Dirs(_) => write!(f, "dirs"),
+            Command::Source { .. } => write!(f, "source"),
+            Command::Export(_) => write!(f, "export"),
+            Command::History(_) => write!(f, "history"),
+            Command::Jobs(_) => write!(f, "jobs"),
+            Command::Fg(_) => write!(f, "fg"),
+            Command::Bg(_) => write!(f, "bg"),
+            Command::Wait(_) => write!(f, "wait"),
+            Command::Disown(_) => write!(f, "disown"),
+            Command::Trap(_) => write!(f, "trap"),
+            Command::Set(_) => write!(f, "set"),
+            Command::Shopt(_) => write!(f, "shopt"),
+            Command::Complete(_) => write!(f, "complete"),
+            Command::Compgen(_) => write!(f, "compgen"),
+            Command::Hash(_) => write!(f, "hash"),
+            Command::Rehash => write!(f, "rehash"),
+            Command::Assign { .. } => write!(f, ""),
             Command::Type(_) => write!(f, "type"),
             Command::EnviromentalModifier { .. } => {
                 write!(f, "")
@@ -306,9 +534,223 @@ impl fmt::Display for Command {
                     exec_path.display()
                 )
             }
-            Command::None(name) => write!(f, "{name}"),
+            Command::Function { name, .. } | Command::None(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Strips a trailing `&` control operator, reporting whether there was one.
+fn split_background(input: &str) -> (&str, bool) {
+    match input.strip_suffix('&') {
+        Some(rest) if !rest.ends_with(['&', '\\', '>', '<']) => (rest.trim_end(), true),
+        _ => (input, false),
+    }
+}
+
+/// Why a line can't be run yet and has to continue on the next one.
+#[derive(Debug, PartialEq)]
+pub enum Continuation {
+    /// A quoted string is still open; the newline belongs to it.
+    Quote(char),
+    /// A function definition's body has no closing brace yet.
+    Function,
+    /// The line ends with a backslash, which joins it to the next.
+    Escape,
+}
+
+impl Continuation {
+    /// Appends the next line to `line`, which this continuation left
+    /// unfinished.
+    pub fn join(&self, line: &mut String, next: &str) {
+        match self {
+            Continuation::Escape => _ = line.pop(),
+            Continuation::Quote(_) | Continuation::Function => line.push('\n'),
+        }
+        line.push_str(next);
+    }
+}
+
+/// Checks whether `input` ends inside quotes, with a line-continuation
+/// backslash or inside the body of a function definition.
+pub fn continuation(input: &str) -> Option<Continuation> {
+    let mut quote = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', Some('\'')) => {}
+            // The guard also skips over the escaped character
+            ('\\', _) if chars.next().is_none() => return Some(Continuation::Escape),
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    if let Some(quote) = quote {
+        return Some(Continuation::Quote(quote));
+    }
+    let last_command = &input[last_command_start(input)..];
+    unclosed_function(last_command).then_some(Continuation::Function)
+}
+
+/// Where the last command of the list in `line` starts.
+fn last_command_start(line: &str) -> usize {
+    let mut start = 0;
+    for word in lexer::lex(line) {
+        if word.kind == lexer::WordKind::Separator
+            && !unclosed_function(&line[start..word.range.start])
+        {
+            start = word.range.end;
+        }
+    }
+    start
+}
+
+/// Whether `command` opens the body of a function definition without
+/// closing it.
+fn unclosed_function(command: &str) -> bool {
+    function_header(command).is_some() && function_definition(command).is_none()
+}
+
+/// Splits a `name() { body; }` or `function name { body; }` definition into
+/// the function's name and its body. As in bash, the closing brace has to
+/// follow a `;` or a newline.
+pub fn function_definition(input: &str) -> Option<(String, String)> {
+    let (name, rest) = function_header(input)?;
+    let body = rest.trim_end().strip_suffix('}')?;
+    let ended = body.trim_end_matches([' ', '\t']).ends_with([';', '\n']);
+    (ended && continuation(body).is_none()).then(|| (name, body.to_string()))
+}
+
+/// The name of the function `input` starts to define, and what follows the
+/// opening brace of its body.
+fn function_header(input: &str) -> Option<(String, &str)> {
+    let input = input.trim_start();
+    let (name, rest) = match input.strip_prefix("function ") {
+        Some(rest) => {
+            let rest = rest.trim_start();
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '(')
+                .unwrap_or(rest.len());
+            let (name, rest) = rest.split_at(end);
+            let rest = rest.trim_start();
+            let rest = match rest.strip_prefix('(') {
+                Some(parens) => parens.trim_start().strip_prefix(')')?,
+                None => rest,
+            };
+            (name, rest)
+        }
+        None => {
+            let (name, rest) = input.split_once('(')?;
+            (name.trim_end(), rest.trim_start().strip_prefix(')')?)
+        }
+    };
+
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'));
+    let body = rest.trim_start().strip_prefix('{')?;
+    // `{` is a word of its own
+    let body_starts = body.is_empty() || body.starts_with(char::is_whitespace);
+    (valid_name && body_starts).then(|| (name.to_string(), body))
+}
+
+/// Splits a `NAME=(word ...)` array assignment into the name and the text
+/// between the parentheses.
+pub fn array_assignment(input: &str) -> Option<(String, &str)> {
+    let input = input.trim();
+    let (name, rest) = input.split_once("=(")?;
+    let elements = rest.strip_suffix(')')?;
+    (is_valid_name(name) && continuation(input).is_none()).then(|| (name.to_string(), elements))
+}
+
+/// Splits `text`, such as the body of a function, into its lines, joining
+/// a line left unfinished with the ones after it.
+pub fn split_commands(text: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut pending: Option<String> = None;
+    for next in text.lines() {
+        let line = match pending.take() {
+            Some(mut line) => {
+                if let Some(continuation) = continuation(&line) {
+                    continuation.join(&mut line, next);
+                }
+                line
+            }
+            None => next.to_string(),
+        };
+        if continuation(&line).is_some() {
+            pending = Some(line);
+        } else if !line.trim().is_empty() {
+            commands.push(line);
+        }
+    }
+    commands.extend(pending);
+    commands
+}
+
+/// How a command in a list depends on the one before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListOp {
+    /// First on the line, or after `;` or `&`: it always runs.
+    Always,
+    /// After `&&`: it runs if the one before succeeded.
+    And,
+    /// After `||`: it runs if the one before failed.
+    Or,
+}
+
+/// Splits `line` into the commands of a list, separated by unquoted `;`,
+/// `&`, `&&` and `||`. A `&` stays on the command it sends to the
+/// background, and the separators in a function body stay in its
+/// definition. Comments are left out. Pipelines aren't supported, so a `|`
+/// is returned as an error.
+pub fn split_list(line: &str) -> Result<Vec<(ListOp, String)>, String> {
+    let mut commands = Vec::new();
+    let mut op = ListOp::Always;
+    let mut start = 0;
+    let mut end = line.len();
+    for word in lexer::lex(line) {
+        let inside_function = unclosed_function(&line[start..word.range.start]);
+        match word.kind {
+            lexer::WordKind::Separator if !inside_function => {
+                let (command_end, next) = match word.text.as_str() {
+                    ";" => (word.range.start, ListOp::Always),
+                    "&" => (word.range.end, ListOp::Always),
+                    "&&" => (word.range.start, ListOp::And),
+                    "||" => (word.range.start, ListOp::Or),
+                    _ => return Err(word.text),
+                };
+                commands.push((op, line[start..command_end].to_string()));
+                op = next;
+                start = word.range.end;
+            }
+            lexer::WordKind::Comment if !inside_function => end = word.range.start,
+            _ => {}
         }
     }
+    commands.push((op, line[start..end].to_string()));
+    commands.retain(|(_, command)| !command.trim().is_empty());
+    Ok(commands)
+}
+
+/// Splits a `NAME=value` word into its name and value, if `NAME` is a valid
+/// variable name.
+pub fn split_assignment(word: &str) -> Option<(String, String)> {
+    let (name, value) = word.split_once('=')?;
+    if is_valid_name(name) {
+        Some((name.to_string(), value.to_string()))
+    } else {
+        None
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
 pub fn get_external_commands(path: OsString) -> HashMap<OsString, PathBuf> {
@@ -351,7 +793,184 @@ pub fn get_external_commands(path: OsString) -> HashMap<OsString, PathBuf> {
 pub struct CommandResult<'a> {
     input: &'a str,
     pub commands: Vec<Command>,
+    /// The expanded words of the line without its redirections, as
+    /// `set -x` prints them.
+    pub words: Vec<String>,
+    /// Whether the line ended with `&`.
+    pub background: bool,
+}
+
+impl CommandResult<'_> {
+    /// The trimmed source line the commands were parsed from.
+    pub fn input(&self) -> &str {
+        self.input
+    }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn assignment_words_are_recognised() {
+        assert_eq!(
+            Some(("FOO".to_string(), "a=b".to_string())),
+            split_assignment("FOO=a=b")
+        );
+        assert_eq!(None, split_assignment("1FOO=bar"));
+        assert_eq!(None, split_assignment("=bar"));
+        assert_eq!(None, split_assignment("--opt=bar"));
+    }
+
+    #[test]
+    fn assignments_prefix_a_command() {
+        let result = Command::parse("A=1 B=2 echo hi");
+        match &result.commands[1] {
+            Command::Assign {
+                assignments,
+                command: Some(command),
+            } => {
+                assert_eq!(2, assignments.len());
+                assert!(matches!(**command, Command::Echo(ref args) if args == &["hi"]));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn names_with_a_slash_are_relative_to_the_working_directory() {
+        let dir = TempDir::new("relative");
+        let script = dir.join("s.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert_eq!(
+            Some(dir.join("./s.sh")),
+            Command::find_executable("./s.sh", dir.path())
+        );
+        assert!(Command::is_known_command("./s.sh", dir.path()));
+        assert!(!Command::is_known_command("./s.sh", Path::new("/")));
+        assert!(!Command::is_known_command("s.sh", dir.path()));
+    }
+
+    #[test]
+    fn force_redirect_is_distinguished() {
+        let result = Command::parse("echo hi >| out.txt 2> err.txt");
+        let kinds: Vec<RedirectKind> = result
+            .commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::EnviromentalModifier {
+                    stdout_redirect,
+                    stderr_redirect,
+                } => stdout_redirect.as_ref().or(stderr_redirect.as_ref()),
+                _ => None,
+            })
+            .map(|redirect| redirect.kind)
+            .collect();
+        assert_eq!(vec![RedirectKind::Force, RedirectKind::Truncate], kinds);
+        assert_eq!(vec!["echo", "hi"], result.words);
+    }
+
+    #[test]
+    fn unfinished_lines_need_continuation() {
+        assert_eq!(None, continuation("echo 'a b' \"c\""));
+        assert_eq!(Some(Continuation::Quote('\'')), continuation("echo 'it"));
+        assert_eq!(Some(Continuation::Quote('"')), continuation("echo \"a 'b"));
+        assert_eq!(Some(Continuation::Escape), continuation("echo a \\"));
+        assert_eq!(None, continuation("echo a \\\\"));
+        assert_eq!(None, continuation("echo 'a\\'"));
+        assert_eq!(Some(Continuation::Function), continuation("f() {"));
+        assert_eq!(Some(Continuation::Function), continuation("f() { echo }"));
+        assert_eq!(None, continuation("f() { echo; }"));
+        assert_eq!(None, continuation("f() { echo; }; f"));
+        assert_eq!(
+            Some(Continuation::Function),
+            continuation("cd /; f() { a; b")
+        );
+    }
+
+    #[test]
+    fn function_definitions_are_recognised() {
+        let def = |input| function_definition(input);
+        assert_eq!(
+            Some(("greet".to_string(), " echo hi; ".to_string())),
+            def("greet() { echo hi; }")
+        );
+        assert_eq!(
+            Some(("_git".to_string(), "\n  ls\n".to_string())),
+            def("function _git () {\n  ls\n}")
+        );
+        assert!(def("function f { x=1; }").is_some());
+        assert_eq!(None, def("echo (a) { b; }"));
+        assert_eq!(None, def("f() {echo; }"));
+        assert_eq!(None, def("f() { echo '}'"));
+    }
+
+    #[test]
+    fn array_assignments_are_recognised() {
+        assert_eq!(
+            Some(("A".to_string(), "a 'b c'")),
+            array_assignment("A=(a 'b c')")
+        );
+        assert_eq!(None, array_assignment("A='(a b)'"));
+        assert_eq!(None, array_assignment("A=(a ')'"));
+    }
+
+    #[test]
+    fn function_bodies_are_split_into_lines() {
+        let commands = split_commands("a=1; echo 'x;y'\n\necho \"a\nb\"; ls # end");
+        assert_eq!(vec!["a=1; echo 'x;y'", "echo \"a\nb\"; ls # end"], commands);
+    }
+
+    #[test]
+    fn lists_are_split_at_their_operators() {
+        let list = |line| split_list(line).unwrap();
+        assert_eq!(
+            vec![
+                (ListOp::Always, "a=1".to_string()),
+                (ListOp::Always, " echo 'x;y' ".to_string()),
+                (ListOp::And, " ls".to_string()),
+                (ListOp::Or, "pwd ".to_string()),
+            ],
+            list("a=1; echo 'x;y' && ls||pwd # end")
+        );
+        assert_eq!(
+            vec![
+                (ListOp::Always, "sleep 1 &".to_string()),
+                (ListOp::Always, " echo a\\;b".to_string()),
+            ],
+            list("sleep 1 & echo a\\;b;")
+        );
+        assert_eq!(
+            vec![
+                (ListOp::Always, "f() { a; b; }".to_string()),
+                (ListOp::Always, " f".to_string()),
+            ],
+            list("f() { a; b; }; f")
+        );
+        assert_eq!(Err("|".to_string()), split_list("ls | wc"));
+        assert_eq!(1, list("echo hi >| out").len());
+    }
+
+    #[test]
+    fn trailing_ampersand_runs_in_background() {
+        let result = Command::parse("sleep 10 &");
+        assert!(result.background);
+        assert_eq!("sleep 10", result.input());
+
+        assert!(Command::parse("sleep 10&").background);
+        assert!(!Command::parse("echo \\&").background);
+        assert!(!Command::parse("true &&").background);
+    }
+
+    #[test]
+    fn dot_is_source() {
+        let result = Command::parse(". ./env.sh one two");
+        assert!(matches!(
+            &result.commands[1],
+            Command::Source { file, args } if file == "./env.sh" && args.len() == 2
+        ));
+    }
+}
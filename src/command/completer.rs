@@ -1,35 +1,334 @@
-use rustyline::Context;
+pub mod fuzzy;
+mod names;
+mod paths;
+pub mod spec;
+
+use std::{
+    borrow::Cow,
+    cell::{Cell, Ref, RefCell},
+    collections::{BTreeMap, HashMap},
+    env,
+    path::{Path, PathBuf},
+};
+
 use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::CmdKind;
+use rustyline::{
+    Cmd, CompletionType, ConditionalEventHandler, Context, Event, EventContext, RepeatCount,
+};
+
+use crate::command::Command;
+use crate::command::highlight::{self, ColorScheme};
+use crate::command::lexer::{self, Word, WordKind};
+use crate::shell::hash::SharedCommandCache;
+use crate::shell::history::SharedHistory;
+use fuzzy::Match;
+use spec::{CompLine, CompletionSpec, FunctionRunner, SharedSpecs, Source};
 
 pub struct CommandCompleter {
-    knowledge_base: PrefixTree,
+    /// Every command name that can be completed, rebuilt when the names on
+    /// `PATH` change.
+    knowledge_base: RefCell<PrefixTree>,
+    /// Names that are always commands, such as builtins.
+    fixed_commands: Vec<String>,
+    /// Executables on `PATH`, shared with the shell.
+    command_cache: Option<SharedCommandCache>,
+    /// The cache generation `knowledge_base` was last synced with, and the
+    /// names from `PATH` it then held.
+    indexed_generation: Cell<Option<u64>>,
+    indexed_path_names: RefCell<Vec<String>>,
+    /// The prompt as it should be displayed, including the non-printing
+    /// sequences the line editor must not count.
+    styled_prompt: Option<String>,
+    /// `None` turns highlighting off.
+    color_scheme: Option<ColorScheme>,
+    /// Whether the next highlight should mark matching brackets; not on the
+    /// final refresh once the line is accepted.
+    show_brackets: Cell<bool>,
+    /// Where suggestions come from; none are made from history without it.
+    history: Option<SharedHistory>,
+    /// The shell's working directory, whose history suggestions win.
+    working_dir: PathBuf,
+    /// The shell's home directory as of the prompt; without it, the user's.
+    home_dir: Option<PathBuf>,
+    /// Specs registered with `complete`.
+    specs: SharedSpecs,
+    /// The shell's variable names as of the prompt; without them, those in
+    /// the environment.
+    variables: Option<Vec<String>>,
+    /// The user names `~user` completes, read from the password database
+    /// the first time they are needed.
+    users: RefCell<Option<Vec<String>>>,
+    /// The shell's function names as of the prompt.
+    functions: Vec<String>,
+    /// Runs the shell functions of `complete -F` specs.
+    function_runner: Option<FunctionRunner>,
+    /// Whether command names match ignoring case, and by the characters in
+    /// them when nothing starts with the word, as `shopt -s fuzzycomplete`
+    /// asks.
+    fuzzy: bool,
+    /// The characters each command name listed by the last completion
+    /// matched, for highlighting.
+    matched: RefCell<HashMap<String, Vec<usize>>>,
 }
 
 impl CommandCompleter {
     pub fn new(commands: &[&str]) -> CommandCompleter {
         let mut knowledge_base = PrefixTree::new();
         for item in commands {
-            match knowledge_base.add(*item) {
+            match knowledge_base.add(item) {
                 Ok(_) => {}
                 Err(msg) => panic!("{}", msg),
             }
         }
-        CommandCompleter { knowledge_base }
+        CommandCompleter {
+            knowledge_base: RefCell::new(knowledge_base),
+            fixed_commands: commands.iter().map(|command| command.to_string()).collect(),
+            command_cache: None,
+            indexed_generation: Cell::new(None),
+            indexed_path_names: RefCell::new(Vec::new()),
+            styled_prompt: None,
+            color_scheme: None,
+            show_brackets: Cell::new(false),
+            history: None,
+            working_dir: PathBuf::new(),
+            home_dir: None,
+            specs: SharedSpecs::default(),
+            variables: None,
+            users: RefCell::new(None),
+            functions: Vec::new(),
+            function_runner: None,
+            fuzzy: false,
+            matched: RefCell::new(HashMap::new()),
+        }
     }
 
     pub fn add_commands(&mut self, commands: &[&str]) -> Result<(), &'static str> {
         for command in commands {
-            self.knowledge_base.add(command)?;
+            self.knowledge_base.get_mut().add(command)?;
+            self.fixed_commands.push(command.to_string());
         }
         Ok(())
     }
+
+    /// Completes and highlights the executables in `cache` from now on,
+    /// following it as `PATH` changes.
+    pub fn set_command_cache(&mut self, cache: SharedCommandCache) {
+        self.command_cache = Some(cache);
+        self.indexed_generation.set(None);
+    }
+
+    /// The command names, brought up to date with the command cache.
+    fn index(&self) -> Ref<'_, PrefixTree> {
+        if let Some(cache) = &self.command_cache {
+            let mut cache = cache.borrow_mut();
+            let generation = cache.generation();
+            if self.indexed_generation.get() != Some(generation) {
+                let names = cache.names();
+                let mut index = self.knowledge_base.borrow_mut();
+                let mut indexed = self.indexed_path_names.borrow_mut();
+                // both lists are sorted
+                for old in indexed.iter() {
+                    if names.binary_search(old).is_err() && !self.fixed_commands.contains(old) {
+                        index.remove(old);
+                    }
+                }
+                for name in names {
+                    if indexed.binary_search(name).is_err() {
+                        // names on PATH are never empty, the only thing add
+                        // rejects
+                        _ = index.add(name);
+                    }
+                }
+                *indexed = names.to_vec();
+                self.indexed_generation.set(Some(generation));
+            }
+        }
+        self.knowledge_base.borrow()
+    }
+
+    /// Whether `name` would run something.
+    fn is_command(&self, name: &str) -> bool {
+        if self.functions.iter().any(|function| function == name) {
+            return true;
+        }
+        match &self.command_cache {
+            Some(_) if !name.contains('/') => self.index().contains(name),
+            _ => Command::is_known_command(name, &self.working_dir),
+        }
+    }
+
+    /// Sets what to display in place of the next prompt passed to the
+    /// editor.
+    pub fn set_styled_prompt(&mut self, styled: String) {
+        self.styled_prompt = Some(styled);
+    }
+
+    pub fn set_color_scheme(&mut self, scheme: Option<ColorScheme>) {
+        self.color_scheme = scheme;
+    }
+
+    pub fn set_history(&mut self, history: SharedHistory) {
+        self.history = Some(history);
+    }
+
+    pub fn set_working_dir(&mut self, dir: PathBuf) {
+        self.working_dir = dir;
+    }
+
+    pub fn set_home_dir(&mut self, dir: Option<PathBuf>) {
+        self.home_dir = dir;
+    }
+
+    /// The directory a leading `~` stands for.
+    fn home_dir(&self) -> Option<PathBuf> {
+        self.home_dir.clone().or_else(env::home_dir)
+    }
+
+    pub fn set_completions(&mut self, specs: SharedSpecs) {
+        self.specs = specs;
+    }
+
+    pub fn set_variables(&mut self, names: Vec<String>) {
+        self.variables = Some(names);
+    }
+
+    /// Sets the user names `~user` completes, instead of those in the
+    /// password database.
+    pub fn set_users(&mut self, names: Vec<String>) {
+        *self.users.get_mut() = Some(names);
+    }
+
+    /// The user names `~user` completes.
+    fn users(&self) -> Ref<'_, [String]> {
+        self.users.borrow_mut().get_or_insert_with(names::users);
+        Ref::map(self.users.borrow(), |users| {
+            users.as_deref().unwrap_or_default()
+        })
+    }
+
+    pub fn set_functions(&mut self, names: Vec<String>) {
+        self.functions = names;
+    }
+
+    pub fn set_function_runner(&mut self, runner: FunctionRunner) {
+        self.function_runner = Some(runner);
+    }
+
+    pub fn set_fuzzy(&mut self, fuzzy: bool) {
+        self.fuzzy = fuzzy;
+    }
+
+    /// The rest of the newest history entry starting with `line`, or else
+    /// of the shortest command name starting with it.
+    fn suggest(&self, line: &str) -> Option<String> {
+        if let Some(history) = &self.history
+            && let Some(entry) = history.borrow().suggest(line, &self.working_dir)
+        {
+            return Some(entry[line.len()..].to_string());
+        }
+
+        let words = lexer::lex(line);
+        let [word] = words.as_slice() else {
+            return None;
+        };
+        if word.kind != WordKind::Command || word.range.end != line.len() || word.text != line {
+            return None;
+        }
+        // names come in lexical order, so that breaks ties in length
+        let names = self.index().starts_with(line)?;
+        let name = names
+            .iter()
+            .filter(|name| name.len() > line.len())
+            .min_by_key(|name| name.len())?;
+        Some(name[line.len()..].to_string())
+    }
+}
+
+/// Accepts the suggestion shown after the cursor, like right-arrow does.
+/// Bound to Ctrl-F; elsewhere in the line the key keeps moving forward.
+pub struct AcceptHint;
+
+impl ConditionalEventHandler for AcceptHint {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        (ctx.has_hint() && ctx.pos() == ctx.line().len()).then_some(Cmd::CompleteHint)
+    }
 }
 
 impl rustyline::Helper for CommandCompleter {}
-impl rustyline::highlight::Highlighter for CommandCompleter {}
+impl rustyline::highlight::Highlighter for CommandCompleter {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        match &self.color_scheme {
+            Some(scheme) => {
+                let bracket_pos = self.show_brackets.get().then_some(pos);
+                Cow::Owned(highlight::highlight(
+                    line,
+                    scheme,
+                    |name| self.is_command(name),
+                    bracket_pos,
+                ))
+            }
+            None => Cow::Borrowed(line),
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        self.show_brackets.set(kind != CmdKind::ForcedRefresh);
+        self.color_scheme.is_some()
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        default: bool,
+    ) -> Cow<'b, str> {
+        match &self.styled_prompt {
+            Some(styled) if default => Cow::Borrowed(styled),
+            _ => Cow::Borrowed(prompt),
+        }
+    }
+
+    fn highlight_candidate<'c>(
+        &self,
+        candidate: &'c str,
+        _completion: CompletionType,
+    ) -> Cow<'c, str> {
+        let matched = self.matched.borrow();
+        match (&self.color_scheme, matched.get(candidate)) {
+            (Some(scheme), Some(positions)) => Cow::Owned(
+                candidate
+                    .chars()
+                    .enumerate()
+                    .map(|(idx, c)| {
+                        if positions.contains(&idx) {
+                            highlight::paint(&c.to_string(), &scheme.matched)
+                        } else {
+                            c.to_string()
+                        }
+                    })
+                    .collect(),
+            ),
+            _ => Cow::Borrowed(candidate),
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        match &self.color_scheme {
+            Some(scheme) => Cow::Owned(highlight::paint(hint, &scheme.hint)),
+            None => Cow::Borrowed(hint),
+        }
+    }
+}
 impl rustyline::validate::Validator for CommandCompleter {}
 impl rustyline::hint::Hinter for CommandCompleter {
     type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() || line.trim().is_empty() {
+            return None;
+        }
+        self.suggest(line)
+    }
 }
 
 impl Completer for CommandCompleter {
@@ -41,129 +340,501 @@ impl Completer for CommandCompleter {
         pos: usize,
         _ctx: &Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Pair>)> {
-        match self.knowledge_base.starts_with(line) {
-            Some(mut results) => {
-                results.sort();
-                Ok((
-                    pos,
-                    results
-                        .iter()
-                        .map(|result| Pair {
-                            display: String::from(result),
-                            replacement: String::from(&result[pos..]) + " ",
-                        })
-                        .collect(),
-                ))
+        self.matched.borrow_mut().clear();
+        let (word, command) = lexer::word_at(line, pos);
+        if let Some(start) = variable_start(line, &word) {
+            let mut candidates = self.complete_variable(&line[start..pos]);
+            finish_unique(&mut candidates, None);
+            return Ok((start, candidates));
+        }
+        if word.open_quote.is_none()
+            && let Some((start, candidates)) = self.complete_account(line, &word)
+        {
+            return Ok((start, candidates));
+        }
+
+        if word.kind == WordKind::Argument
+            && let Some(command) = &command
+            && let Some(spec) = self.specs.get(&command.text)
+            && let Some(candidates) = self.complete_with_spec(&spec, line, pos, &word, command)
+        {
+            return Ok((word.range.start, candidates));
+        }
+
+        let (start, mut candidates) = match word.kind {
+            WordKind::Command if !word.text.contains('/') => {
+                (word.range.start, self.complete_command(&word.text))
+            }
+            WordKind::Command | WordKind::Argument | WordKind::RedirectTarget => {
+                // `cd` only takes directories
+                let dirs_only = word.kind == WordKind::Argument
+                    && command.is_some_and(|command| command.text == "cd");
+                (word.range.start, self.complete_path(&word, dirs_only))
+            }
+            _ => (pos, Vec::new()),
+        };
+        finish_unique(&mut candidates, word.open_quote);
+        Ok((start, candidates))
+    }
+}
+
+/// Closes the word when there is exactly one candidate, so typing can go
+/// straight on to the next word. A directory stays open for the names in
+/// it. With several candidates the line editor inserts their common prefix
+/// and lists them on a second Tab.
+fn finish_unique(candidates: &mut [Pair], open_quote: Option<char>) {
+    if let [only] = candidates
+        && !only.replacement.ends_with('/')
+    {
+        if let Some(quote) = open_quote {
+            only.replacement.push(quote);
+        }
+        only.replacement.push(' ');
+    }
+}
+
+impl CommandCompleter {
+    /// The command names starting with `name`. Fuzzy matching also
+    /// ignores case, and only when no name starts with `name` that way
+    /// falls back to names containing its characters, best match first.
+    fn match_commands(&self, name: &str) -> Vec<Match> {
+        let index = self.index();
+        let len = name.chars().count();
+        let prefixed = if self.fuzzy {
+            index.starts_with_ignore_case(name)
+        } else {
+            index.starts_with(name).unwrap_or_default()
+        };
+        if !prefixed.is_empty() || !self.fuzzy {
+            return prefixed
+                .into_iter()
+                .map(|name| Match::prefix(name, len))
+                .collect();
+        }
+        let names = index.starts_with("").unwrap_or_default();
+        fuzzy::rank(name, names.iter().map(String::as_str))
+    }
+
+    fn complete_command(&self, name: &str) -> Vec<Pair> {
+        let matches = self.match_commands(name);
+        let mut matched = self.matched.borrow_mut();
+        matches
+            .into_iter()
+            .map(|found| {
+                matched.insert(found.name.clone(), found.positions);
+                Pair {
+                    display: found.name.clone(),
+                    replacement: found.name,
+                }
+            })
+            .collect()
+    }
+
+    /// Completes an argument to `command` with its spec. Returns `None`
+    /// when the spec generates nothing and asks for the default completion.
+    fn complete_with_spec(
+        &self,
+        spec: &CompletionSpec,
+        line: &str,
+        pos: usize,
+        word: &Word,
+        command: &Word,
+    ) -> Option<Vec<Pair>> {
+        let prev = lexer::lex(&line[..word.range.start])
+            .pop()
+            .map(|prev| prev.text)
+            .unwrap_or_default();
+        let (words, cword) = lexer::command_words(line, pos);
+        let comp = CompLine {
+            line,
+            point: pos,
+            command: &command.text,
+            word: &word.text,
+            prev: &prev,
+            words,
+            cword,
+        };
+        let mut generated = spec.generate(&comp, &mut &*self);
+        if generated.is_empty() {
+            if spec.dirnames {
+                return Some(self.complete_path(word, true));
+            }
+            if spec.default {
+                return None;
             }
-            None => Ok((
-                pos,
-                vec![Pair {
-                    display: String::new(),
-                    replacement: String::from("\x07"),
-                }],
-            )),
         }
+        generated.sort();
+        generated.dedup();
+
+        let filenames = spec.completes_filenames();
+        let mut candidates: Vec<Pair> = generated
+            .into_iter()
+            .map(|candidate| {
+                if !filenames {
+                    return Pair {
+                        display: candidate.clone(),
+                        replacement: candidate,
+                    };
+                }
+                let mut replacement = paths::quote(&candidate, word.open_quote);
+                if self.working_dir.join(&candidate).is_dir() {
+                    replacement.push('/');
+                }
+                Pair {
+                    display: candidate,
+                    replacement,
+                }
+            })
+            .collect();
+        if !spec.nospace {
+            finish_unique(&mut candidates, word.open_quote);
+        }
+        Some(candidates)
+    }
+
+    fn complete_path(&self, word: &Word, dirs_only: bool) -> Vec<Pair> {
+        let home = self.home_dir();
+        paths::complete(
+            &word.text,
+            word.open_quote,
+            dirs_only,
+            &self.working_dir,
+            home.as_deref(),
+        )
+    }
+}
+
+impl Source for &CommandCompleter {
+    fn cwd(&self) -> &Path {
+        &self.working_dir
+    }
+
+    fn home(&self) -> Option<PathBuf> {
+        self.home_dir()
+    }
+
+    fn commands(&self, prefix: &str) -> Vec<String> {
+        let mut names = self.index().starts_with(prefix).unwrap_or_default();
+        names.retain(|name| !name.contains('>'));
+        names
+    }
+
+    fn variables(&self) -> Vec<String> {
+        match &self.variables {
+            Some(names) => names.clone(),
+            None => env::vars_os()
+                .filter_map(|(name, _)| name.into_string().ok())
+                .collect(),
+        }
+    }
+
+    fn call_function(&mut self, function: &str, comp: &CompLine) -> Vec<String> {
+        match &self.function_runner {
+            Some(runner) => runner(function, comp),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Where the `$name` being typed at the end of `word` starts, if the
+/// cursor is in one.
+fn variable_start(line: &str, word: &Word) -> Option<usize> {
+    if let Some(variable) = word.variables.last()
+        && variable.end == word.range.end
+    {
+        return Some(variable.start);
+    }
+    let raw = &line[word.range.clone()];
+    let bare_dollar = raw.ends_with('$') && !raw.ends_with("\\$") && word.open_quote != Some('\'');
+    bare_dollar.then(|| word.range.end - 1)
+}
+
+impl CommandCompleter {
+    /// Completes `reference`, a `$name` or unclosed `${name`, with the names
+    /// of variables. A unique match inside braces closes them.
+    fn complete_variable(&self, reference: &str) -> Vec<Pair> {
+        let (open, name) = match reference.strip_prefix("${") {
+            Some(name) => ("${", name),
+            None => ("$", &reference[1..]),
+        };
+        if name.contains('}') {
+            return Vec::new();
+        }
+
+        let mut names = self.variables();
+        names.retain(|candidate| candidate.starts_with(name));
+        names.sort();
+        names.dedup();
+        let close = if open == "${" && names.len() == 1 {
+            "}"
+        } else {
+            ""
+        };
+        names
+            .into_iter()
+            .map(|name| Pair {
+                replacement: format!("{open}{name}{close}"),
+                display: name,
+            })
+            .collect()
+    }
+}
+
+impl CommandCompleter {
+    /// Completes a user name after a leading `~`, or a host name after the
+    /// last `@`, in an unquoted `word`. Returns where the replacement
+    /// starts, or `None` when nothing matches so the word is completed as a
+    /// path.
+    fn complete_account(&self, line: &str, word: &Word) -> Option<(usize, Vec<Pair>)> {
+        let raw = &line[word.range.clone()];
+        let plain = |text: &str| {
+            text.chars()
+                .all(|c| c.is_alphanumeric() || "-_.".contains(c))
+        };
+
+        if let Some(user) = raw.strip_prefix('~')
+            && plain(user)
+        {
+            let candidates: Vec<Pair> = self
+                .users()
+                .iter()
+                .filter(|name| name.starts_with(user))
+                .map(|name| Pair {
+                    replacement: format!("~{name}/"),
+                    display: name.clone(),
+                })
+                .collect();
+            return (!candidates.is_empty()).then_some((word.range.start, candidates));
+        }
+
+        let at = raw.rfind('@')?;
+        let host = &raw[at + 1..];
+        if word.kind != WordKind::Argument || !plain(host) {
+            return None;
+        }
+        let mut candidates: Vec<Pair> = names::hosts()
+            .into_iter()
+            .filter(|name| name.starts_with(host))
+            .map(|name| Pair {
+                replacement: name.clone(),
+                display: name,
+            })
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        finish_unique(&mut candidates, None);
+        Some((word.range.start + at + 1, candidates))
     }
 }
 
-#[derive(Debug)]
+/// A set of names stored character by character, for finding every name
+/// that starts with a prefix. Children are kept sorted, so names come out
+/// in lexical order.
+#[derive(Debug, Default)]
 struct PrefixTree {
-    root_children: Vec<PrefixNode>,
+    root: PrefixNode,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Default)]
 struct PrefixNode {
-    data: u8,
     is_end: bool,
-    children: Vec<PrefixNode>,
+    children: BTreeMap<char, PrefixNode>,
 }
 
 impl PrefixTree {
     fn new() -> PrefixTree {
-        PrefixTree {
-            root_children: Vec::new(),
-        }
+        PrefixTree::default()
     }
 
-    fn add(&mut self, items: &str) -> Result<(), &'static str> {
-        let mut children = &mut self.root_children;
-        let mut item_iter = items.as_bytes().iter().peekable();
-        while let Some(item) = item_iter.next() {
-            let item_is_last = item_iter.peek().is_none();
-
-            let child_idx = children.iter().position(|c| c.data == *item);
-            if let Some(idx) = child_idx {
-                let child = &mut children[idx];
-                if item_is_last {
-                    if !child.is_end {
-                        child.is_end = true;
-                    }
-                    return Ok(());
-                }
-                children = &mut child.children;
-            } else {
-                let index = children.len();
-                let new_node = PrefixNode {
-                    data: *item,
-                    is_end: item_is_last,
-                    children: Vec::new(),
-                };
-                children.push(new_node);
-                children = &mut children[index].children;
-            }
+    fn add(&mut self, item: &str) -> Result<(), &'static str> {
+        if item.is_empty() {
+            return Err("cannot add an empty name to the prefix tree");
         }
-
+        let mut node = &mut self.root;
+        for c in item.chars() {
+            node = node.children.entry(c).or_default();
+        }
+        node.is_end = true;
         Ok(())
     }
 
-    fn starts_with(&self, input: &str) -> Option<Vec<String>> {
-        let mut results: Vec<String> = Vec::new();
-        let mut children = &self.root_children;
-        let input_bytes = input.as_bytes();
-        let mut running_prefix: Vec<u8> = Vec::with_capacity(input_bytes.len());
-
-        for byte in input_bytes {
-            let matching_child = children.iter().find(|child| child.data == *byte);
-            if let Some(matching_node) = matching_child {
-                running_prefix.push(*byte);
-                children = &matching_node.children;
-                if input_bytes.len() == running_prefix.len() && matching_node.is_end {
-                    results.push(String::from_utf8_lossy(&running_prefix).to_string());
-                }
-            } else {
-                return None;
+    /// Removes `item`, dropping the branches only it used. Returns whether
+    /// it was there.
+    fn remove(&mut self, item: &str) -> bool {
+        fn remove_from(node: &mut PrefixNode, mut chars: std::str::Chars) -> bool {
+            let Some(c) = chars.next() else {
+                return std::mem::take(&mut node.is_end);
+            };
+            let Some(child) = node.children.get_mut(&c) else {
+                return false;
+            };
+            let removed = remove_from(child, chars);
+            if removed && !child.is_end && child.children.is_empty() {
+                node.children.remove(&c);
             }
+            removed
         }
+        remove_from(&mut self.root, item.chars())
+    }
 
-        let mut search_stack: Vec<(PrefixNode, Vec<u8>)> = Vec::with_capacity(10);
-        for child in children {
-            let path_to_child = Vec::from(running_prefix.clone());
-            search_stack.push((child.clone(), path_to_child));
+    fn contains(&self, item: &str) -> bool {
+        self.find(item).is_some_and(|node| node.is_end)
+    }
+
+    fn find(&self, prefix: &str) -> Option<&PrefixNode> {
+        prefix
+            .chars()
+            .try_fold(&self.root, |node, c| node.children.get(&c))
+    }
+
+    /// Every name starting with `input`, in lexical order, or `None` if
+    /// there are none.
+    fn starts_with(&self, input: &str) -> Option<Vec<String>> {
+        let node = self.find(input)?;
+        let mut results = Vec::new();
+        node.collect(&mut input.to_string(), &mut results);
+        if results.is_empty() {
+            return None;
         }
+        Some(results)
+    }
 
-        while !search_stack.is_empty() {
-            if let Some((node, mut path_to_node)) = search_stack.pop() {
-                path_to_node.push(node.data);
-                if node.is_end {
-                    results.push(String::from_utf8_lossy(&path_to_node).to_string());
-                }
-                for child in node.children {
-                    search_stack.push((child, path_to_node.clone()));
+    /// Every name starting with `input` when case is ignored, in lexical
+    /// order.
+    fn starts_with_ignore_case(&self, input: &str) -> Vec<String> {
+        fn walk(node: &PrefixNode, input: &[char], path: &mut String, results: &mut Vec<String>) {
+            let Some((first, rest)) = input.split_first() else {
+                node.collect(path, results);
+                return;
+            };
+            for (c, child) in &node.children {
+                if fuzzy::fold(*c) == *first {
+                    path.push(*c);
+                    walk(child, rest, path, results);
+                    path.pop();
                 }
             }
         }
 
-        if results.len() == 0 {
-            return None;
-        }
+        let input: Vec<char> = input.chars().map(fuzzy::fold).collect();
+        let mut results = Vec::new();
+        walk(&self.root, &input, &mut String::new(), &mut results);
+        results
+    }
+}
 
-        Some(results)
+impl PrefixNode {
+    /// Pushes every name at or below this node, which `path` leads to.
+    fn collect(&self, path: &mut String, results: &mut Vec<String>) {
+        if self.is_end {
+            results.push(path.clone());
+        }
+        for (c, child) in &self.children {
+            path.push(*c);
+            child.collect(path, results);
+            path.pop();
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::command::{BUILTIN_COMMAND_NAMES, completer::PrefixTree};
+    use rustyline::Context;
+    use rustyline::completion::Completer;
+    use rustyline::history::{DefaultHistory, History};
+
+    use crate::command::{
+        BUILTIN_COMMAND_NAMES, completer::CommandCompleter, completer::PrefixTree,
+    };
+    use crate::shell::history::SharedHistory;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn suggestions_come_from_history_then_commands() {
+        let mut completer = CommandCompleter::new(&["echo", "exit", "export"]);
+        assert_eq!(Some("it".to_string()), completer.suggest("ex"));
+        assert_eq!(None, completer.suggest("echo "));
+        assert_eq!(None, completer.suggest("cat e"));
+
+        let mut history = SharedHistory::default();
+        history.add("export A=1").unwrap();
+        completer.set_history(history);
+        assert_eq!(Some("port A=1".to_string()), completer.suggest("ex"));
+        assert_eq!(Some(" A=1".to_string()), completer.suggest("export"));
+    }
+
+    #[test]
+    fn completion_uses_the_word_under_the_cursor() {
+        let mut completer = CommandCompleter::new(&["echo", "exit"]);
+        completer.set_variables(vec!["PATH".to_string(), "PWD".to_string()]);
+        completer.set_users(vec!["alice".to_string(), "bob".to_string()]);
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let complete = |line: &str, pos| {
+            let (start, pairs) = completer.complete(line, pos, &ctx).unwrap();
+            let replacements: Vec<String> =
+                pairs.into_iter().map(|pair| pair.replacement).collect();
+            (start, replacements)
+        };
+
+        assert_eq!((2, vec!["echo ".to_string()]), complete("  ec", 4));
+        assert_eq!(
+            (0, vec!["echo".to_string(), "exit".to_string()]),
+            complete("e", 1)
+        );
+        assert_eq!((0, vec![]), complete("nope", 4));
+        assert_eq!((0, vec!["exit ".to_string()]), complete("exi 1", 3));
+        assert_eq!((4, vec!["echo ".to_string()]), complete("ls; ec", 6));
+        assert_eq!((3, vec!["echo ".to_string()]), complete("ls|ec", 5));
+        assert_eq!((3, vec!["exit ".to_string()]), complete("a&&exi", 6));
+        assert_eq!((5, vec!["$PATH ".to_string()]), complete("echo $PAT", 9));
+        assert_eq!(
+            (5, vec!["${PATH} ".to_string()]),
+            complete("echo ${PAT", 10)
+        );
+        assert_eq!((3, vec!["~alice/".to_string()]), complete("ls ~al", 6));
+    }
+
+    #[test]
+    fn unmatched_accounts_fall_back_to_paths() {
+        let dir = TempDir::new("account");
+        std::fs::write(dir.join("foo@nohost.txt"), "").unwrap();
+        std::fs::write(dir.join("~nouser.txt"), "").unwrap();
+
+        let mut completer = CommandCompleter::new(&[]);
+        completer.set_working_dir(dir.path().to_path_buf());
+        completer.set_users(vec!["nobody".to_string()]);
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let complete = |line: &str| {
+            let (_, pairs) = completer.complete(line, line.len(), &ctx).unwrap();
+            pairs
+                .into_iter()
+                .map(|pair| pair.replacement)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["foo@nohost.txt "], complete("cat foo@noh"));
+        assert_eq!(vec!["~nouser.txt "], complete("cat ~nouse"));
+    }
+
+    #[test]
+    fn variables_come_from_the_shell() {
+        let mut completer = CommandCompleter::new(&[]);
+        completer.set_variables(vec!["HOME".to_string(), "HOSTNAME".to_string()]);
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let complete = |line: &str| {
+            let (_, pairs) = completer.complete(line, line.len(), &ctx).unwrap();
+            pairs
+                .into_iter()
+                .map(|pair| pair.replacement)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["$HOME", "$HOSTNAME"], complete("echo $HO"));
+        assert_eq!(vec!["${HOME} "], complete("echo ${HOM"));
+        assert!(complete("echo $PATH").is_empty());
+    }
 
     #[test]
     fn prefix_tree_constructed_correctly() {
@@ -179,6 +850,65 @@ mod test {
         dbg!(&pt);
     }
 
+    #[test]
+    fn prefix_tree_lists_names_in_order() {
+        let mut pt = PrefixTree::new();
+        for name in ["git", "gcc", "git-lfs", "grep", "gitk"] {
+            pt.add(name).unwrap();
+        }
+        assert!(pt.add("").is_err());
+        let expected = ["gcc", "git", "git-lfs", "gitk", "grep"].map(String::from);
+        assert_eq!(Some(expected.to_vec()), pt.starts_with("g"));
+        assert_eq!(None, pt.starts_with("h"));
+    }
+
+    #[test]
+    fn prefix_tree_removes_names() {
+        let mut pt = PrefixTree::new();
+        for name in ["git", "git-lfs"] {
+            pt.add(name).unwrap();
+        }
+        assert!(pt.remove("git"));
+        assert!(!pt.remove("git"));
+        assert!(!pt.remove("gi"));
+        assert!(!pt.contains("git"));
+        assert!(pt.contains("git-lfs"));
+
+        assert!(pt.remove("git-lfs"));
+        assert!(pt.root.children.is_empty());
+    }
+
+    #[test]
+    fn fuzzy_matching_ignores_case_then_ranks() {
+        let mut completer = CommandCompleter::new(&["Git", "git-checkout", "kubectl"]);
+        assert_eq!(1, completer.match_commands("gi").len());
+
+        completer.set_fuzzy(true);
+        let names = |matches: Vec<super::Match>| -> Vec<String> {
+            matches.into_iter().map(|found| found.name).collect()
+        };
+        assert_eq!(
+            vec!["Git", "git-checkout"],
+            names(completer.match_commands("gi"))
+        );
+        assert_eq!(vec!["git-checkout"], names(completer.match_commands("gco")));
+        let found = completer.match_commands("kctl");
+        assert_eq!(vec![0, 4, 5, 6], found[0].positions);
+    }
+
+    #[test]
+    fn prefix_tree_handles_unicode() {
+        let mut pt = PrefixTree::new();
+        for name in ["café", "cafétéria", "caffè"] {
+            pt.add(name).unwrap();
+        }
+        assert_eq!(
+            Some(vec!["café".to_string(), "cafétéria".to_string()]),
+            pt.starts_with("caf\u{e9}")
+        );
+        assert_eq!(Some(vec!["caffè".to_string()]), pt.starts_with("caff"));
+    }
+
     #[test]
     fn prefix_tree_starts_with_works_correctly() {
         let mut pt = PrefixTree::new();
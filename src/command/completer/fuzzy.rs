@@ -0,0 +1,153 @@
+//! Ranking names that contain the typed characters in order, for
+//! `shopt -s fuzzycomplete`.
+
+/// A candidate name and the characters of it the typed word matched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub name: String,
+    /// Indices, counted in chars, of the matched characters.
+    pub positions: Vec<usize>,
+    /// Higher for a better match.
+    pub score: i32,
+}
+
+impl Match {
+    /// `name`, matched by its first `len` characters.
+    pub fn prefix(name: String, len: usize) -> Match {
+        Match {
+            name,
+            positions: (0..len).collect(),
+            score: 0,
+        }
+    }
+}
+
+/// `c` with case ignored.
+pub fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// The names containing the characters of `query` in order, ignoring case,
+/// best first. Matches at the start of words and runs of consecutive
+/// characters score higher; ties go to the shorter name.
+pub fn rank<'a>(query: &str, names: impl IntoIterator<Item = &'a str>) -> Vec<Match> {
+    let mut matches: Vec<Match> = names
+        .into_iter()
+        .filter_map(|name| subsequence(query, name))
+        .collect();
+    matches.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(a.name.len().cmp(&b.name.len()))
+            .then(a.name.cmp(&b.name))
+    });
+    matches
+}
+
+/// The best way to match `query` against `name` as a subsequence, if there
+/// is one.
+pub fn subsequence(query: &str, name: &str) -> Option<Match> {
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let chars: Vec<char> = name.chars().collect();
+    if query.is_empty() || query.len() > chars.len() {
+        return None;
+    }
+
+    // best[i][j] is the best score for matching query[..=i] with query[i]
+    // at chars[j], and where query[i - 1] was matched
+    let mut best: Vec<Vec<Option<(i32, usize)>>> = vec![vec![None; chars.len()]; query.len()];
+    for (i, q) in query.iter().enumerate() {
+        // the best match for query[i - 1] far enough back that its gap
+        // penalty is capped, so each row stays linear in the name's length
+        let mut far: Option<(i32, usize)> = None;
+        for j in i..chars.len() {
+            if i > 0
+                && let Some(k) = j.checked_sub(MAX_GAP + 1)
+                && let Some((score, _)) = best[i - 1][k]
+                && far.is_none_or(|(best_far, _)| score > best_far)
+            {
+                far = Some((score, k));
+            }
+            if fold(chars[j]) != *q {
+                continue;
+            }
+            let bonus = position_bonus(&chars, j);
+            best[i][j] = if i == 0 {
+                Some((bonus - gap_penalty(j), 0))
+            } else {
+                let near = (j.saturating_sub(MAX_GAP).max(i - 1)..j)
+                    .filter_map(|k| best[i - 1][k].map(|(score, _)| (score, k)));
+                near.chain(far)
+                    .map(|(score, k)| {
+                        let step = if k + 1 == j {
+                            4
+                        } else {
+                            -gap_penalty(j - k - 1)
+                        };
+                        (score + bonus + step, k)
+                    })
+                    .max_by_key(|(score, _)| *score)
+            };
+        }
+    }
+
+    let last = query.len() - 1;
+    let (mut j, score) = (0..chars.len())
+        .filter_map(|j| best[last][j].map(|(score, _)| (j, score)))
+        .max_by_key(|(_, score)| *score)?;
+    let mut positions = vec![0; query.len()];
+    for i in (0..query.len()).rev() {
+        positions[i] = j;
+        j = best[i][j].map_or(0, |(_, prev)| prev);
+    }
+    Some(Match {
+        name: name.to_string(),
+        positions,
+        score,
+    })
+}
+
+/// Characters that start a word, after a separator or at a change to upper
+/// case, are worth more.
+fn position_bonus(chars: &[char], idx: usize) -> i32 {
+    let starts_word = match idx.checked_sub(1).map(|prev| chars[prev]) {
+        None => true,
+        Some(prev) => "-_./ ".contains(prev) || (prev.is_lowercase() && chars[idx].is_uppercase()),
+    };
+    if starts_word { 4 } else { 1 }
+}
+
+/// Gaps longer than this cost no more.
+const MAX_GAP: usize = 3;
+
+fn gap_penalty(gap: usize) -> i32 {
+    gap.min(MAX_GAP) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ranked(query: &str, names: &[&str]) -> Vec<String> {
+        rank(query, names.iter().copied())
+            .into_iter()
+            .map(|found| found.name)
+            .collect()
+    }
+
+    #[test]
+    fn subsequences_are_ranked() {
+        let names = ["git-checkout", "gitk", "go", "kubectl", "kill"];
+        assert_eq!(vec!["git-checkout"], ranked("gco", &names));
+        assert_eq!(vec!["kubectl"], ranked("kctl", &names));
+        assert_eq!(vec!["kubectl"], ranked("KCTL", &names));
+        assert!(ranked("xyz", &names).is_empty());
+        assert_eq!(
+            vec!["ls", "lsof", "false"],
+            ranked("ls", &["false", "lsof", "ls"])
+        );
+
+        let found = subsequence("gco", "git-checkout").unwrap();
+        assert_eq!(vec![0, 4, 9], found.positions);
+    }
+}
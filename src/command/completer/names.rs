@@ -0,0 +1,67 @@
+//! User names for `~user` and host names for `user@host`.
+
+use std::{ffi::CStr, fs};
+
+const HOSTS_FILE: &str = "/etc/hosts";
+
+/// Every user name in the password database, sorted.
+pub fn users() -> Vec<String> {
+    let mut names = Vec::new();
+    // SAFETY: the entries are only read between setpwent and endpwent, and
+    // each name is copied out before the next call reuses its buffer.
+    unsafe {
+        libc::setpwent();
+        loop {
+            let entry = libc::getpwent();
+            if entry.is_null() {
+                break;
+            }
+            if let Ok(name) = CStr::from_ptr((*entry).pw_name).to_str() {
+                names.push(name.to_string());
+            }
+        }
+        libc::endpwent();
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Every host name in `/etc/hosts`, sorted.
+pub fn hosts() -> Vec<String> {
+    fs::read_to_string(HOSTS_FILE)
+        .map(|contents| parse_hosts(&contents))
+        .unwrap_or_default()
+}
+
+/// The names after the address on each line of a hosts file.
+fn parse_hosts(contents: &str) -> Vec<String> {
+    let mut names: Vec<String> = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split_whitespace().skip(1))
+        .map(str::to_string)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hosts_file_names_are_listed() {
+        let contents = "\
+# static table
+127.0.0.1\tlocalhost
+::1 localhost ip6-localhost # loopback
+10.0.0.2  build-box build
+";
+        assert_eq!(
+            vec!["build", "build-box", "ip6-localhost", "localhost"],
+            parse_hosts(contents)
+        );
+    }
+}
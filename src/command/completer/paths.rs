@@ -0,0 +1,152 @@
+//! Completion of file and directory names.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rustyline::completion::Pair;
+
+/// Characters that need a backslash to stay part of an unquoted word.
+const SPECIAL_CHARS: &str = " \t\n'\"\\$&;|<>()*?[]{}#!`";
+
+/// Completes the path `text`, the unquoted word being typed. Names are
+/// resolved against `cwd`, with a leading `~` standing for `home`. The
+/// replacements are written back in the quoting the word was typed with:
+/// inside `open_quote` if there is one, otherwise with backslash escapes.
+/// Directories end in `/`; nothing closes the quote or the word.
+pub fn complete(
+    text: &str,
+    open_quote: Option<char>,
+    dirs_only: bool,
+    cwd: &Path,
+    home: Option<&Path>,
+) -> Vec<Pair> {
+    let (dir_part, entries) = entries(text, dirs_only, cwd, home);
+    entries
+        .into_iter()
+        .map(|(name, is_dir)| {
+            let mut replacement = quote(&format!("{dir_part}{name}"), open_quote);
+            if is_dir {
+                replacement.push('/');
+            }
+            let display = if is_dir { format!("{name}/") } else { name };
+            Pair {
+                display,
+                replacement,
+            }
+        })
+        .collect()
+}
+
+/// The paths matching `text`, completed but not quoted, as `compgen -f`
+/// lists them.
+pub fn matching(text: &str, dirs_only: bool, cwd: &Path, home: Option<&Path>) -> Vec<String> {
+    let (dir_part, entries) = entries(text, dirs_only, cwd, home);
+    entries
+        .into_iter()
+        .map(|(name, _)| format!("{dir_part}{name}"))
+        .collect()
+}
+
+/// Writes `path` back inside `open_quote` if the word was typed with one,
+/// otherwise with backslash escapes.
+pub fn quote(path: &str, open_quote: Option<char>) -> String {
+    match open_quote {
+        Some(quote) => format!("{quote}{path}"),
+        None => escape(path),
+    }
+}
+
+/// Splits `text` into the directory part as typed and the sorted names in
+/// that directory that complete the rest, each with whether it is a
+/// directory.
+fn entries<'t>(
+    text: &'t str,
+    dirs_only: bool,
+    cwd: &Path,
+    home: Option<&Path>,
+) -> (&'t str, Vec<(String, bool)>) {
+    let (dir_part, prefix) = match text.rfind('/') {
+        Some(idx) => text.split_at(idx + 1),
+        None if text == "~" => ("~/", ""),
+        None => ("", text),
+    };
+    let Ok(entries) = fs::read_dir(resolve(dir_part, cwd, home)) else {
+        return (dir_part, Vec::new());
+    };
+
+    let mut entries: Vec<(String, bool)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // follow symlinks so a link to a directory counts as one
+            let is_dir = fs::metadata(entry.path()).is_ok_and(|meta| meta.is_dir());
+            (is_dir || !dirs_only).then_some((name, is_dir))
+        })
+        .collect();
+    entries.sort();
+    (dir_part, entries)
+}
+
+/// The directory `dir_part` names, which is either empty or ends in `/`.
+fn resolve(dir_part: &str, cwd: &Path, home: Option<&Path>) -> PathBuf {
+    if let Some(rest) = dir_part.strip_prefix("~/")
+        && let Some(home) = home
+    {
+        return home.join(rest);
+    }
+    cwd.join(dir_part)
+}
+
+/// Backslash-escapes the characters in `path` the shell would otherwise
+/// treat specially. `~` is left alone so a leading one still expands.
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if SPECIAL_CHARS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn replacements(pairs: Vec<Pair>) -> Vec<String> {
+        pairs.into_iter().map(|pair| pair.replacement).collect()
+    }
+
+    #[test]
+    fn paths_are_completed_and_escaped() {
+        let dir = TempDir::new("paths-test");
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/my dir")).unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/.hidden"), "").unwrap();
+
+        let complete_in = |text: &str, quote, dirs_only| {
+            replacements(complete(text, quote, dirs_only, root, Some(root)))
+        };
+        assert_eq!(vec!["src/"], complete_in("s", None, false));
+        assert_eq!(
+            vec![r"src/main.rs", r"src/my\ dir/"],
+            complete_in("src/m", None, false)
+        );
+        assert_eq!(vec![r"src/my\ dir/"], complete_in("src/", None, true));
+        assert_eq!(
+            vec!["'src/main.rs", "'src/my dir/"],
+            complete_in("src/m", Some('\''), false)
+        );
+        assert_eq!(vec!["src/.hidden"], complete_in("src/.", None, false));
+        assert_eq!(vec!["~/src/"], complete_in("~", None, false));
+        assert!(complete_in("nothing/", None, false).is_empty());
+    }
+}
@@ -0,0 +1,499 @@
+//! Completion specifications registered with `complete`, and the candidates
+//! they generate for the line editor and for `compgen`.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+    process::{Command as StdProcCmd, Stdio},
+    rc::Rc,
+};
+
+use crate::command::completer::paths;
+use crate::command::{BUILTIN_COMMAND_NAMES, lexer};
+
+/// A kind of name a spec completes, given with `-A` or its short flag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Builtin,
+    Command,
+    Directory,
+    Export,
+    File,
+    Variable,
+}
+
+/// `-A` action names and their short flags.
+const ACTIONS: &[(Action, &str, char)] = &[
+    (Action::Builtin, "builtin", 'b'),
+    (Action::Command, "command", 'c'),
+    (Action::Directory, "directory", 'd'),
+    (Action::Export, "export", 'e'),
+    (Action::File, "file", 'f'),
+    (Action::Variable, "variable", 'v'),
+];
+
+impl Action {
+    fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(_, action_name, _)| *action_name == name)
+            .map(|(action, _, _)| *action)
+    }
+
+    fn from_flag(flag: char) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(_, _, action_flag)| *action_flag == flag)
+            .map(|(action, _, _)| *action)
+    }
+
+    fn flag(self) -> char {
+        ACTIONS
+            .iter()
+            .find(|(action, _, _)| *action == self)
+            .map(|(_, _, flag)| *flag)
+            .unwrap()
+    }
+}
+
+/// How to complete the arguments of a command.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionSpec {
+    pub actions: Vec<Action>,
+    /// `-W`: a whitespace-separated list of words.
+    pub words: Option<String>,
+    /// `-C`: a command whose output lines are the candidates.
+    pub command: Option<String>,
+    /// `-F`: a shell function that leaves the candidates in `COMPREPLY`.
+    pub function: Option<String>,
+    /// `-P` and `-S`: added to every candidate.
+    pub prefix: String,
+    pub suffix: String,
+    /// `-o nospace`: don't end the word after a unique candidate.
+    pub nospace: bool,
+    /// `-o filenames`: treat candidates as paths, quoting them and marking
+    /// directories.
+    pub filenames: bool,
+    /// `-o default` and `-o dirnames`: what to complete instead when the
+    /// spec generates nothing.
+    pub default: bool,
+    pub dirnames: bool,
+}
+
+/// Arguments to `complete` or `compgen`, split into the spec they describe
+/// and the rest.
+#[derive(Debug, Default, PartialEq)]
+pub struct SpecArgs {
+    pub spec: CompletionSpec,
+    /// The command names for `complete`, or the word for `compgen`.
+    pub names: Vec<String>,
+    /// `-p`
+    pub print: bool,
+    /// `-r`
+    pub remove: bool,
+    /// `compgen -V`: the array to store the candidates in instead of
+    /// printing them.
+    pub array: Option<String>,
+}
+
+/// The line being completed.
+#[derive(Debug, Default)]
+pub struct CompLine<'a> {
+    pub line: &'a str,
+    /// The cursor position in `line`.
+    pub point: usize,
+    /// The command whose argument is being completed.
+    pub command: &'a str,
+    pub word: &'a str,
+    /// The word before the one being completed.
+    pub prev: &'a str,
+    /// The words of the command, as typed, for `COMP_WORDS`.
+    pub words: Vec<String>,
+    /// The index in `words` of the word being completed, for `COMP_CWORD`.
+    pub cword: usize,
+}
+
+/// Runs a shell function for a `-F` spec on behalf of the line editor,
+/// returning what it left in `COMPREPLY`.
+pub type FunctionRunner = Box<dyn Fn(&str, &CompLine) -> Vec<String>>;
+
+/// What a spec draws its names from.
+pub trait Source {
+    /// The directory relative paths are completed in.
+    fn cwd(&self) -> &Path;
+
+    /// The directory a leading `~` stands for.
+    fn home(&self) -> Option<PathBuf>;
+
+    /// The builtins and executables whose name starts with `prefix`.
+    fn commands(&self, prefix: &str) -> Vec<String>;
+
+    /// The names of shell and environment variables.
+    fn variables(&self) -> Vec<String>;
+
+    /// Runs the shell function `function` to complete `comp`, returning
+    /// what it left in `COMPREPLY`.
+    fn call_function(&mut self, function: &str, comp: &CompLine) -> Vec<String>;
+}
+
+impl SpecArgs {
+    /// Parses the options of `builtin`, which is `complete` or `compgen`.
+    pub fn parse(builtin: &str, args: &[String]) -> Result<Self, String> {
+        let mut parsed = SpecArgs::default();
+        let spec = &mut parsed.spec;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.names.extend(args.cloned());
+                break;
+            }
+            if !arg.starts_with('-') || arg.len() == 1 {
+                parsed.names.push(arg.clone());
+                continue;
+            }
+
+            for flag in arg.chars().skip(1) {
+                match flag {
+                    'p' => parsed.print = true,
+                    'r' => parsed.remove = true,
+                    'V' if builtin != "compgen" => {
+                        return Err(format!("{builtin}: -V: invalid option"));
+                    }
+                    'A' | 'W' | 'C' | 'F' | 'V' | 'o' | 'P' | 'S' => {
+                        let Some(value) = args.next() else {
+                            return Err(format!("{builtin}: -{flag}: option requires an argument"));
+                        };
+                        match flag {
+                            'A' => match Action::from_name(value) {
+                                Some(action) => spec.actions.push(action),
+                                None => {
+                                    return Err(format!("{builtin}: {value}: invalid action name"));
+                                }
+                            },
+                            'W' => spec.words = Some(value.clone()),
+                            'C' => spec.command = Some(value.clone()),
+                            'F' => spec.function = Some(value.clone()),
+                            'V' => parsed.array = Some(value.clone()),
+                            'o' => match value.as_str() {
+                                "nospace" => spec.nospace = true,
+                                "filenames" => spec.filenames = true,
+                                "default" | "bashdefault" => spec.default = true,
+                                "dirnames" => spec.dirnames = true,
+                                _ => {
+                                    return Err(format!("{builtin}: {value}: invalid option name"));
+                                }
+                            },
+                            'P' => spec.prefix = value.clone(),
+                            _ => spec.suffix = value.clone(),
+                        }
+                    }
+                    other => match Action::from_flag(other) {
+                        Some(action) => spec.actions.push(action),
+                        None => return Err(format!("{builtin}: -{other}: invalid option")),
+                    },
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl CompletionSpec {
+    /// The candidates for the word being completed in `comp`.
+    pub fn generate(&self, comp: &CompLine, source: &mut dyn Source) -> Vec<String> {
+        let word = comp.word;
+        let mut candidates = Vec::new();
+        for action in &self.actions {
+            match action {
+                Action::Builtin => candidates.extend(
+                    BUILTIN_COMMAND_NAMES
+                        .iter()
+                        .filter(|name| !name.contains('>'))
+                        .filter(|name| name.starts_with(word))
+                        .map(|name| name.to_string()),
+                ),
+                Action::Command => candidates.extend(source.commands(word)),
+                Action::Directory | Action::File => {
+                    let dirs_only = *action == Action::Directory;
+                    let home = source.home();
+                    candidates.extend(paths::matching(
+                        word,
+                        dirs_only,
+                        source.cwd(),
+                        home.as_deref(),
+                    ));
+                }
+                Action::Export => candidates.extend(
+                    env::vars_os()
+                        .filter_map(|(name, _)| name.into_string().ok())
+                        .filter(|name| name.starts_with(word)),
+                ),
+                Action::Variable => candidates.extend(
+                    source
+                        .variables()
+                        .into_iter()
+                        .filter(|name| name.starts_with(word)),
+                ),
+            }
+        }
+        if let Some(words) = &self.words {
+            candidates.extend(
+                words
+                    .split_whitespace()
+                    .filter(|candidate| candidate.starts_with(word))
+                    .map(str::to_string),
+            );
+        }
+        if let Some(function) = &self.function {
+            candidates.extend(source.call_function(function, comp));
+        }
+        if let Some(command) = &self.command {
+            candidates.extend(run_completion_command(command, comp, source.cwd()));
+        }
+
+        candidates
+            .into_iter()
+            .map(|candidate| format!("{}{candidate}{}", self.prefix, self.suffix))
+            .collect()
+    }
+
+    /// Whether candidates are paths, to be quoted and have directories
+    /// marked.
+    pub fn completes_filenames(&self) -> bool {
+        self.filenames
+            || self
+                .actions
+                .iter()
+                .any(|action| matches!(action, Action::File | Action::Directory))
+    }
+
+    /// The `complete` command that would register this spec for `name`.
+    pub fn to_command(&self, name: &str) -> String {
+        let mut parts = vec!["complete".to_string()];
+        for (enabled, option) in [
+            (self.nospace, "nospace"),
+            (self.filenames, "filenames"),
+            (self.default, "default"),
+            (self.dirnames, "dirnames"),
+        ] {
+            if enabled {
+                parts.push(format!("-o {option}"));
+            }
+        }
+        parts.extend(
+            self.actions
+                .iter()
+                .map(|action| format!("-{}", action.flag())),
+        );
+        if let Some(words) = &self.words {
+            parts.push(format!("-W {}", single_quote(words)));
+        }
+        if let Some(command) = &self.command {
+            parts.push(format!("-C {}", single_quote(command)));
+        }
+        if let Some(function) = &self.function {
+            parts.push(format!("-F {function}"));
+        }
+        if !self.prefix.is_empty() {
+            parts.push(format!("-P {}", single_quote(&self.prefix)));
+        }
+        if !self.suffix.is_empty() {
+            parts.push(format!("-S {}", single_quote(&self.suffix)));
+        }
+        parts.push(name.to_string());
+        parts.join(" ")
+    }
+}
+
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Runs a `-C` command the way bash does: with the command name, the word
+/// being completed and the word before it as arguments, and `COMP_LINE`
+/// and `COMP_POINT` in its environment. Each line it prints is a candidate.
+fn run_completion_command(command: &str, comp: &CompLine, cwd: &Path) -> Vec<String> {
+    let words: Vec<String> = lexer::lex(command)
+        .into_iter()
+        .map(|word| word.text)
+        .collect();
+    let Some((program, args)) = words.split_first() else {
+        return Vec::new();
+    };
+
+    let output = StdProcCmd::new(program)
+        .args(args)
+        .args([comp.command, comp.word, comp.prev])
+        .env("COMP_LINE", comp.line)
+        .env("COMP_POINT", comp.point.to_string())
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The specs registered with `complete`, shared between the shell and the
+/// line editor.
+#[derive(Debug, Clone, Default)]
+pub struct SharedSpecs(Rc<RefCell<BTreeMap<String, CompletionSpec>>>);
+
+impl SharedSpecs {
+    /// The spec for `command`, looked up by its full name and then by the
+    /// last component of a path.
+    pub fn get(&self, command: &str) -> Option<CompletionSpec> {
+        let specs = self.0.borrow();
+        specs
+            .get(command)
+            .or_else(|| {
+                let (_, name) = command.rsplit_once('/')?;
+                specs.get(name)
+            })
+            .cloned()
+    }
+
+    pub fn insert(&self, command: String, spec: CompletionSpec) {
+        self.0.borrow_mut().insert(command, spec);
+    }
+
+    /// Removes the spec for `command`. Returns whether there was one.
+    pub fn remove(&self, command: &str) -> bool {
+        self.0.borrow_mut().remove(command).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    /// Every command with a spec and its spec, sorted by command.
+    pub fn entries(&self) -> Vec<(String, CompletionSpec)> {
+        self.0
+            .borrow()
+            .iter()
+            .map(|(name, spec)| (name.clone(), spec.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::args;
+
+    struct FakeSource(PathBuf);
+
+    impl Source for FakeSource {
+        fn cwd(&self) -> &Path {
+            &self.0
+        }
+
+        fn home(&self) -> Option<PathBuf> {
+            None
+        }
+
+        fn commands(&self, prefix: &str) -> Vec<String> {
+            ["git", "grep"]
+                .iter()
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| name.to_string())
+                .collect()
+        }
+
+        fn variables(&self) -> Vec<String> {
+            vec!["GIT_DIR".to_string(), "HOME".to_string()]
+        }
+
+        fn call_function(&mut self, function: &str, comp: &CompLine) -> Vec<String> {
+            vec![format!(
+                "{function}:{}:{}",
+                comp.cword,
+                comp.words.join(",")
+            )]
+        }
+    }
+
+    #[test]
+    fn specs_are_parsed_and_printed() {
+        let parsed = SpecArgs::parse(
+            "complete",
+            &args(&["-o", "nospace", "-cv", "-W", "a 'b", "-A", "file", "git"]),
+        )
+        .unwrap();
+        assert_eq!(vec!["git"], parsed.names);
+        assert_eq!(
+            vec![Action::Command, Action::Variable, Action::File],
+            parsed.spec.actions
+        );
+        assert_eq!(
+            r"complete -o nospace -c -v -f -W 'a '\''b' git",
+            parsed.spec.to_command("git")
+        );
+
+        let parsed =
+            SpecArgs::parse("complete", &args(&["-F", "_git", "-o", "default", "git"])).unwrap();
+        assert_eq!(Some("_git".to_string()), parsed.spec.function);
+        assert_eq!(
+            "complete -o default -F _git git",
+            parsed.spec.to_command("git")
+        );
+
+        let parsed = SpecArgs::parse("compgen", &args(&["-W", "a", "-V", "REPLY"])).unwrap();
+        assert_eq!(Some("REPLY".to_string()), parsed.array);
+        assert!(SpecArgs::parse("complete", &args(&["-V", "REPLY"])).is_err());
+        assert!(SpecArgs::parse("compgen", &args(&["-A", "alias"])).is_err());
+        assert!(SpecArgs::parse("compgen", &args(&["-W"])).is_err());
+    }
+
+    #[test]
+    fn candidates_are_generated() {
+        let mut source = FakeSource(PathBuf::from("/"));
+        let spec = SpecArgs::parse("compgen", &args(&["-c", "-v", "-W", "go get", "-S", "!"]))
+            .unwrap()
+            .spec;
+        let comp = CompLine {
+            word: "g",
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["git!", "grep!", "go!", "get!"],
+            spec.generate(&comp, &mut source)
+        );
+
+        let comp = CompLine {
+            word: "G",
+            ..Default::default()
+        };
+        assert_eq!(vec!["GIT_DIR!"], spec.generate(&comp, &mut source));
+
+        let spec = SpecArgs::parse("compgen", &args(&["-F", "_git", "-P", "<"]))
+            .unwrap()
+            .spec;
+        let comp = CompLine {
+            words: args(&["git", "co"]),
+            cword: 1,
+            ..Default::default()
+        };
+        assert_eq!(vec!["<_git:1:git,co"], spec.generate(&comp, &mut source));
+    }
+
+    #[test]
+    fn specs_are_found_by_command_name() {
+        let specs = SharedSpecs::default();
+        specs.insert("git".to_string(), CompletionSpec::default());
+        assert!(specs.get("git").is_some());
+        assert!(specs.get("/usr/bin/git").is_some());
+        assert!(specs.get("gitk").is_none());
+        assert!(specs.remove("git"));
+        assert!(!specs.remove("git"));
+    }
+}
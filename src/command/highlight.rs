@@ -0,0 +1,237 @@
+//! Colours for the line being edited.
+
+use crate::command::Lookup;
+use crate::command::lexer::{self, WordKind};
+
+/// Holds the colour scheme, as `key=SGR` pairs separated by colons in the
+/// style of `GCC_COLORS`, e.g. `command=1;32:comment=90`.
+const SCHEME_VAR: &str = "SHELL_COLORS";
+
+/// SGR parameters for each kind of text. An empty string leaves the text
+/// uncoloured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorScheme {
+    pub command: String,
+    pub unknown: String,
+    pub string: String,
+    pub variable: String,
+    pub redirect: String,
+    pub comment: String,
+    pub bracket: String,
+    /// The suggested rest of the line shown after the cursor.
+    pub hint: String,
+    /// The characters a listed completion matched.
+    pub matched: String,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            command: "32".to_string(),
+            unknown: "31".to_string(),
+            string: "33".to_string(),
+            variable: "36".to_string(),
+            redirect: "35".to_string(),
+            comment: "90".to_string(),
+            bracket: "1;4".to_string(),
+            hint: "90".to_string(),
+            matched: "1".to_string(),
+        }
+    }
+}
+
+impl ColorScheme {
+    /// The default scheme with the entries in `spec` overriding it. Unknown
+    /// keys are ignored.
+    pub fn parse(spec: &str) -> Self {
+        let mut scheme = Self::default();
+        for entry in spec.split(':') {
+            let Some((key, value)) = entry.split_once('=') else {
+                continue;
+            };
+            let slot = match key {
+                "command" => &mut scheme.command,
+                "unknown" => &mut scheme.unknown,
+                "string" => &mut scheme.string,
+                "variable" => &mut scheme.variable,
+                "redirect" => &mut scheme.redirect,
+                "comment" => &mut scheme.comment,
+                "bracket" => &mut scheme.bracket,
+                "hint" => &mut scheme.hint,
+                "match" => &mut scheme.matched,
+                _ => continue,
+            };
+            *slot = value.to_string();
+        }
+        scheme
+    }
+
+    /// The scheme configured through `lookup`, or `None` when colour is
+    /// turned off with `NO_COLOR` or the terminal is dumb.
+    pub fn from_lookup(lookup: &dyn Lookup) -> Option<Self> {
+        if lookup
+            .variable("NO_COLOR")
+            .is_some_and(|value| !value.is_empty())
+        {
+            return None;
+        }
+        if lookup.variable("TERM").as_deref() == Some("dumb") {
+            return None;
+        }
+        Some(Self::parse(
+            &lookup.variable(SCHEME_VAR).unwrap_or_default(),
+        ))
+    }
+}
+
+/// Colours `line`. `is_command` decides whether a command name is green or
+/// red, and `bracket_pos` is the cursor position to match brackets at, if
+/// any.
+pub fn highlight(
+    line: &str,
+    scheme: &ColorScheme,
+    is_command: impl Fn(&str) -> bool,
+    bracket_pos: Option<usize>,
+) -> String {
+    let mut styles: Vec<&str> = vec![""; line.len()];
+    for word in lexer::lex(line) {
+        let style: &str = match word.kind {
+            WordKind::Command if is_command(&word.text) => &scheme.command,
+            WordKind::Command => &scheme.unknown,
+            WordKind::Redirect | WordKind::Background | WordKind::Separator => &scheme.redirect,
+            WordKind::Comment => &scheme.comment,
+            _ => "",
+        };
+        styles[word.range.clone()].fill(style);
+        for quote in word.quotes {
+            styles[quote].fill(&scheme.string);
+        }
+        for variable in word.variables {
+            styles[variable].fill(&scheme.variable);
+        }
+    }
+
+    if let Some(pos) = bracket_pos
+        && let Some((open, close)) = matching_brackets(line, pos)
+    {
+        styles[open] = &scheme.bracket;
+        styles[close] = &scheme.bracket;
+    }
+
+    let mut highlighted = String::with_capacity(line.len() * 2);
+    let mut current = "";
+    for (idx, c) in line.char_indices() {
+        let style = styles[idx];
+        if style != current {
+            if !current.is_empty() {
+                highlighted.push_str("\x1b[0m");
+            }
+            if !style.is_empty() {
+                highlighted.push_str(&format!("\x1b[{style}m"));
+            }
+            current = style;
+        }
+        highlighted.push(c);
+    }
+    if !current.is_empty() {
+        highlighted.push_str("\x1b[0m");
+    }
+    highlighted
+}
+
+/// Wraps `text` in the SGR parameters `style`.
+pub fn paint(text: &str, style: &str) -> String {
+    if style.is_empty() {
+        text.to_string()
+    } else {
+        format!("\x1b[{style}m{text}\x1b[0m")
+    }
+}
+
+/// Finds the bracket under or just before the cursor and its partner.
+/// Returns their byte offsets, opening bracket first.
+fn matching_brackets(line: &str, pos: usize) -> Option<(usize, usize)> {
+    let bytes = line.as_bytes();
+    let at = [Some(pos), pos.checked_sub(1)]
+        .into_iter()
+        .flatten()
+        .find(|idx| bytes.get(*idx).is_some_and(|b| b"()[]{}".contains(b)))?;
+
+    let (open, close, forward) = match bytes[at] {
+        b'(' => (b'(', b')', true),
+        b'[' => (b'[', b']', true),
+        b'{' => (b'{', b'}', true),
+        b')' => (b'(', b')', false),
+        b']' => (b'[', b']', false),
+        _ => (b'{', b'}', false),
+    };
+
+    let mut depth = 0;
+    let partner = if forward {
+        (at..bytes.len()).find(|idx| {
+            depth += bracket_step(bytes[*idx], open, close);
+            depth == 0
+        })
+    } else {
+        (0..=at).rev().find(|idx| {
+            depth -= bracket_step(bytes[*idx], open, close);
+            depth == 0
+        })
+    }?;
+    Some((at.min(partner), at.max(partner)))
+}
+
+fn bracket_step(byte: u8, open: u8, close: u8) -> i32 {
+    if byte == open {
+        1
+    } else if byte == close {
+        -1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plain() -> ColorScheme {
+        ColorScheme::parse("")
+    }
+
+    #[test]
+    fn commands_are_green_or_red() {
+        let known = highlight("echo hi", &plain(), |name| name == "echo", None);
+        assert_eq!("\x1b[32mecho\x1b[0m hi", known);
+
+        let unknown = highlight("ehco hi", &plain(), |name| name == "echo", None);
+        assert_eq!("\x1b[31mehco\x1b[0m hi", unknown);
+    }
+
+    #[test]
+    fn strings_variables_and_comments_are_coloured() {
+        let line = "x 'a' $HOME > f # done";
+        let highlighted = highlight(line, &plain(), |_| true, None);
+        assert_eq!(
+            "\x1b[32mx\x1b[0m \x1b[33m'a'\x1b[0m \x1b[36m$HOME\x1b[0m \x1b[35m>\x1b[0m f \x1b[90m# done\x1b[0m",
+            highlighted
+        );
+    }
+
+    #[test]
+    fn brackets_match_around_the_cursor() {
+        assert_eq!(Some((6, 11)), matching_brackets("echo ${HOME}", 6));
+        assert_eq!(Some((6, 11)), matching_brackets("echo ${HOME}", 12));
+        assert_eq!(Some((0, 4)), matching_brackets("((a))", 0));
+        assert_eq!(None, matching_brackets("echo (", 6));
+        assert_eq!(None, matching_brackets("echo", 2));
+    }
+
+    #[test]
+    fn scheme_entries_override_defaults() {
+        let scheme = ColorScheme::parse("command=1;32:bogus=4:comment=");
+        assert_eq!("1;32", scheme.command);
+        assert_eq!("", scheme.comment);
+        assert_eq!(ColorScheme::default().unknown, scheme.unknown);
+    }
+}
@@ -0,0 +1,395 @@
+//! Splits a line into words with their byte ranges, without expanding
+//! anything. Unlike `MetaSymbolExpander`, which produces the words a command
+//! runs with, this keeps track of where every word, quote and `$name` sits
+//! in the line as typed, for highlighting and completion.
+
+use std::ops::Range;
+
+use crate::command::{CommandPartial, split_assignment};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordKind {
+    /// The word naming the command to run.
+    Command,
+    Argument,
+    /// A `NAME=value` prefix before the command.
+    Assignment,
+    /// A redirection operator such as `>` or `2>>`.
+    Redirect,
+    /// The file a redirection operator applies to.
+    RedirectTarget,
+    /// The `&` that ends a line.
+    Background,
+    /// Everything from an unquoted `#` that starts a word.
+    Comment,
+    /// `;`, `|`, `||`, `&&` or an `&` before more commands, after which a
+    /// new command starts.
+    Separator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub kind: WordKind,
+    /// Byte range of the word in the line, quotes included.
+    pub range: Range<usize>,
+    /// The word with quotes and backslashes removed.
+    pub text: String,
+    /// Byte ranges of the quoted parts, quotes included.
+    pub quotes: Vec<Range<usize>>,
+    /// Byte ranges of `$name` and `${name}` references outside single
+    /// quotes.
+    pub variables: Vec<Range<usize>>,
+    /// The quote still open at the end of the line, if any.
+    pub open_quote: Option<char>,
+}
+
+impl Word {
+    fn new(start: usize) -> Self {
+        Self {
+            kind: WordKind::Argument,
+            range: start..start,
+            text: String::new(),
+            quotes: Vec::new(),
+            variables: Vec::new(),
+            open_quote: None,
+        }
+    }
+}
+
+/// Splits `line` into words, classifying each by its position in the
+/// command.
+pub fn lex(line: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut word = Word::new(start);
+        if is_operator(c) {
+            chars.next();
+            word.text.push(c);
+            // `||` and `&&`; `;;` isn't an operator here
+            if c != ';' && chars.next_if(|(_, next)| *next == c).is_some() {
+                word.text.push(c);
+            }
+            word.range = start..start + word.text.len();
+            words.push(word);
+            continue;
+        }
+        if c == '#' {
+            word.kind = WordKind::Comment;
+            word.text = line[start..].to_string();
+            word.range = start..line.len();
+            words.push(word);
+            break;
+        }
+
+        let mut quote: Option<(char, usize)> = None;
+        let mut end = start;
+        while let Some(&(idx, c)) = chars.peek() {
+            // `>|` forces a redirection rather than starting a pipe
+            let in_redirect = matches!(c, '|' | '&') && line[..idx].ends_with('>');
+            if quote.is_none() && (c.is_whitespace() || (is_operator(c) && !in_redirect)) {
+                break;
+            }
+            chars.next();
+            end = idx + c.len_utf8();
+
+            match (c, quote) {
+                (q, Some((open, quote_start))) if q == open => {
+                    word.quotes.push(quote_start..end);
+                    quote = None;
+                }
+                ('\'' | '"', None) => quote = Some((c, idx)),
+                ('\\', Some(('\'', _))) => word.text.push(c),
+                // in double quotes a backslash only escapes these
+                ('\\', Some(('"', _)))
+                    if chars
+                        .peek()
+                        .is_none_or(|(_, next)| !matches!(next, '$' | '`' | '"' | '\\' | '\n')) =>
+                {
+                    word.text.push(c)
+                }
+                ('\\', _) => {
+                    if let Some((escaped_idx, escaped)) = chars.next() {
+                        end = escaped_idx + escaped.len_utf8();
+                        word.text.push(escaped);
+                    }
+                }
+                ('$', Some(('\'', _))) => word.text.push(c),
+                ('$', _) => {
+                    let var_end = variable_end(line, end);
+                    if var_end > end {
+                        word.variables.push(idx..var_end);
+                        while chars.next_if(|(next_idx, _)| *next_idx < var_end).is_some() {}
+                        word.text.push_str(&line[idx..var_end]);
+                        end = var_end;
+                    } else {
+                        word.text.push(c);
+                    }
+                }
+                _ => word.text.push(c),
+            }
+        }
+
+        if let Some((open, quote_start)) = quote {
+            word.quotes.push(quote_start..end);
+            word.open_quote = Some(open);
+        }
+        word.range = start..end;
+        words.push(word);
+    }
+
+    classify(line, &mut words);
+    words
+}
+
+/// Whether `c` is, or starts, an operator that ends the word before it.
+fn is_operator(c: char) -> bool {
+    matches!(c, ';' | '|' | '&')
+}
+
+/// The end of the parameter name following a `$` whose name starts at
+/// `start`. Returns `start` when there is no name. A `${` that is never
+/// closed runs to the end of the word.
+fn variable_end(line: &str, start: usize) -> usize {
+    let rest = &line[start..];
+    if let Some(braced) = rest.strip_prefix('{') {
+        return match braced.find(|c: char| c == '}' || c.is_whitespace()) {
+            Some(close) if braced[close..].starts_with('}') => start + close + 2,
+            Some(space) => start + space + 1,
+            None => line.len(),
+        };
+    }
+    match rest.chars().next() {
+        Some(c) if matches!(c, '?' | '$' | '#' | '@' | '*' | '!' | '-') || c.is_ascii_digit() => {
+            start + 1
+        }
+        _ => {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            start + len
+        }
+    }
+}
+
+/// The word the cursor at `pos` is at the end of, as lexed from the line
+/// up to the cursor, and the command word it belongs to. Right after
+/// whitespace or an operator the word is empty, classified as whatever
+/// would be typed there.
+pub fn word_at(line: &str, pos: usize) -> (Word, Option<Word>) {
+    let line = &line[..pos];
+    let mut words = lex(line);
+    if words.last().is_none_or(|word| {
+        word.range.end != pos || matches!(word.kind, WordKind::Separator | WordKind::Background)
+    }) {
+        words.push(Word::new(pos));
+        classify(line, &mut words);
+    }
+
+    let word = words.pop().unwrap();
+    let command = words
+        .into_iter()
+        .rev()
+        .take_while(|word| word.kind != WordKind::Separator)
+        .find(|word| word.kind == WordKind::Command);
+    (word, command)
+}
+
+/// The words of the command the cursor at `pos` is in, as typed, and the
+/// index of the one the cursor is in or at the end of. Between words, an
+/// empty word is inserted at the cursor, as bash does for `COMP_WORDS`.
+pub fn command_words(line: &str, pos: usize) -> (Vec<String>, usize) {
+    let mut words = Vec::new();
+    let mut cword = None;
+    for word in lex(line) {
+        if matches!(
+            word.kind,
+            WordKind::Separator | WordKind::Background | WordKind::Comment
+        ) {
+            if word.range.start >= pos {
+                break;
+            }
+            words.clear();
+            cword = None;
+            continue;
+        }
+        if cword.is_none() && word.range.start > pos {
+            cword = Some(words.len());
+            words.push(String::new());
+        }
+        if cword.is_none() && (word.range.contains(&pos) || word.range.end == pos) {
+            cword = Some(words.len());
+        }
+        words.push(line[word.range.clone()].to_string());
+    }
+    let cword = cword.unwrap_or_else(|| {
+        words.push(String::new());
+        words.len() - 1
+    });
+    (words, cword)
+}
+
+fn classify(line: &str, words: &mut [Word]) {
+    let mut seen_command = false;
+    let mut after_redirect = false;
+    let last = words.len().saturating_sub(1);
+
+    for (idx, word) in words.iter_mut().enumerate() {
+        if word.kind == WordKind::Comment {
+            continue;
+        }
+        let raw = &line[word.range.clone()];
+        word.kind = if matches!(raw, ";" | "|" | "||" | "&&") {
+            seen_command = false;
+            after_redirect = false;
+            WordKind::Separator
+        } else if after_redirect {
+            after_redirect = false;
+            WordKind::RedirectTarget
+        } else if raw == "&" {
+            if idx == last {
+                WordKind::Background
+            } else {
+                seen_command = false;
+                after_redirect = false;
+                WordKind::Separator
+            }
+        } else if word.quotes.is_empty() && CommandPartial::parse(raw).is_redirect() {
+            after_redirect = true;
+            WordKind::Redirect
+        } else if !seen_command && split_assignment(raw).is_some() {
+            WordKind::Assignment
+        } else if !seen_command {
+            seen_command = true;
+            WordKind::Command
+        } else {
+            WordKind::Argument
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(line: &str) -> Vec<WordKind> {
+        lex(line).iter().map(|word| word.kind).collect()
+    }
+
+    #[test]
+    fn words_are_classified() {
+        use WordKind::*;
+        assert_eq!(
+            vec![Assignment, Command, Argument, Redirect, RedirectTarget],
+            kinds("A=1 echo hi 2>> log.txt")
+        );
+        assert_eq!(vec![Command, Argument, Background], kinds("sleep 5 &"));
+        assert_eq!(vec![Command, Argument, Comment], kinds("ls -l # long"));
+        assert_eq!(vec![Command, Argument], kinds("echo '>'"));
+        assert_eq!(
+            vec![Command, Argument, Separator, Command, Separator, Command],
+            kinds("cd src; ls | wc")
+        );
+        assert_eq!(
+            vec![Command, Separator, Command, Separator, Command, Background],
+            kinds("ls|wc&&make&")
+        );
+        assert_eq!(
+            vec![Command, Argument, Separator, Command, Argument, Separator],
+            kinds("sleep 1& ls x||")
+        );
+        assert_eq!(vec![Command, Redirect, RedirectTarget], kinds("ls >| out"));
+        let texts: Vec<String> = lex("a||b&c").into_iter().map(|word| word.text).collect();
+        assert_eq!(vec!["a", "||", "b", "&", "c"], texts);
+    }
+
+    #[test]
+    fn word_at_cursor() {
+        let (word, command) = word_at("  ech", 5);
+        assert_eq!((WordKind::Command, 2..5), (word.kind, word.range));
+        assert!(command.is_none());
+
+        let (word, command) = word_at("cd sr && ls", 5);
+        assert_eq!((WordKind::Argument, "sr"), (word.kind, word.text.as_str()));
+        assert_eq!("cd", command.unwrap().text);
+
+        let (word, command) = word_at("cd src;ls > ", 12);
+        assert_eq!((WordKind::RedirectTarget, 12..12), (word.kind, word.range));
+        assert_eq!("ls", command.unwrap().text);
+
+        let (word, _) = word_at("echo hi; ", 9);
+        assert_eq!(WordKind::Command, word.kind);
+
+        let (word, command) = word_at("ls|", 3);
+        assert_eq!((WordKind::Command, 3..3), (word.kind, word.range));
+        assert!(command.is_none());
+
+        let (word, command) = word_at("ls|wc -", 7);
+        assert_eq!((WordKind::Argument, "-"), (word.kind, word.text.as_str()));
+        assert_eq!("wc", command.unwrap().text);
+    }
+
+    #[test]
+    fn command_words_around_the_cursor() {
+        let (words, cword) = command_words("ls; git co ma", 10);
+        assert_eq!(vec!["git", "co", "ma"], words);
+        assert_eq!(1, cword);
+
+        let (words, cword) = command_words("git  'my file'", 4);
+        assert_eq!(vec!["git", "", "'my file'"], words);
+        assert_eq!(1, cword);
+
+        let (words, cword) = command_words("git commit ", 11);
+        assert_eq!(vec!["git", "commit", ""], words);
+        assert_eq!(2, cword);
+
+        let (words, cword) = command_words("make x && ls", 6);
+        assert_eq!(vec!["make", "x"], words);
+        assert_eq!(1, cword);
+    }
+
+    #[test]
+    fn quotes_and_variables_are_located() {
+        let line = r#"echo "a $HOME" '$x' b\ c ${PATH}"#;
+        let words = lex(line);
+        assert_eq!(5, words.len());
+
+        let quoted = &words[1];
+        assert_eq!(vec![5..14], quoted.quotes);
+        assert_eq!(vec![8..13], quoted.variables);
+        assert_eq!("a $HOME", quoted.text);
+
+        assert!(words[2].variables.is_empty());
+        assert_eq!("$x", words[2].text);
+        assert_eq!("b c", words[3].text);
+        assert_eq!(vec![25..32], words[4].variables);
+
+        let words = lex("echo ${PAT x");
+        assert_eq!(3, words.len());
+        assert_eq!(vec![5..10], words[1].variables);
+        assert_eq!(vec![5..8], lex("echo ${a b}")[1].variables);
+        assert_eq!(vec![5..10], lex("echo ${PAT")[1].variables);
+    }
+
+    #[test]
+    fn backslashes_in_double_quotes_only_escape_some_characters() {
+        let words = lex(r#"echo "a\b \$ \" \\" \x"#);
+        assert_eq!(r#"a\b $ " \"#, words[1].text);
+        assert_eq!("x", words[2].text);
+    }
+
+    #[test]
+    fn open_quotes_run_to_the_end() {
+        let words = lex("cat 'my fi");
+        assert_eq!(2, words.len());
+        assert_eq!(Some('\''), words[1].open_quote);
+        assert_eq!("my fi", words[1].text);
+        assert_eq!(4..10, words[1].range);
+    }
+}
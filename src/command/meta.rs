@@ -1,4 +1,10 @@
-use std::{env, str::Chars};
+use std::{
+    env,
+    ffi::{CStr, CString},
+    str::Chars,
+};
+
+use crate::command::{Lookup, ProcessEnv, is_valid_name};
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum SpecialChar {
@@ -19,23 +25,25 @@ impl SpecialChar {
         }
     }
 
-    pub fn expand(&self, expansion_buf: &str, modifier: &Option<ModifierChar>) -> String {
+    pub fn expand(
+        &self,
+        expansion_buf: &str,
+        modifier: &Option<ModifierChar>,
+        lookup: &dyn Lookup,
+    ) -> String {
+        if *self == Self::Dollar && expansion_buf.is_empty() {
+            return self.name().to_string();
+        }
+
         if let Some(active_mod) = modifier {
             if active_mod.allows_special_char(self) {
                 match self {
-                    Self::Dollar => env::var(expansion_buf).unwrap(),
+                    Self::Dollar => expand_parameter(expansion_buf, lookup),
                     Self::Star => self.name().to_string(),
-                    Self::Tilde => {
-                        let home_dir = env::home_dir().unwrap();
-                        home_dir.into_os_string().into_string().unwrap()
-                    }
+                    Self::Tilde => expand_tilde(expansion_buf, lookup),
                     Self::Backslash => {
                         if *active_mod == ModifierChar::DoubleQuote {
-                            let allowed = match expansion_buf {
-                                "\"" => true,
-                                "\\" => true,
-                                _ => false,
-                            };
+                            let allowed = matches!(expansion_buf, "\"" | "\\");
                             if allowed {
                                 expansion_buf.to_owned()
                             } else {
@@ -46,17 +54,16 @@ impl SpecialChar {
                         }
                     }
                 }
+            } else if *self == Self::Tilde {
+                format!("~{expansion_buf}")
             } else {
                 self.name().to_string()
             }
         } else {
             match self {
-                Self::Dollar => env::var(expansion_buf).unwrap(),
+                Self::Dollar => expand_parameter(expansion_buf, lookup),
                 Self::Star => self.name().to_string(),
-                Self::Tilde => {
-                    let home_dir = env::home_dir().unwrap();
-                    home_dir.into_os_string().into_string().unwrap()
-                }
+                Self::Tilde => expand_tilde(expansion_buf, lookup),
                 Self::Backslash => expansion_buf.to_owned(),
             }
         }
@@ -86,11 +93,9 @@ impl ModifierChar {
     pub fn allows_special_char(&self, special_char: &SpecialChar) -> bool {
         match self {
             Self::SingleQuote => false,
-            Self::DoubleQuote => match special_char {
-                SpecialChar::Dollar => true,
-                SpecialChar::Backslash => true,
-                _ => false,
-            },
+            Self::DoubleQuote => {
+                matches!(special_char, SpecialChar::Dollar | SpecialChar::Backslash)
+            }
         }
     }
 
@@ -145,43 +150,84 @@ enum MetaSymbolExpanderMode {
     EndReached,
 }
 
-#[derive(Debug)]
 pub struct MetaSymbolExpander<'a> {
     chars: Chars<'a>,
+    lookup: &'a dyn Lookup,
     temp_buffer: String,
     expansion_buffer: String,
     mode: MetaSymbolExpanderMode,
     active_mod: Option<ModifierChar>,
     active_special: Option<SpecialChar>,
+    /// Set once the current word has quotes, so `''` still yields a word.
+    word_quoted: bool,
+    /// Set once anything of the current word has been read, even if it
+    /// expanded to nothing, so a `#` after it doesn't start a comment.
+    word_started: bool,
     dbg_run: usize,
 }
 
 impl<'a> MetaSymbolExpander<'a> {
-    pub fn new(chars: Chars) -> MetaSymbolExpander {
+    #[allow(dead_code)]
+    pub fn new(chars: Chars<'a>) -> MetaSymbolExpander<'a> {
+        Self::with_lookup(chars, &ProcessEnv)
+    }
+
+    /// Creates an expander that resolves `$name` through `lookup` instead of
+    /// the process environment.
+    pub fn with_lookup(chars: Chars<'a>, lookup: &'a dyn Lookup) -> MetaSymbolExpander<'a> {
         MetaSymbolExpander {
             dbg_run: 0,
             chars,
+            lookup,
             temp_buffer: String::with_capacity(10),
             expansion_buffer: String::with_capacity(10),
             mode: MetaSymbolExpanderMode::Chunking,
             active_mod: None,
             active_special: None,
+            word_quoted: false,
+            word_started: false,
         }
     }
 
     fn process_next_char(&mut self) {
         let next_char = self.chars.next();
-        if let None = next_char {
+        if next_char.is_none() {
+            if self.active_special == Some(SpecialChar::Dollar) {
+                self.finish_parameter();
+            } else if self.active_special == Some(SpecialChar::Tilde) {
+                self.finish_tilde();
+            }
             self.mode = MetaSymbolExpanderMode::EndReached;
             return;
         }
 
         let fn_for_normal = |s: &mut Self, normal_char: char| {
-            if let Some(special) = s.active_special {
+            if normal_char == '#'
+                && !s.word_started
+                && s.active_mod.is_none()
+                && s.active_special.is_none()
+            {
+                // A '#' that starts a word comments out the rest of the line
+                s.mode = MetaSymbolExpanderMode::EndReached;
+                return;
+            }
+            s.word_started = true;
+            if s.active_special == Some(SpecialChar::Dollar) {
+                s.push_parameter_char(normal_char);
+            } else if s.active_special == Some(SpecialChar::Tilde)
+                && (normal_char == '/' || (normal_char == ':' && !s.temp_buffer.is_empty()))
+            {
+                // In an assignment's value, a `:` ends the prefix too
+                s.finish_tilde();
+                s.temp_buffer.push(normal_char);
+            } else if let Some(special) = s.active_special {
                 s.expansion_buffer.push(normal_char);
                 if special == SpecialChar::Backslash {
-                    s.temp_buffer
-                        .push_str(&special.expand(&s.expansion_buffer, &s.active_mod));
+                    s.temp_buffer.push_str(&special.expand(
+                        &s.expansion_buffer,
+                        &s.active_mod,
+                        s.lookup,
+                    ));
                     s.expansion_buffer.clear();
                     s.active_special = None;
                 }
@@ -191,6 +237,7 @@ impl<'a> MetaSymbolExpander<'a> {
         };
 
         let fn_for_special = |s: &mut Self, special_char: SpecialChar| {
+            s.word_started = true;
             if s.active_mod
                 .is_some_and(|s| s.allows_special_char(&special_char))
                 || s.active_mod.is_none()
@@ -199,19 +246,26 @@ impl<'a> MetaSymbolExpander<'a> {
                 //     "active-mod is some and allows new special char",
                 //     special_char
                 // );
+                if s.active_special == Some(SpecialChar::Dollar)
+                    && s.push_parameter_special(special_char)
+                {
+                    return;
+                }
                 if let Some(active_spec_char) = s.active_special {
                     // dbg!("there is another special char");
                     s.expansion_buffer.push(special_char.name());
                     if let SpecialChar::Backslash = active_spec_char {
                         // dbg!("active special char is backslash");
-                        s.temp_buffer
-                            .push_str(&active_spec_char.expand(&s.expansion_buffer, &s.active_mod));
+                        s.temp_buffer.push_str(&active_spec_char.expand(
+                            &s.expansion_buffer,
+                            &s.active_mod,
+                            s.lookup,
+                        ));
                         s.expansion_buffer.clear();
                         s.active_special = None;
                     }
-                } else if let SpecialChar::Tilde = special_char {
-                    s.temp_buffer
-                        .push_str(&special_char.expand("", &s.active_mod));
+                } else if special_char == SpecialChar::Tilde && !s.tilde_can_expand() {
+                    s.temp_buffer.push(special_char.name());
                 } else {
                     // dbg!("the new special char gets set as active special");
                     s.active_special = Some(special_char);
@@ -222,6 +276,7 @@ impl<'a> MetaSymbolExpander<'a> {
         };
 
         let fn_for_mod = |s: &mut Self, new_mod_char: ModifierChar| {
+            s.word_started = true;
             // dbg!("here comes a new mod char", new_mod_char, s.dbg_run);
             if let Some(current_mod_char) = s.active_mod {
                 // dbg!(
@@ -240,8 +295,11 @@ impl<'a> MetaSymbolExpander<'a> {
                             // dbg!("no. active zone - deativated", s.dbg_run);
                             s.active_mod = None;
                         }
-                        s.temp_buffer
-                            .push_str(&special_char.expand(&s.expansion_buffer, &s.active_mod));
+                        s.temp_buffer.push_str(&special_char.expand(
+                            &s.expansion_buffer,
+                            &s.active_mod,
+                            s.lookup,
+                        ));
                         s.expansion_buffer.clear();
                         // dbg!(
                         //     "expansion buffer expanded and merged with temp buffer",
@@ -266,8 +324,11 @@ impl<'a> MetaSymbolExpander<'a> {
                         //     s.active_special
                         // );
                         s.expansion_buffer.push(new_mod_char.name());
-                        s.temp_buffer
-                            .push_str(&special_char.expand(&s.expansion_buffer, &s.active_mod));
+                        s.temp_buffer.push_str(&special_char.expand(
+                            &s.expansion_buffer,
+                            &s.active_mod,
+                            s.lookup,
+                        ));
                         s.active_special = None;
                         s.expansion_buffer.clear();
                     } else {
@@ -280,13 +341,18 @@ impl<'a> MetaSymbolExpander<'a> {
                         s.expansion_buffer.push(new_mod_char.name());
                     } else {
                         s.active_mod = Some(new_mod_char);
+                        s.word_quoted = true;
                     }
-                    s.temp_buffer
-                        .push_str(&special_char.expand(&s.expansion_buffer, &s.active_mod));
+                    s.temp_buffer.push_str(&special_char.expand(
+                        &s.expansion_buffer,
+                        &s.active_mod,
+                        s.lookup,
+                    ));
                     s.expansion_buffer.clear();
                     s.active_special = None;
                 } else {
                     s.active_mod = Some(new_mod_char);
+                    s.word_quoted = true;
                 }
             }
         };
@@ -298,8 +364,11 @@ impl<'a> MetaSymbolExpander<'a> {
                     s.expansion_buffer.push(separator.name());
                 }
 
-                s.temp_buffer
-                    .push_str(&special_char.expand(&s.expansion_buffer, &s.active_mod));
+                s.temp_buffer.push_str(&special_char.expand(
+                    &s.expansion_buffer,
+                    &s.active_mod,
+                    s.lookup,
+                ));
                 s.expansion_buffer.clear();
                 s.active_special = None;
 
@@ -309,8 +378,11 @@ impl<'a> MetaSymbolExpander<'a> {
             }
             if s.active_mod.is_some() {
                 s.temp_buffer.push(separator.name());
-            } else if !s.temp_buffer.is_empty() {
-                s.mode = MetaSymbolExpanderMode::ChunkReady;
+            } else {
+                if !s.temp_buffer.is_empty() || s.word_quoted {
+                    s.mode = MetaSymbolExpanderMode::ChunkReady;
+                }
+                s.word_started = false;
             }
         };
         self.apply_special_or_meta_or_separator_or_else(
@@ -322,6 +394,88 @@ impl<'a> MetaSymbolExpander<'a> {
         );
     }
 
+    /// Adds `c` to the parameter name following a `$`, expanding the
+    /// parameter as soon as the name is complete.
+    fn push_parameter_char(&mut self, c: char) {
+        if self.expansion_buffer.starts_with('{') {
+            if c == '}' {
+                self.expansion_buffer.remove(0);
+                self.finish_parameter();
+            } else {
+                self.expansion_buffer.push(c);
+            }
+        } else if self.expansion_buffer.is_empty() && c == '{' {
+            self.expansion_buffer.push(c);
+        } else if self.expansion_buffer.is_empty() && is_special_parameter(c) {
+            self.expansion_buffer.push(c);
+            self.finish_parameter();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            self.expansion_buffer.push(c);
+        } else {
+            self.finish_parameter();
+            self.temp_buffer.push(c);
+        }
+    }
+
+    /// Handles a special character following a `$`. Returns `false` when the
+    /// character isn't part of the parameter, in which case the parameter has
+    /// been expanded and the character still needs processing.
+    fn push_parameter_special(&mut self, special_char: SpecialChar) -> bool {
+        let is_parameter = match special_char {
+            SpecialChar::Dollar | SpecialChar::Star => self.expansion_buffer.is_empty(),
+            _ => false,
+        };
+        if is_parameter {
+            self.expansion_buffer.push(special_char.name());
+            self.finish_parameter();
+            true
+        } else if self.expansion_buffer.starts_with('{') {
+            self.expansion_buffer.push(special_char.name());
+            true
+        } else {
+            self.finish_parameter();
+            false
+        }
+    }
+
+    fn finish_parameter(&mut self) {
+        self.temp_buffer.push_str(&SpecialChar::Dollar.expand(
+            &self.expansion_buffer,
+            &self.active_mod,
+            self.lookup,
+        ));
+        self.expansion_buffer.clear();
+        self.active_special = None;
+    }
+
+    /// Whether a `~` here starts a tilde prefix: at the start of a word, or
+    /// in an assignment word such as `PATH=~/bin:~/sbin` right after the
+    /// first `=` or after a `:` in the value.
+    fn tilde_can_expand(&self) -> bool {
+        if self.word_quoted {
+            return false;
+        }
+        if self.temp_buffer.is_empty() {
+            return true;
+        }
+        self.temp_buffer
+            .split_once('=')
+            .is_some_and(|(name, value)| {
+                is_valid_name(name) && (value.is_empty() || value.ends_with(':'))
+            })
+    }
+
+    /// Expands the tilde prefix collected since a `~` that can expand.
+    fn finish_tilde(&mut self) {
+        self.temp_buffer.push_str(&SpecialChar::Tilde.expand(
+            &self.expansion_buffer,
+            &self.active_mod,
+            self.lookup,
+        ));
+        self.expansion_buffer.clear();
+        self.active_special = None;
+    }
+
     fn apply_special_or_meta_or_separator_or_else(
         &mut self,
         character: Option<char>,
@@ -346,26 +500,73 @@ impl<'a> MetaSymbolExpander<'a> {
     }
 }
 
+fn expand_parameter(name: &str, lookup: &dyn Lookup) -> String {
+    lookup.variable(name).unwrap_or_else(|| {
+        lookup.unset_variable(name);
+        String::new()
+    })
+}
+
+/// Expands `~prefix`: `~` alone is `$HOME`, `~+` and `~-` are `$PWD` and
+/// `$OLDPWD`, `~N`, `~+N` and `~-N` are directory stack entries, and
+/// anything else names a user. A prefix that resolves to nothing is left as
+/// it was.
+fn expand_tilde(prefix: &str, lookup: &dyn Lookup) -> String {
+    let expanded = match prefix {
+        "" => lookup
+            .variable("HOME")
+            .or_else(|| env::home_dir().map(|home| home.display().to_string())),
+        "+" => lookup.variable("PWD"),
+        "-" => lookup.variable("OLDPWD"),
+        _ => lookup
+            .directory_stack_entry(prefix)
+            .map(|dir| dir.display().to_string())
+            .or_else(|| user_home(prefix)),
+    };
+    expanded.unwrap_or_else(|| format!("~{prefix}"))
+}
+
+fn user_home(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    // SAFETY: getpwnam returns null or a pointer to a static record that
+    // stays valid until the next call; the directory is copied out right
+    // away.
+    unsafe {
+        let entry = libc::getpwnam(name.as_ptr());
+        if entry.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*entry).pw_dir)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// Single-character parameters such as `$?` and `$1`.
+fn is_special_parameter(c: char) -> bool {
+    matches!(c, '?' | '#' | '@' | '!' | '-') || c.is_ascii_digit()
+}
+
 impl<'a> Iterator for MetaSymbolExpander<'a> {
     type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
-        'outer: while self.mode != MetaSymbolExpanderMode::EndReached {
-            while self.mode != MetaSymbolExpanderMode::ChunkReady {
-                if self.mode == MetaSymbolExpanderMode::EndReached {
-                    break 'outer;
-                }
-                self.process_next_char();
+        while self.mode != MetaSymbolExpanderMode::EndReached {
+            if self.mode == MetaSymbolExpanderMode::ChunkReady {
+                self.mode = MetaSymbolExpanderMode::Chunking;
+                self.word_quoted = false;
+
+                let res = self.temp_buffer.clone();
+                self.temp_buffer.clear();
+                return Some(res);
             }
-
-            self.mode = MetaSymbolExpanderMode::Chunking;
-
-            let res = self.temp_buffer.clone();
-            self.temp_buffer.clear();
-            return Some(res);
+            self.process_next_char();
         }
 
-        if !&self.temp_buffer.is_empty() {
+        if !self.temp_buffer.is_empty() || self.word_quoted {
+            self.word_quoted = false;
             let res = self.temp_buffer.clone();
             self.temp_buffer.clear();
             return Some(res);
@@ -378,6 +579,7 @@ impl<'a> Iterator for MetaSymbolExpander<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::path::PathBuf;
     #[test]
     fn expander_case1() {
         let input = "\"hello\" \"world\"";
@@ -503,4 +705,125 @@ mod test {
         assert_eq!(expected_command, actual_command);
         assert_eq!(expected_args, actual_args);
     }
+
+    struct TestLookup;
+
+    impl Lookup for TestLookup {
+        fn variable(&self, name: &str) -> Option<String> {
+            match name {
+                "HOME" => Some("/home/user".to_string()),
+                "?" => Some("1".to_string()),
+                "1" => Some("first".to_string()),
+                "OLDPWD" => Some("/old".to_string()),
+                _ => None,
+            }
+        }
+
+        fn directory_stack_entry(&self, spec: &str) -> Option<PathBuf> {
+            (spec == "1").then(|| PathBuf::from("/pushed"))
+        }
+    }
+
+    #[test]
+    fn expander_parameters() {
+        let input = r#"$HOME/bin "${HOME}x" $? $1$HOME $UNSET. '$HOME' $ end$HOME"#;
+        let input_iter = MetaSymbolExpander::with_lookup(input.chars(), &TestLookup);
+
+        let actual: Vec<String> = input_iter.collect();
+        let expected = vec![
+            "/home/user/bin".to_string(),
+            "/home/userx".to_string(),
+            "1".to_string(),
+            "first/home/user".to_string(),
+            ".".to_string(),
+            "$HOME".to_string(),
+            "$".to_string(),
+            "end/home/user".to_string(),
+        ];
+
+        assert_eq!(expected, actual, "\ninput: {:#?}", input);
+    }
+
+    #[test]
+    fn expander_case11() {
+        let input = "echo hello#world # a comment";
+        let input_iter = MetaSymbolExpander::new(input.chars());
+
+        let actual: Vec<String> = input_iter.collect();
+        let expected = vec!["echo".to_string(), "hello#world".to_string()];
+
+        assert_eq!(expected, actual, "\ninput: {:#?}", input);
+    }
+
+    #[test]
+    fn expander_case12() {
+        let input = "trap '' INT \"\"";
+        let input_iter = MetaSymbolExpander::new(input.chars());
+
+        let actual: Vec<String> = input_iter.collect();
+        let expected = vec![
+            "trap".to_string(),
+            "".to_string(),
+            "INT".to_string(),
+            "".to_string(),
+        ];
+
+        assert_eq!(expected, actual, "\ninput: {:#?}", input);
+    }
+
+    #[test]
+    fn expander_case13() {
+        let input = "echo ''#x $SHELL_SURELY_UNSET#y \\#z #gone";
+        let input_iter = MetaSymbolExpander::new(input.chars());
+
+        let actual: Vec<String> = input_iter.collect();
+        let expected = vec![
+            "echo".to_string(),
+            "#x".to_string(),
+            "#y".to_string(),
+            "#z".to_string(),
+        ];
+
+        assert_eq!(expected, actual, "\ninput: {:#?}", input);
+    }
+
+    #[test]
+    fn expander_tilde_prefixes() {
+        let input = r#"~ ~/src ~1/x ~- ~+ ~root a~ "~" ~'' ~9 ~nosuchuser/x"#;
+        let input_iter = MetaSymbolExpander::with_lookup(input.chars(), &TestLookup);
+
+        let actual: Vec<String> = input_iter.collect();
+        let expected = vec![
+            "/home/user".to_string(),
+            "/home/user/src".to_string(),
+            "/pushed/x".to_string(),
+            "/old".to_string(),
+            "~+".to_string(),
+            user_home("root").unwrap(),
+            "a~".to_string(),
+            "~".to_string(),
+            "~".to_string(),
+            "~9".to_string(),
+            "~nosuchuser/x".to_string(),
+        ];
+
+        assert_eq!(expected, actual, "\ninput: {:#?}", input);
+
+        let input = r#"X=~/bin PATH=~/bin:~1:$HOME a=~ X=a~ X=a=~ 1X=~ a:~ X="~""#;
+        let input_iter = MetaSymbolExpander::with_lookup(input.chars(), &TestLookup);
+
+        let actual: Vec<String> = input_iter.collect();
+        let expected = vec![
+            "X=/home/user/bin".to_string(),
+            "PATH=/home/user/bin:/pushed:/home/user".to_string(),
+            "a=/home/user".to_string(),
+            "X=a~".to_string(),
+            "X=a=~".to_string(),
+            "1X=~".to_string(),
+            "a:~".to_string(),
+            "X=~".to_string(),
+        ];
+
+        assert_eq!(expected, actual, "\ninput: {:#?}", input);
+    }
 }
@@ -1,2 +1,5 @@
 pub mod command;
 pub mod shell;
+
+#[cfg(test)]
+mod test_util;
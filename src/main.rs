@@ -1,43 +1,101 @@
-use codecrafters_shell::command::{self, BUILTIN_COMMAND_NAMES};
-use codecrafters_shell::shell::{Command, CommandCompleter, Shell};
+use codecrafters_shell::command::completer::AcceptHint;
+use codecrafters_shell::command::highlight::ColorScheme;
+use codecrafters_shell::command::{self, BUILTIN_COMMAND_NAMES, Continuation};
+use codecrafters_shell::shell::history::SharedHistory;
+use codecrafters_shell::shell::history::picker::HistoryPicker;
+use codecrafters_shell::shell::{CommandCompleter, Shell, StartupOptions};
 use rustyline::error::ReadlineError;
-use rustyline::history::DefaultHistory;
-use rustyline::{CompletionType, Config, Editor, Result};
+use rustyline::{CompletionType, Config, Editor, EventHandler, KeyEvent, Result};
+
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[allow(unused_imports)]
 use std::io::{self, Write, stdin};
 
 fn main() -> Result<()> {
-    let path = std::env::var_os("PATH").unwrap_or_default();
-    let external_commands = command::get_external_commands(path);
-    let command_names: Vec<String> = external_commands
-        .keys()
-        .map(|key| key.clone().into_string())
-        .filter_map(|key| key.ok())
-        .collect();
-    let command_name_refs: Vec<&str> = command_names.iter().map(String::as_str).collect();
-    let mut autocompleter = CommandCompleter::new(&command_name_refs);
-    if let Err(msg) = autocompleter.add_commands(BUILTIN_COMMAND_NAMES) {
-        panic!("{}", msg);
-    }
+    let startup_options = match StartupOptions::from_args(std::env::args()) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("shell: {msg}");
+            std::process::exit(2);
+        }
+    };
+
+    let mut autocompleter = CommandCompleter::new(BUILTIN_COMMAND_NAMES);
     let config = Config::builder()
         .completion_type(CompletionType::List) // or CompletionType::List
         .build();
-    let mut rl: Editor<CommandCompleter, DefaultHistory> = Editor::with_config(config)?;
-    rl.set_helper(Some(autocompleter));
-
     let mut shell = Shell::new();
+    shell.init_signals();
+    shell.init_job_control();
+    shell.run_startup_files(&startup_options);
+    shell.load_history();
+
+    let mut rl: Editor<CommandCompleter, SharedHistory> =
+        Editor::with_history(config, shell.history())?;
+    autocompleter.set_history(shell.history());
+    autocompleter.set_command_cache(shell.command_cache());
+    autocompleter.set_completions(shell.completions());
+
+    // The completer runs `complete -F` functions in the shell, which it can
+    // only borrow while no line is being executed.
+    let shell = Rc::new(RefCell::new(shell));
+    let runner_shell = Rc::clone(&shell);
+    autocompleter.set_function_runner(Box::new(move |function, comp| {
+        match runner_shell.try_borrow_mut() {
+            Ok(mut shell) => shell.run_completion_function(function, comp),
+            Err(_) => Vec::new(),
+        }
+    }));
+    rl.set_helper(Some(autocompleter));
+    rl.bind_sequence(
+        KeyEvent::ctrl('F'),
+        EventHandler::Conditional(Box::new(AcceptHint)),
+    );
+    let picker = HistoryPicker::new(shell.borrow().history());
+    rl.bind_sequence(
+        KeyEvent::ctrl('R'),
+        EventHandler::Conditional(Box::new(picker.clone())),
+    );
 
     loop {
-        let readline = rl.readline("$ ");
+        let prompt = {
+            let mut shell = shell.borrow_mut();
+            shell.run_pending_traps();
+            shell.notify_jobs();
+            let prompt = shell.prompt();
+            if let Some(helper) = rl.helper_mut() {
+                helper.set_color_scheme(ColorScheme::from_lookup(&*shell));
+                helper.set_working_dir(shell.working_dir().to_path_buf());
+                helper.set_home_dir(shell.home_dir());
+                helper.set_fuzzy(shell.fuzzy_completion());
+                helper.set_variables(shell.variable_names());
+                helper.set_functions(shell.function_names());
+            }
+            picker.set_dir(shell.working_dir(), shell.home_dir());
+            prompt
+        };
+        if let Some(helper) = rl.helper_mut() {
+            helper.set_styled_prompt(prompt.styled);
+        }
+        let readline = rl.readline(&prompt.text);
         match readline {
             Ok(line) => {
-                let command_result = Command::parse(&line);
-                shell.exec_command(command_result);
+                let Some(line) = read_continuation_lines(&mut rl, &shell, line) else {
+                    continue;
+                };
+                let mut shell = shell.borrow_mut();
+                if let Some(line) = shell.expand_history(&line) {
+                    shell.add_history(&line);
+                    shell.run_line(&line);
+                    shell.finish_history();
+                }
             }
             Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
+                // Ctrl-C only discards the line being edited
+                shell.borrow_mut().line_interrupted();
+                continue;
             }
             Err(ReadlineError::Eof) => {
                 println!("CTRL-D");
@@ -50,5 +108,39 @@ fn main() -> Result<()> {
         }
     }
 
+    shell.borrow_mut().run_exit_trap();
     Ok(())
 }
+
+/// Keeps reading lines with the `PS2` prompt while `line` is unfinished.
+/// Returns `None` if reading is interrupted before the line is complete.
+fn read_continuation_lines(
+    rl: &mut Editor<CommandCompleter, SharedHistory>,
+    shell: &RefCell<Shell>,
+    mut line: String,
+) -> Option<String> {
+    while let Some(continuation) = command::continuation(&line) {
+        let prompt = shell.borrow_mut().continuation_prompt();
+        if let Some(helper) = rl.helper_mut() {
+            helper.set_styled_prompt(prompt.styled);
+        }
+        match rl.readline(&prompt.text) {
+            Ok(next) => continuation.join(&mut line, &next),
+            Err(ReadlineError::Eof) => {
+                match continuation {
+                    Continuation::Quote(quote) => {
+                        eprintln!("unexpected EOF while looking for matching `{quote}'");
+                    }
+                    Continuation::Function => eprintln!("syntax error: unexpected end of file"),
+                    Continuation::Escape => {}
+                }
+                return None;
+            }
+            Err(_) => {
+                shell.borrow_mut().line_interrupted();
+                return None;
+            }
+        }
+    }
+    Some(line)
+}
@@ -1,18 +1,96 @@
+mod completion;
+mod dirs;
+mod functions;
+pub mod hash;
+pub mod history;
+pub mod jobs;
+mod options;
+mod output;
+pub mod prompt;
+mod read;
+pub mod startup;
+mod traps;
+mod vars;
+
 pub use crate::command::Command;
 pub use crate::command::completer::CommandCompleter;
+pub use crate::shell::startup::StartupOptions;
 
-use crate::command::{CommandResult, RedirectInfo};
+use crate::command::completer::spec::SharedSpecs;
+use crate::command::{self, CommandResult, ListOp, RedirectInfo};
+use crate::shell::hash::SharedCommandCache;
+use crate::shell::history::SharedHistory;
+use crate::shell::jobs::JobTable;
+use crate::shell::options::Options;
+use crate::shell::traps::{Trap, TrapTable};
 
 use std::{
-    env,
+    cell::RefCell,
+    collections::HashMap,
+    env, fs,
     io::{self, Write},
-    path::PathBuf,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
     process::{self, Command as StdProcCmd, Stdio},
 };
 pub struct Shell {
     working_dir: PathBuf,
+    /// Directories saved by `pushd`, most recent first. The working
+    /// directory is the top of the stack and isn't stored here.
+    dir_stack: Vec<PathBuf>,
     stdout_redirect: Option<RedirectInfo>,
     stderr_redirect: Option<RedirectInfo>,
+    /// Where stdout goes when nothing on the line redirects it, while a
+    /// command substitution is capturing output.
+    capture: Option<RedirectInfo>,
+    source_location: Option<SourceLocation>,
+    /// Variables that are not exported; exported ones live in the process
+    /// environment so children inherit them.
+    variables: HashMap<String, String>,
+    /// Indexed arrays, assigned with `name=(...)`. `${name[N]}` reads an
+    /// element and `$name` the first one.
+    arrays: HashMap<String, Vec<String>>,
+    positional_params: Vec<String>,
+    /// Bodies of the functions defined with `name() { ... }`, by name.
+    functions: HashMap<String, String>,
+    last_status: i32,
+    history: SharedHistory,
+    /// Completion specs registered with `complete`.
+    completions: SharedSpecs,
+    /// Executables found on `PATH`, shared with the completer.
+    commands: SharedCommandCache,
+    jobs: JobTable,
+    traps: TrapTable,
+    options: Options,
+    /// Whether stdin is a terminal.
+    interactive: bool,
+    /// The shell's process id, `$$`. A background builtin runs in a forked
+    /// copy of the shell, which keeps this shell's id as bash does.
+    pid: u32,
+    /// Whether the command running is tested by an `&&` or `||`, so a
+    /// failure neither triggers `set -e` nor the `ERR` trap.
+    in_condition: bool,
+    /// The first unset parameter expanded while parsing a line under
+    /// `set -u`. The parser only has shared access to the shell.
+    unbound_variable: RefCell<Option<String>>,
+    /// The line being executed, used to describe jobs.
+    current_line: String,
+    /// Set to the job's command text while running a line that ended with `&`.
+    background_job: Option<String>,
+}
+
+/// The file and line currently being executed by `source_file`, used to
+/// prefix error messages.
+#[derive(Debug, Clone)]
+struct SourceLocation {
+    file: PathBuf,
+    line: usize,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Shell {
@@ -22,145 +100,465 @@ impl Shell {
     ///
     ///
     pub fn new() -> Self {
-        Self {
-            working_dir: env::current_dir().unwrap(),
+        let mut shell = Self {
+            working_dir: dirs::initial_working_dir(),
+            dir_stack: Vec::new(),
             stdout_redirect: None,
             stderr_redirect: None,
+            capture: None,
+            source_location: None,
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            positional_params: Vec::new(),
+            functions: HashMap::new(),
+            last_status: 0,
+            history: SharedHistory::default(),
+            completions: SharedSpecs::default(),
+            commands: SharedCommandCache::default(),
+            jobs: JobTable::default(),
+            traps: TrapTable::default(),
+            options: Options::default(),
+            interactive: false,
+            pid: process::id(),
+            in_condition: false,
+            unbound_variable: RefCell::new(None),
+            current_line: String::new(),
+            background_job: None,
+        };
+        shell.init_history_variables();
+        shell.init_prompt_variables();
+        shell
+    }
+
+    /// Parses and executes a single line of input, which may be a list of
+    /// commands separated by `;`, `&`, `&&` and `||`.
+    pub fn run_line(&mut self, line: &str) {
+        let commands = match command::split_list(line) {
+            Ok(commands) => commands,
+            Err(operator) => {
+                self.display_error(format!(
+                    "syntax error near `{operator}': pipelines are not supported"
+                ));
+                self.last_status = 2;
+                return;
+            }
+        };
+
+        let outer_condition = self.in_condition;
+        let mut commands = commands.into_iter().peekable();
+        while let Some((op, command)) = commands.next() {
+            let runs = match op {
+                ListOp::Always => true,
+                ListOp::And => self.last_status == 0,
+                ListOp::Or => self.last_status != 0,
+            };
+            if !runs {
+                continue;
+            }
+            self.in_condition = outer_condition
+                || commands
+                    .peek()
+                    .is_some_and(|(next, _)| *next != ListOp::Always);
+            let finished = self.run_command(&command);
+            self.in_condition = outer_condition;
+            if !finished {
+                break;
+            }
+        }
+    }
+
+    /// Parses and executes one command of a list. Returns false if the rest
+    /// of the line mustn't run, because expanding an unset variable failed.
+    fn run_command(&mut self, line: &str) -> bool {
+        if let Some((name, body)) = command::function_definition(line) {
+            self.functions.insert(name, body);
+            self.last_status = 0;
+            return true;
+        }
+        if self.assign_array(line) {
+            return true;
+        }
+        let command_result = Command::parse_with(line, self);
+        if let Some(name) = self.unbound_variable.take() {
+            self.display_error(format!("{name}: unbound variable"));
+            self.last_status = 1;
+            if !self.interactive {
+                self.exit(1);
+            }
+            return false;
+        }
+        if self.options.noexec && !self.interactive {
+            return true;
+        }
+        self.exec_command(command_result);
+        true
+    }
+
+    /// Runs the `EXIT` trap and exits with `status`.
+    pub fn exit(&mut self, status: i32) -> ! {
+        self.run_exit_trap();
+        process::exit(status);
+    }
+
+    /// Exit status of the most recently executed command, as in `$?`.
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    /// The directory commands run in, as shown by `pwd`.
+    pub fn working_dir(&self) -> &Path {
+        &self.working_dir
+    }
+
+    /// Executes every line of `path` in this shell, so directory changes and
+    /// the like persist after the file is done.
+    ///
+    /// Errors from individual lines are reported with the file name and line
+    /// number and do not stop the remaining lines from running.
+    pub fn source_file(&mut self, path: &Path) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        let outer_location = self.source_location.take();
+
+        let mut lines = contents.lines().enumerate();
+        while let Some((idx, first)) = lines.next() {
+            self.source_location = Some(SourceLocation {
+                file: path.to_path_buf(),
+                line: idx + 1,
+            });
+            // a line left unfinished takes in the lines after it
+            let mut line = first.to_string();
+            while let Some(continuation) = command::continuation(&line)
+                && let Some((_, next)) = lines.next()
+            {
+                continuation.join(&mut line, next);
+            }
+            self.run_line(&line);
+        }
+
+        self.source_location = outer_location;
+        Ok(())
+    }
+
+    /// Runs the lines of `text` in the middle of another line, as traps and
+    /// prompt substitutions do. `$?`, the redirections and the job
+    /// bookkeeping of the outer line are restored afterwards.
+    fn run_nested(&mut self, text: &str) {
+        let saved_status = self.last_status;
+        let stdout_redirect = self.stdout_redirect.take();
+        let stderr_redirect = self.stderr_redirect.take();
+        let current_line = std::mem::take(&mut self.current_line);
+        let background_job = self.background_job.take();
+
+        for line in text.lines() {
+            self.run_line(line);
         }
+
+        self.last_status = saved_status;
+        self.stdout_redirect = stdout_redirect;
+        self.stderr_redirect = stderr_redirect;
+        self.current_line = current_line;
+        self.background_job = background_job;
     }
 
     pub fn exec_command(&mut self, command_result: CommandResult) {
+        self.current_line = command_result.input().to_string();
+        self.background_job = command_result
+            .background
+            .then(|| command_result.input().to_string());
+
         for cmd in command_result.commands {
-            match &cmd {
-                Command::Cd(exec_path) => {
-                    match env::set_current_dir(&exec_path) {
-                        Ok(_) => self.change_dir(env::current_dir().unwrap()),
-                        Err(_) => {
-                            self.display_error(format!(
-                                "cd: {}: No such file or directory",
-                                exec_path.display()
-                            ));
-                        }
-                    };
+            let is_modifier = matches!(cmd, Command::EnviromentalModifier { .. });
+            if let Err(err) = self.check_noclobber(&cmd) {
+                self.display_error(err);
+                self.last_status = 1;
+                break;
+            }
+            if !is_modifier {
+                self.run_trap(Trap::Debug);
+                if self.options.xtrace {
+                    self.trace(&command_result.words);
                 }
-                Command::Echo(msg) => {
-                    self.display_result(format!("{msg}"));
+            }
+            self.last_status = self.exec_single(&cmd, &[]);
+            if !is_modifier && self.last_status != 0 && !self.in_condition {
+                self.run_trap(Trap::Err);
+                if self.options.errexit {
+                    self.exit(self.last_status);
                 }
+            }
+            self.run_pending_traps();
+        }
 
-                Command::External { exec_path, args } => {
-                    let filename = exec_path
-                        .file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or_default();
-
-                    let mut cmd = StdProcCmd::new(filename);
-                    cmd.args(args).stdin(Stdio::inherit());
-
-                    if let Some(stdout_redirect) = &self.stdout_redirect {
-                        match stdout_redirect.options.open(&stdout_redirect.file_path) {
-                            Ok(file) => {
-                                cmd.stdout(Stdio::from(file));
-                            }
-                            Err(_) => {}
-                        }
-                    } else {
-                        cmd.stdout(Stdio::inherit());
-                    }
+        self.background_job = None;
+    }
 
-                    if let Some(stderr_redirect) = &self.stderr_redirect {
-                        match stderr_redirect.options.open(&stderr_redirect.file_path) {
-                            Ok(file) => {
-                                cmd.stderr(Stdio::from(file));
-                            }
-                            Err(_) => {}
-                        }
-                    } else {
-                        cmd.stderr(Stdio::inherit());
+    /// Runs one parsed command and returns its exit status. `env_overrides`
+    /// holds `NAME=value` prefixes that only apply to this command.
+    fn exec_single(&mut self, cmd: &Command, env_overrides: &[(String, String)]) -> i32 {
+        if self.background_job.is_some() && runs_in_shell(cmd) {
+            return self.fork_builtin(cmd, env_overrides);
+        }
+        match cmd {
+            Command::Cd(args) => self.cd_builtin(args),
+            Command::Echo(args) => self.echo_builtin(args),
+
+            Command::Printf(args) => self.printf_builtin(args),
+
+            Command::Read(args) => self.read_builtin(args, env_overrides),
+
+            Command::External {
+                name,
+                exec_path,
+                args,
+            } => {
+                let mut cmd = StdProcCmd::new(exec_path);
+                cmd.arg0(name)
+                    .args(args)
+                    .envs(env_overrides.iter().map(|(name, value)| (name, value)))
+                    .stdin(Stdio::inherit());
+
+                if let Some(stdout_redirect) = self.stdout_target() {
+                    if let Ok(file) = stdout_redirect.options.open(&stdout_redirect.file_path) {
+                        cmd.stdout(Stdio::from(file));
                     }
+                } else {
+                    cmd.stdout(Stdio::inherit());
+                }
 
-                    let _ = cmd.status();
+                if let Some(stderr_redirect) = &self.stderr_redirect {
+                    if let Ok(file) = stderr_redirect.options.open(&stderr_redirect.file_path) {
+                        cmd.stderr(Stdio::from(file));
+                    }
+                } else {
+                    cmd.stderr(Stdio::inherit());
                 }
 
-                Command::Type(inner_commands) => {
-                    for command in inner_commands {
-                        match command {
-                            Command::None(name) => {
-                                self.display_error(format!("{name}: not found"));
-                            }
-                            Command::External { exec_path, args: _ } => {
-                                let res = format!(
-                                    "{} is {}",
-                                    exec_path.file_name().unwrap_or_default().display(),
-                                    exec_path.display()
-                                );
-                                self.display_result(res);
-                            }
-                            Command::EnviromentalModifier { .. } => {}
-                            builtin => {
-                                self.display_result(format!("{builtin} is a shell builtin"));
-                            }
+                self.spawn_job(cmd, name)
+            }
+
+            Command::Type(inner_commands) => {
+                let mut status = 0;
+                for command in inner_commands {
+                    match command {
+                        Command::None(name) => {
+                            self.display_error(format!("{name}: not found"));
+                            status = 1;
+                        }
+                        Command::External { exec_path, .. } => {
+                            let res = format!(
+                                "{} is {}",
+                                exec_path.file_name().unwrap_or_default().display(),
+                                exec_path.display()
+                            );
+                            self.display_result(res);
+                        }
+                        Command::Function { name, .. } => {
+                            self.display_result(format!("{name} is a function"));
+                        }
+                        Command::EnviromentalModifier { .. } | Command::Assign { .. } => {}
+                        builtin => {
+                            self.display_result(format!("{builtin} is a shell builtin"));
                         }
                     }
                 }
+                status
+            }
+
+            Command::Pwd(args) => self.pwd_builtin(args),
+
+            Command::Pushd(args) => self.pushd_builtin(args),
+
+            Command::Popd(args) => self.popd_builtin(args),
+
+            Command::Dirs(args) => self.dirs_builtin(args),
+
+            Command::Exit => self.exit(0),
+
+            Command::Source { file, args } => self.source_builtin(file, args),
+
+            Command::Export(args) => self.export_builtin(args),
+
+            Command::History(args) => self.history_builtin(args),
 
-                Command::Pwd => {
-                    self.display_result(format!("{}", self.working_dir.display()));
+            Command::Jobs(args) => self.jobs_builtin(args),
+
+            Command::Fg(args) => self.fg_builtin(args),
+
+            Command::Bg(args) => self.bg_builtin(args),
+
+            Command::Wait(args) => self.wait_builtin(args),
+
+            Command::Disown(args) => self.disown_builtin(args),
+
+            Command::Trap(args) => self.trap_builtin(args),
+
+            Command::Set(args) => self.set_builtin(args),
+
+            Command::Shopt(args) => self.shopt_builtin(args),
+
+            Command::Complete(args) => self.complete_builtin(args),
+
+            Command::Compgen(args) => self.compgen_builtin(args),
+
+            Command::Hash(args) => self.hash_builtin(args),
+
+            Command::Rehash => self.hash_builtin(&["-r".to_string()]),
+
+            Command::Function { name, args } => self.call_function(name, args),
+
+            Command::Assign {
+                assignments,
+                command,
+            } => match command {
+                Some(command) => self.exec_single(command, assignments),
+                None => {
+                    for (name, value) in assignments {
+                        self.set_variable(name, value);
+                    }
+                    0
                 }
+            },
 
-                Command::Exit => {
-                    process::exit(0);
+            Command::None(cmd_name) => {
+                self.display_error(format!("{cmd_name}: command not found"));
+                127
+            }
+
+            Command::EnviromentalModifier {
+                stdout_redirect,
+                stderr_redirect,
+            } => {
+                self.stdout_redirect = stdout_redirect.clone();
+                self.stderr_redirect = stderr_redirect.clone();
+
+                if let Some(stdout) = &self.stdout_redirect {
+                    _ = stdout.options.open(&stdout.file_path);
                 }
 
-                Command::None(cmd_name) => {
-                    self.display_error(format!("{cmd_name}: command not found"));
+                if let Some(stderr) = &self.stderr_redirect {
+                    _ = stderr.options.open(&stderr.file_path);
                 }
+                self.last_status
+            }
+        }
+    }
+
+    /// `source file [args]`: runs `file` in the current shell with `args`
+    /// temporarily replacing the positional parameters.
+    fn source_builtin(&mut self, file: &str, args: &[String]) -> i32 {
+        if file.is_empty() {
+            self.display_error("source: filename argument required".to_string());
+            return 2;
+        }
 
-                Command::EnviromentalModifier {
-                    stdout_redirect,
-                    stderr_redirect,
-                } => {
-                    self.stdout_redirect = stdout_redirect.clone();
-                    self.stderr_redirect = stderr_redirect.clone();
+        let Some(path) = find_source_file(file, self.options.sourcepath) else {
+            self.display_error(format!("source: {file}: No such file or directory"));
+            return 1;
+        };
 
-                    if let Some(stdout) = &self.stdout_redirect {
-                        _ = stdout.options.open(&stdout.file_path);
-                    }
+        let outer_params = if args.is_empty() {
+            None
+        } else {
+            Some(std::mem::replace(
+                &mut self.positional_params,
+                args.to_vec(),
+            ))
+        };
 
-                    if let Some(stderr) = &self.stderr_redirect {
-                        _ = stderr.options.open(&stderr.file_path);
-                    }
-                }
+        self.last_status = 0;
+        let status = match self.source_file(&path) {
+            Ok(()) => self.last_status,
+            Err(err) => {
+                self.display_error(format!("source: {file}: {err}"));
+                1
             }
+        };
+
+        if let Some(params) = outer_params {
+            self.positional_params = params;
         }
+        self.last_status = status;
+        self.run_trap(Trap::Return);
+        status
     }
 
     fn write_output<W: Write>(
         &self,
         text: String,
-        redirect: &Option<RedirectInfo>,
+        redirect: Option<&RedirectInfo>,
         fallback_writer: &mut W,
     ) {
         if let Some(io_stream) = redirect {
-            match io_stream.options.open(&io_stream.file_path) {
-                Ok(mut file_handle) => {
-                    _ = writeln!(file_handle, "{}", text);
-                }
-                Err(_) => {}
+            if let Ok(mut file_handle) = io_stream.options.open(&io_stream.file_path) {
+                _ = writeln!(file_handle, "{}", text);
             }
         } else {
             _ = writeln!(fallback_writer, "{}", text);
         }
     }
 
+    /// The redirection stdout currently goes to, if any.
+    fn stdout_target(&self) -> Option<&RedirectInfo> {
+        self.stdout_redirect.as_ref().or(self.capture.as_ref())
+    }
+
     fn display_result(&self, text: String) {
-        self.write_output(text, &self.stdout_redirect, &mut io::stdout());
+        self.write_output(text, self.stdout_target(), &mut io::stdout());
+    }
+
+    /// Writes `text` to stdout as it is, without adding a newline.
+    fn display_raw(&self, text: &str) {
+        if let Some(io_stream) = self.stdout_target() {
+            if let Ok(mut file_handle) = io_stream.options.open(&io_stream.file_path) {
+                _ = file_handle.write_all(text.as_bytes());
+            }
+        } else {
+            let mut stdout = io::stdout();
+            _ = stdout.write_all(text.as_bytes());
+            _ = stdout.flush();
+        }
     }
 
     fn display_error(&self, text: String) {
-        self.write_output(text, &self.stderr_redirect, &mut io::stderr());
+        let text = match &self.source_location {
+            Some(location) => format!(
+                "{}: line {}: {}",
+                location.file.display(),
+                location.line,
+                text
+            ),
+            None => text,
+        };
+        self.write_output(text, self.stderr_redirect.as_ref(), &mut io::stderr());
     }
+}
+
+/// Whether `cmd` runs inside the shell rather than as a child process.
+fn runs_in_shell(cmd: &Command) -> bool {
+    match cmd {
+        Command::External { .. } | Command::EnviromentalModifier { .. } | Command::None(_) => false,
+        Command::Assign {
+            command: Some(command),
+            ..
+        } => runs_in_shell(command),
+        _ => true,
+    }
+}
 
-    fn change_dir(&mut self, path: PathBuf) {
-        self.working_dir = path;
+/// Resolves the file argument of `source`. Names without a slash are looked
+/// up in `PATH` first, unless `shopt -u sourcepath`, and then in the current
+/// directory, like bash does.
+fn find_source_file(file: &str, search_path: bool) -> Option<PathBuf> {
+    if search_path && !file.contains('/') {
+        let path = env::var_os("PATH").unwrap_or_default();
+        let found = env::split_paths(&path)
+            .map(|dir| dir.join(file))
+            .find(|candidate| candidate.is_file());
+        if found.is_some() {
+            return found;
+        }
     }
+
+    let path = PathBuf::from(file);
+    path.is_file().then_some(path)
 }
@@ -0,0 +1,271 @@
+//! The `complete` and `compgen` builtins.
+
+use std::path::{Path, PathBuf};
+
+use crate::command::BUILTIN_COMMAND_NAMES;
+use crate::command::completer::spec::{CompLine, SharedSpecs, Source, SpecArgs};
+use crate::shell::Shell;
+
+impl Source for Shell {
+    fn cwd(&self) -> &Path {
+        &self.working_dir
+    }
+
+    fn home(&self) -> Option<PathBuf> {
+        self.home_dir()
+    }
+
+    fn commands(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = BUILTIN_COMMAND_NAMES
+            .iter()
+            .filter(|name| !name.contains('>'))
+            .map(|name| name.to_string())
+            .chain(self.commands.borrow_mut().names().iter().cloned())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn variables(&self) -> Vec<String> {
+        self.variable_names()
+    }
+
+    fn call_function(&mut self, function: &str, comp: &CompLine) -> Vec<String> {
+        self.run_completion_function(function, comp)
+    }
+}
+
+/// The variables a completion function is given.
+const COMP_VARIABLES: &[&str] = &["COMP_WORDS", "COMP_CWORD", "COMP_LINE", "COMP_POINT"];
+
+impl Shell {
+    /// The specs shared with the line editor's completer.
+    pub fn completions(&self) -> SharedSpecs {
+        self.completions.clone()
+    }
+
+    /// Runs `function` the way bash runs the function of a `complete -F`
+    /// spec: with the command name, the word being completed and the word
+    /// before it as arguments, and `COMP_WORDS`, `COMP_CWORD`, `COMP_LINE`
+    /// and `COMP_POINT` describing the line. Returns the elements of
+    /// `COMPREPLY` it leaves behind. `$?` is left as it was.
+    pub fn run_completion_function(&mut self, function: &str, comp: &CompLine) -> Vec<String> {
+        if !self.functions.contains_key(function) {
+            return Vec::new();
+        }
+        self.set_array("COMP_WORDS", comp.words.clone());
+        self.variables
+            .insert("COMP_CWORD".to_string(), comp.cword.to_string());
+        self.variables
+            .insert("COMP_LINE".to_string(), comp.line.to_string());
+        self.variables
+            .insert("COMP_POINT".to_string(), comp.point.to_string());
+        self.arrays.remove("COMPREPLY");
+        self.variables.remove("COMPREPLY");
+
+        let saved_status = self.last_status;
+        let args = [comp.command, comp.word, comp.prev].map(str::to_string);
+        self.call_function(function, &args);
+        self.last_status = saved_status;
+
+        for name in COMP_VARIABLES {
+            self.arrays.remove(*name);
+            self.variables.remove(*name);
+        }
+        match self.arrays.remove("COMPREPLY") {
+            Some(reply) => reply,
+            None => self.variables.remove("COMPREPLY").into_iter().collect(),
+        }
+    }
+
+    /// `complete [-pr] [-bcdefv] [-o option] [-A action] [-W wordlist]
+    /// [-C command] [-F function] [-P prefix] [-S suffix] [name ...]`.
+    pub(super) fn complete_builtin(&mut self, args: &[String]) -> i32 {
+        let parsed = match SpecArgs::parse("complete", args) {
+            Ok(parsed) => parsed,
+            Err(msg) => {
+                self.display_error(msg);
+                return 2;
+            }
+        };
+
+        if parsed.remove {
+            if parsed.names.is_empty() {
+                self.completions.clear();
+                return 0;
+            }
+            let mut status = 0;
+            for name in &parsed.names {
+                if !self.completions.remove(name) {
+                    self.display_error(format!("complete: {name}: no completion specification"));
+                    status = 1;
+                }
+            }
+            return status;
+        }
+
+        if parsed.print || parsed.names.is_empty() {
+            return self.print_completions(&parsed.names);
+        }
+        for name in parsed.names {
+            self.completions.insert(name, parsed.spec.clone());
+        }
+        0
+    }
+
+    /// Lists the specs for `names`, or all of them, as `complete` commands.
+    fn print_completions(&self, names: &[String]) -> i32 {
+        if names.is_empty() {
+            for (name, spec) in self.completions.entries() {
+                self.display_result(spec.to_command(&name));
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for name in names {
+            match self.completions.get(name) {
+                Some(spec) => self.display_result(spec.to_command(name)),
+                None => {
+                    self.display_error(format!("complete: {name}: no completion specification"));
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    /// `compgen [-bcdefv] [-o option] [-A action] [-W wordlist] [-C command]
+    /// [-F function] [-P prefix] [-S suffix] [-V array] [word]` prints what a
+    /// spec with these options would complete `word` to, or with `-V` stores
+    /// it in `array`, which is how a completion function fills `COMPREPLY`.
+    pub(super) fn compgen_builtin(&mut self, args: &[String]) -> i32 {
+        let parsed = match SpecArgs::parse("compgen", args) {
+            Ok(parsed) if parsed.print || parsed.remove => {
+                let flag = if parsed.print { 'p' } else { 'r' };
+                self.display_error(format!("compgen: -{flag}: invalid option"));
+                return 2;
+            }
+            Ok(parsed) => parsed,
+            Err(msg) => {
+                self.display_error(msg);
+                return 2;
+            }
+        };
+
+        let word = parsed.names.first().map(String::as_str).unwrap_or_default();
+        let comp = CompLine {
+            word,
+            ..Default::default()
+        };
+        let candidates = parsed.spec.generate(&comp, self);
+        let status = if candidates.is_empty() { 1 } else { 0 };
+        match &parsed.array {
+            Some(array) => self.set_array(array, candidates),
+            None => {
+                for candidate in &candidates {
+                    self.display_result(candidate.clone());
+                }
+            }
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::Lookup;
+    use crate::test_util::{TempDir, args};
+    use std::fs;
+
+    #[test]
+    fn specs_are_registered_and_removed() {
+        let mut shell = Shell::new();
+        assert_eq!(
+            0,
+            shell.complete_builtin(&args(&["-W", "add commit", "git"]))
+        );
+        assert_eq!(0, shell.complete_builtin(&args(&["-d", "pushd"])));
+        assert_eq!(
+            vec!["git", "pushd"],
+            shell
+                .completions()
+                .entries()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(1, shell.complete_builtin(&args(&["-p", "svn"])));
+        assert_eq!(0, shell.complete_builtin(&args(&["-r", "git"])));
+        assert_eq!(1, shell.complete_builtin(&args(&["-r", "git"])));
+        assert!(shell.completions().get("git").is_none());
+        assert_eq!(2, shell.complete_builtin(&args(&["-V", "REPLY", "git"])));
+    }
+
+    #[test]
+    fn functions_complete_through_comp_words_and_compreply() {
+        let mut shell = Shell::new();
+        shell.run_line(
+            "_git() { SEEN=\"$1 $2 $3 ${COMP_WORDS[0]} $COMP_CWORD $COMP_POINT\"; \
+             compgen -W 'checkout cherry-pick commit' -V COMPREPLY -- \"$2\"; }",
+        );
+        assert_eq!(0, shell.complete_builtin(&args(&["-F", "_git", "git"])));
+        let spec = shell.completions().get("git").unwrap();
+        assert_eq!("complete -F _git git", spec.to_command("git"));
+
+        let comp = CompLine {
+            line: "git ch",
+            point: 6,
+            command: "git",
+            word: "ch",
+            prev: "git",
+            words: args(&["git", "ch"]),
+            cword: 1,
+        };
+        shell.last_status = 3;
+        assert_eq!(
+            vec!["checkout", "cherry-pick"],
+            spec.generate(&comp, &mut shell)
+        );
+        assert_eq!(
+            Some("git ch git git 1 6".to_string()),
+            shell.variable("SEEN")
+        );
+        assert_eq!(None, shell.variable("COMP_CWORD"));
+        assert_eq!(3, shell.last_status());
+
+        shell.run_line("_fixed() { COMPREPLY=(one 'two words'); }");
+        assert_eq!(
+            vec!["one", "two words"],
+            shell.run_completion_function("_fixed", &comp)
+        );
+        assert!(shell.run_completion_function("_missing", &comp).is_empty());
+    }
+
+    #[test]
+    fn variables_include_shell_variables() {
+        let mut shell = Shell::new();
+        shell.set_variable("COMPGEN_TEST_VAR", "1");
+        assert!(shell.variables().contains(&"COMPGEN_TEST_VAR".to_string()));
+        assert_eq!(0, shell.compgen_builtin(&args(&["-v", "COMPGEN_TEST"])));
+        assert_eq!(1, shell.compgen_builtin(&args(&["-W", "a b", "c"])));
+    }
+
+    #[test]
+    fn tilde_is_the_shells_home() {
+        let home = TempDir::new("compgen-home");
+        fs::create_dir(home.join("projects")).unwrap();
+        let mut shell = Shell::new();
+        shell.set_variable("HOME", &home.path().display().to_string());
+        assert_eq!(Some(home.path().to_path_buf()), shell.home());
+        assert_eq!(
+            0,
+            shell.compgen_builtin(&args(&["-d", "-V", "DIRS", "~/pro"]))
+        );
+        assert_eq!(Some("~/projects".to_string()), shell.variable("DIRS"));
+    }
+}
@@ -0,0 +1,515 @@
+//! `cd`, `pwd` and the directory stack: `pushd`, `popd` and `dirs`.
+//!
+//! The working directory is kept logically by default: a path through a
+//! symlink stays as it was typed, and `..` goes back up the way it came.
+//! `-P` resolves symlinks instead.
+//!
+//! The stack's top entry is always the working directory, so `+0` names it
+//! and `+1` the directory `pushd` last left.
+
+use std::{
+    env, fs, io, iter, mem,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::command::Lookup;
+use crate::shell::{Shell, prompt::abbreviate_home};
+
+impl Shell {
+    /// `cd [-L|-P] [dir]`. No `dir` means `$HOME`, and `-` means
+    /// `$OLDPWD`. Relative names are looked for in `CDPATH` first.
+    pub(super) fn cd_builtin(&mut self, args: &[String]) -> i32 {
+        let mut physical = false;
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next_if(|arg| arg.starts_with('-') && arg.len() > 1) {
+            if arg == "--" {
+                break;
+            }
+            for flag in arg.chars().skip(1) {
+                match flag {
+                    'L' => physical = false,
+                    'P' => physical = true,
+                    _ => {
+                        self.display_error(format!("cd: -{flag}: invalid option"));
+                        self.display_error("cd: usage: cd [-L|-P] [dir]".to_string());
+                        return 2;
+                    }
+                }
+            }
+        }
+        let operands: Vec<&String> = args.collect();
+        if operands.len() > 1 {
+            self.display_error("cd: too many arguments".to_string());
+            return 1;
+        }
+
+        let mut print = false;
+        let dir = match operands.first() {
+            None => match self.variable("HOME").filter(|home| !home.is_empty()) {
+                Some(home) => home,
+                None => {
+                    self.display_error("cd: HOME not set".to_string());
+                    return 1;
+                }
+            },
+            Some(dir) if dir.as_str() == "-" => {
+                match self.variable("OLDPWD").filter(|old| !old.is_empty()) {
+                    Some(old) => {
+                        print = true;
+                        old
+                    }
+                    None => {
+                        self.display_error("cd: OLDPWD not set".to_string());
+                        return 1;
+                    }
+                }
+            }
+            Some(dir) => dir.to_string(),
+        };
+
+        let (path, from_cdpath) = self.cd_target(&dir);
+        match self.change_dir(&path, physical) {
+            Ok(()) => {
+                if print || from_cdpath {
+                    self.display_result(self.working_dir.display().to_string());
+                }
+                0
+            }
+            Err(err) => {
+                self.display_error(format!("cd: {dir}: {}", describe(&err)));
+                1
+            }
+        }
+    }
+
+    /// Where `cd dir` goes, and whether a `CDPATH` entry other than the
+    /// current directory found it. Names starting with `/`, `.` or `..`
+    /// skip `CDPATH`.
+    fn cd_target(&self, dir: &str) -> (PathBuf, bool) {
+        let searchable = !dir.is_empty()
+            && !matches!(
+                Path::new(dir).components().next(),
+                Some(Component::RootDir | Component::CurDir | Component::ParentDir)
+            );
+        if searchable && let Some(cdpath) = self.variable("CDPATH") {
+            for entry in cdpath.split(':') {
+                let candidate = self.working_dir.join(entry).join(dir);
+                if candidate.is_dir() {
+                    return (candidate, !entry.is_empty());
+                }
+            }
+        }
+        (self.working_dir.join(dir), false)
+    }
+
+    /// Moves to the absolute `path`, updating `PWD` and `OLDPWD`. Unless
+    /// `physical`, `.` and `..` are resolved by name before symlinks are.
+    fn change_dir(&mut self, path: &Path, physical: bool) -> io::Result<()> {
+        let target = if physical {
+            path.to_path_buf()
+        } else {
+            normalize(path)
+        };
+        env::set_current_dir(&target)?;
+        let new = if physical {
+            env::current_dir()?
+        } else {
+            target
+        };
+
+        let old = mem::replace(&mut self.working_dir, new);
+        self.set_variable("OLDPWD", &old.display().to_string());
+        let pwd = self.working_dir.display().to_string();
+        self.set_variable("PWD", &pwd);
+        Ok(())
+    }
+
+    /// `pwd [-LP]`: the logical working directory, or with `-P` the one
+    /// with every symlink resolved.
+    pub(super) fn pwd_builtin(&mut self, args: &[String]) -> i32 {
+        let mut physical = false;
+        for arg in args
+            .iter()
+            .take_while(|arg| arg.starts_with('-') && *arg != "--")
+        {
+            for flag in arg.chars().skip(1) {
+                match flag {
+                    'L' => physical = false,
+                    'P' => physical = true,
+                    _ => {
+                        self.display_error(format!("pwd: -{flag}: invalid option"));
+                        self.display_error("pwd: usage: pwd [-LP]".to_string());
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        let dir = if physical {
+            match fs::canonicalize(&self.working_dir) {
+                Ok(dir) => dir,
+                Err(err) => {
+                    self.display_error(format!("pwd: {}", describe(&err)));
+                    return 1;
+                }
+            }
+        } else {
+            self.working_dir.clone()
+        };
+        self.display_result(dir.display().to_string());
+        0
+    }
+
+    /// `pushd [dir | +N | -N]`. With `dir`, saves the working directory on
+    /// the stack and moves to `dir`; with `+N` or `-N`, rotates the stack so
+    /// that entry is on top; with nothing, swaps the top two entries.
+    pub(super) fn pushd_builtin(&mut self, args: &[String]) -> i32 {
+        let args = operands(args);
+        if args.len() > 1 {
+            self.display_error("pushd: too many arguments".to_string());
+            return 1;
+        }
+
+        match args.first() {
+            None => {
+                if self.dir_stack.is_empty() {
+                    self.display_error("pushd: no other directory".to_string());
+                    return 1;
+                }
+                let mut stack = self.full_stack();
+                stack.swap(0, 1);
+                if !self.replace_stack(stack, "pushd") {
+                    return 1;
+                }
+            }
+            Some(spec) if is_stack_spec(spec) => {
+                let mut stack = self.full_stack();
+                let Some(index) = stack_position(spec, stack.len()) else {
+                    self.display_error(format!(
+                        "pushd: {spec}: directory stack index out of range"
+                    ));
+                    return 1;
+                };
+                stack.rotate_left(index);
+                if !self.replace_stack(stack, "pushd") {
+                    return 1;
+                }
+            }
+            Some(dir) => {
+                let old = self.working_dir.clone();
+                let (path, _) = self.cd_target(dir);
+                if let Err(err) = self.change_dir(&path, false) {
+                    self.display_error(format!("pushd: {dir}: {}", describe(&err)));
+                    return 1;
+                }
+                self.dir_stack.insert(0, old);
+            }
+        }
+        self.print_stack(false, ListStyle::Line);
+        0
+    }
+
+    /// `popd [+N | -N]`. Drops the top entry and moves to the next, or drops
+    /// the given entry.
+    pub(super) fn popd_builtin(&mut self, args: &[String]) -> i32 {
+        let args = operands(args);
+        if args.len() > 1 {
+            self.display_error("popd: too many arguments".to_string());
+            return 1;
+        }
+        if let Some(arg) = args.first()
+            && !is_stack_spec(arg)
+        {
+            self.display_error(format!("popd: {arg}: invalid argument"));
+            self.display_error("popd: usage: popd [+N | -N]".to_string());
+            return 2;
+        }
+        if self.dir_stack.is_empty() {
+            self.display_error("popd: directory stack empty".to_string());
+            return 1;
+        }
+
+        let stack_len = self.dir_stack.len() + 1;
+        let index = match args.first() {
+            None => 0,
+            Some(spec) => match stack_position(spec, stack_len) {
+                Some(index) => index,
+                None => {
+                    self.display_error(format!("popd: {spec}: directory stack index out of range"));
+                    return 1;
+                }
+            },
+        };
+        if index == 0 {
+            let mut stack = self.full_stack();
+            stack.remove(0);
+            if !self.replace_stack(stack, "popd") {
+                return 1;
+            }
+        } else {
+            self.dir_stack.remove(index - 1);
+        }
+        self.print_stack(false, ListStyle::Line);
+        0
+    }
+
+    /// `dirs [-clpv] [+N | -N]`. Lists the stack with `$HOME` shown as `~`,
+    /// or with `-l` in full; `-p` puts each entry on its own line and `-v`
+    /// numbers them. `-c` empties the stack.
+    pub(super) fn dirs_builtin(&mut self, args: &[String]) -> i32 {
+        let mut long = false;
+        let mut style = ListStyle::Line;
+        let mut clear = false;
+        let mut entry = None;
+        for arg in args {
+            if is_stack_spec(arg) {
+                if entry.replace(arg).is_some() {
+                    self.display_error("dirs: too many arguments".to_string());
+                    return 1;
+                }
+                continue;
+            }
+            let flags = arg.strip_prefix('-').filter(|flags| !flags.is_empty());
+            for flag in flags.unwrap_or(arg).chars() {
+                match flag {
+                    'c' if flags.is_some() => clear = true,
+                    'l' if flags.is_some() => long = true,
+                    'p' if flags.is_some() => style = style.max(ListStyle::PerLine),
+                    'v' if flags.is_some() => style = ListStyle::Numbered,
+                    _ => {
+                        match flags {
+                            Some(_) => self.display_error(format!("dirs: -{flag}: invalid option")),
+                            None => self.display_error(format!("dirs: {arg}: invalid argument")),
+                        }
+                        self.display_error("dirs: usage: dirs [-clpv] [+N] [-N]".to_string());
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        if clear {
+            self.dir_stack.clear();
+            return 0;
+        }
+        if let Some(spec) = entry {
+            let stack = self.full_stack();
+            let Some(index) = stack_position(spec, stack.len()) else {
+                self.display_error(format!("dirs: {spec}: directory stack index out of range"));
+                return 1;
+            };
+            let dir = self.show_dir(&stack[index], long);
+            self.display_result(match style {
+                ListStyle::Numbered => format!("{index:2}  {dir}"),
+                _ => dir,
+            });
+            return 0;
+        }
+        self.print_stack(long, style);
+        0
+    }
+
+    /// The directory `~` stands for: `$HOME`, or the user's home when it is
+    /// unset.
+    pub fn home_dir(&self) -> Option<PathBuf> {
+        self.variable("HOME")
+            .map(PathBuf::from)
+            .or_else(env::home_dir)
+    }
+
+    /// The directory `~spec` names, where `spec` is `N`, `+N` or `-N`.
+    pub(super) fn stack_entry(&self, spec: &str) -> Option<PathBuf> {
+        let spec = if spec.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("+{spec}")
+        } else {
+            spec.to_string()
+        };
+        if !is_stack_spec(&spec) {
+            return None;
+        }
+        let mut stack = self.full_stack();
+        let index = stack_position(&spec, stack.len())?;
+        Some(stack.swap_remove(index))
+    }
+
+    /// The whole stack, the working directory first.
+    fn full_stack(&self) -> Vec<PathBuf> {
+        iter::once(self.working_dir.clone())
+            .chain(self.dir_stack.iter().cloned())
+            .collect()
+    }
+
+    /// Moves to the first directory in `stack` and keeps the rest below it.
+    /// On failure the error is reported for `builtin` and nothing changes.
+    fn replace_stack(&mut self, mut stack: Vec<PathBuf>, builtin: &str) -> bool {
+        let top = stack.remove(0);
+        if let Err(err) = self.change_dir(&top, false) {
+            self.display_error(format!("{builtin}: {}: {}", top.display(), describe(&err)));
+            return false;
+        }
+        self.dir_stack = stack;
+        true
+    }
+
+    fn print_stack(&mut self, long: bool, style: ListStyle) {
+        let dirs: Vec<String> = self
+            .full_stack()
+            .iter()
+            .map(|dir| self.show_dir(dir, long))
+            .collect();
+        let text = match style {
+            ListStyle::Line => dirs.join(" "),
+            ListStyle::PerLine => dirs.join("\n"),
+            ListStyle::Numbered => dirs
+                .iter()
+                .enumerate()
+                .map(|(index, dir)| format!("{index:2}  {dir}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        self.display_result(text);
+    }
+
+    fn show_dir(&self, dir: &Path, long: bool) -> String {
+        let dir = dir.display().to_string();
+        if long {
+            dir
+        } else {
+            abbreviate_home(dir, self.variable("HOME").as_deref())
+        }
+    }
+}
+
+/// How `dirs` lays the stack out, from `-p` and `-v`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ListStyle {
+    Line,
+    PerLine,
+    Numbered,
+}
+
+/// `args` without a leading `--`.
+fn operands(args: &[String]) -> &[String] {
+    match args.first() {
+        Some(first) if first == "--" => &args[1..],
+        _ => args,
+    }
+}
+
+/// Whether `arg` is `+N` or `-N` rather than a directory or an option.
+fn is_stack_spec(arg: &str) -> bool {
+    arg.strip_prefix(['+', '-'])
+        .is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The index into a stack of `len` entries that `+N` or `-N` names,
+/// counting from the top or the bottom.
+fn stack_position(spec: &str, len: usize) -> Option<usize> {
+    let n: usize = spec[1..].parse().ok()?;
+    if n >= len {
+        return None;
+    }
+    if spec.starts_with('-') {
+        Some(len - 1 - n)
+    } else {
+        Some(n)
+    }
+}
+
+/// The directory to start in: `$PWD` if it names the current directory,
+/// keeping any symlinks it goes through, or else the physical path.
+pub(super) fn initial_working_dir() -> PathBuf {
+    let physical = env::current_dir().unwrap();
+    let Some(pwd) = env::var_os("PWD").map(PathBuf::from) else {
+        return physical;
+    };
+    let is_clean = pwd.is_absolute()
+        && pwd
+            .components()
+            .all(|component| !matches!(component, Component::CurDir | Component::ParentDir));
+    let same = fs::canonicalize(&pwd).ok() == fs::canonicalize(&physical).ok();
+    if is_clean && same { pwd } else { physical }
+}
+
+/// `path` with `.` and `..` resolved by name alone, the way `cd -L` sees
+/// it.
+pub(super) fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    normal
+}
+
+/// The system's description of `err`, without Rust's error code suffix.
+pub(super) fn describe(err: &io::Error) -> String {
+    let text = err.to_string();
+    match text.split_once(" (os error") {
+        Some((description, _)) => description.to_string(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn paths_are_normalized_by_name() {
+        assert_eq!(PathBuf::from("/a/c"), normalize(Path::new("/a/b/../c/.")));
+        assert_eq!(PathBuf::from("/"), normalize(Path::new("/../..")));
+
+        let missing = fs::read_dir("/surely/not/here").unwrap_err();
+        assert_eq!("No such file or directory", describe(&missing));
+    }
+
+    #[test]
+    fn cdpath_is_searched_for_plain_names() {
+        let dir = TempDir::new("cdpath");
+        let root = dir.path();
+        fs::create_dir_all(root.join("projects/app")).unwrap();
+        fs::create_dir_all(root.join("here/app")).unwrap();
+
+        let mut shell = Shell::new();
+        shell.working_dir = root.join("here");
+        shell.variables.insert(
+            "CDPATH".to_string(),
+            format!(":{}", root.join("projects").display()),
+        );
+        assert_eq!((root.join("here/app"), false), shell.cd_target("app"));
+
+        shell.working_dir = root.to_path_buf();
+        assert_eq!((root.join("projects/app"), true), shell.cd_target("app"));
+        assert_eq!((root.join("./app"), false), shell.cd_target("./app"));
+        assert_eq!((PathBuf::from("/tmp"), false), shell.cd_target("/tmp"));
+    }
+
+    #[test]
+    fn stack_entries_count_from_either_end() {
+        assert_eq!(Some(0), stack_position("+0", 3));
+        assert_eq!(Some(2), stack_position("+2", 3));
+        assert_eq!(Some(2), stack_position("-0", 3));
+        assert_eq!(Some(0), stack_position("-2", 3));
+        assert_eq!(None, stack_position("+3", 3));
+        assert!(!is_stack_spec("-"));
+        assert!(!is_stack_spec("-v"));
+        assert!(!is_stack_spec("12"));
+
+        let mut shell = Shell::new();
+        shell.working_dir = PathBuf::from("/a");
+        shell.dir_stack = vec![PathBuf::from("/b"), PathBuf::from("/c")];
+        assert_eq!(Some(PathBuf::from("/a")), shell.stack_entry("0"));
+        assert_eq!(Some(PathBuf::from("/b")), shell.stack_entry("1"));
+        assert_eq!(Some(PathBuf::from("/c")), shell.stack_entry("-0"));
+        assert_eq!(Some(PathBuf::from("/b")), shell.stack_entry("+1"));
+        assert_eq!(None, shell.stack_entry("3"));
+        assert_eq!(None, shell.stack_entry("1x"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Shell functions, defined with `name() { ...; }` and run like commands,
+//! with their arguments as the positional parameters.
+
+use crate::command::{self, RedirectInfo, RedirectKind};
+use crate::shell::Shell;
+
+impl Shell {
+    /// The names of the defined functions, sorted.
+    pub fn function_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.functions.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Runs the function `name` with `args` as `$1`, `$2` and so on, and
+    /// returns the status of the last command in its body.
+    pub(super) fn call_function(&mut self, name: &str, args: &[String]) -> i32 {
+        let Some(body) = self.functions.get(name).cloned() else {
+            self.display_error(format!("{name}: command not found"));
+            return 127;
+        };
+
+        let params = std::mem::replace(&mut self.positional_params, args.to_vec());
+        // Every line of the body starts with no redirections of its own, so
+        // output redirected for the call goes through the capture instead.
+        // The redirection has already truncated the file.
+        let capture = self.capture.clone();
+        let stdout_redirect = self.stdout_redirect.take();
+        if let Some(redirect) = &stdout_redirect {
+            self.capture = Some(RedirectInfo::new(
+                redirect.file_path.clone(),
+                RedirectKind::Append,
+            ));
+        }
+        let stderr_redirect = self.stderr_redirect.take();
+        let current_line = std::mem::take(&mut self.current_line);
+        let background_job = self.background_job.take();
+
+        self.last_status = 0;
+        for line in command::split_commands(&body) {
+            self.run_line(&line);
+        }
+        let status = self.last_status;
+
+        self.positional_params = params;
+        self.capture = capture;
+        self.stdout_redirect = stdout_redirect;
+        self.stderr_redirect = stderr_redirect;
+        self.current_line = current_line;
+        self.background_job = background_job;
+        status
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::Lookup;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn functions_run_with_their_arguments() {
+        let mut shell = Shell::new();
+        shell.run_line("greet() { GREETING=\"hi $1\"; WHO=$2; }");
+        assert_eq!(vec!["greet"], shell.function_names());
+
+        shell.positional_params = vec!["outer".to_string()];
+        shell.run_line("greet you me");
+        assert_eq!(Some("hi you".to_string()), shell.variable("GREETING"));
+        assert_eq!(Some("me".to_string()), shell.variable("WHO"));
+        assert_eq!(vec!["outer"], shell.positional_params);
+        assert_eq!(0, shell.last_status());
+    }
+
+    #[test]
+    fn function_bodies_can_span_lines() {
+        let mut shell = Shell::new();
+        shell.run_line("function set_two {\n  A=1\n  # a comment\n  B=2\n}");
+        shell.run_line("set_two");
+        assert_eq!(Some("1".to_string()), shell.variable("A"));
+        assert_eq!(Some("2".to_string()), shell.variable("B"));
+
+        shell.run_line("fails() { no-such-command-here; }");
+        shell.run_line("fails");
+        assert_eq!(127, shell.last_status());
+    }
+
+    #[test]
+    fn sourced_definitions_continue_on_the_next_lines() {
+        let dir = TempDir::new("functions");
+        let path = dir.join("greet.sh");
+        std::fs::write(&path, "greet() {\n  GREETING=\"hi\n$1\"\n}\ngreet you\n").unwrap();
+        let mut shell = Shell::new();
+        shell.source_file(&path).unwrap();
+
+        assert_eq!(vec!["greet"], shell.function_names());
+        assert_eq!(Some("hi\nyou".to_string()), shell.variable("GREETING"));
+    }
+}
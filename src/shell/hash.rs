@@ -0,0 +1,278 @@
+//! Remembered command locations and the `hash` builtin.
+//!
+//! Executables found on `PATH` are remembered, with a count of how often
+//! each has been run, until `PATH` changes or `hash -r` forgets them. The
+//! same cache lists every command name on `PATH` for the completer, so
+//! running and completing always agree.
+
+use std::{
+    cell::{RefCell, RefMut},
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    path::PathBuf,
+    rc::Rc,
+};
+
+use crate::command::{self, BUILTIN_COMMAND_NAMES, Command};
+use crate::shell::Shell;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Hashed {
+    path: PathBuf,
+    hits: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct CommandCache {
+    /// The `PATH` everything below was found on.
+    path: OsString,
+    hashed: BTreeMap<String, Hashed>,
+    /// Every executable name on `PATH`, sorted, listed on first use.
+    names: Option<Vec<String>>,
+    /// Bumped whenever `names` is dropped, so the completer knows to
+    /// rebuild its index.
+    generation: u64,
+}
+
+impl CommandCache {
+    /// Forgets everything if `PATH` has changed since it was filled.
+    fn check_path(&mut self) {
+        let path = env::var_os("PATH").unwrap_or_default();
+        if path != self.path {
+            self.path = path;
+            self.clear();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.hashed.clear();
+        self.names = None;
+        self.generation += 1;
+    }
+
+    /// Where to run `name` from, counting the lookup as a hit. A remembered
+    /// path that is no longer executable is searched for again.
+    pub fn find(&mut self, name: &str) -> Option<PathBuf> {
+        self.check_path();
+        if let Some(hashed) = self.hashed.get_mut(name)
+            && Command::is_executable(&hashed.path)
+        {
+            hashed.hits += 1;
+            return Some(hashed.path.clone());
+        }
+        let path = Command::get_executable_path(name)?;
+        self.remember(name, path.clone(), 1);
+        Some(path)
+    }
+
+    fn remember(&mut self, name: &str, path: PathBuf, hits: u32) {
+        self.hashed.insert(name.to_string(), Hashed { path, hits });
+    }
+
+    /// Every executable name on `PATH`, sorted.
+    pub fn names(&mut self) -> &[String] {
+        self.check_path();
+        let path = &self.path;
+        self.names.get_or_insert_with(|| {
+            let mut names: Vec<String> = command::get_external_commands(path.clone())
+                .into_keys()
+                .filter_map(|name| name.into_string().ok())
+                .collect();
+            names.sort();
+            names
+        })
+    }
+
+    /// Changes each time the names on `PATH` may have changed.
+    pub fn generation(&mut self) -> u64 {
+        self.check_path();
+        self.generation
+    }
+}
+
+/// A handle to the command cache that the completer shares with the shell.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCommandCache(Rc<RefCell<CommandCache>>);
+
+impl SharedCommandCache {
+    pub fn borrow_mut(&self) -> RefMut<'_, CommandCache> {
+        self.0.borrow_mut()
+    }
+}
+
+impl Shell {
+    /// The command cache shared with the completer.
+    pub fn command_cache(&self) -> SharedCommandCache {
+        self.commands.clone()
+    }
+
+    /// `hash [-lrt] [-p path] [-d] [name ...]`. Without names, lists the
+    /// remembered commands and their hit counts.
+    pub(super) fn hash_builtin(&mut self, args: &[String]) -> i32 {
+        let mut list = false;
+        let mut cleared = false;
+        let mut print_paths = false;
+        let mut forget = false;
+        let mut path = None;
+        let mut names = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-r" => {
+                    self.commands.borrow_mut().clear();
+                    cleared = true;
+                }
+                "-l" => list = true,
+                "-t" => print_paths = true,
+                "-d" => forget = true,
+                "-p" => match args.next() {
+                    Some(value) => path = Some(PathBuf::from(value)),
+                    None => {
+                        self.display_error("hash: -p: option requires an argument".to_string());
+                        return 2;
+                    }
+                },
+                "--" => names.extend(args.by_ref().cloned()),
+                option if option.starts_with('-') && option.len() > 1 => {
+                    self.display_error(format!("hash: {option}: invalid option"));
+                    self.display_error(
+                        "hash: usage: hash [-lr] [-p pathname] [-dt] [name ...]".to_string(),
+                    );
+                    return 2;
+                }
+                name => names.push(name.to_string()),
+            }
+        }
+
+        if names.is_empty() {
+            if print_paths || forget || path.is_some() {
+                self.display_error(
+                    "hash: usage: hash [-lr] [-p pathname] [-dt] [name ...]".to_string(),
+                );
+                return 2;
+            }
+            if !cleared {
+                self.print_hashed(list);
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for name in &names {
+            let found = {
+                let mut cache = self.commands.borrow_mut();
+                cache.check_path();
+                if let Some(path) = &path {
+                    cache.remember(name, path.clone(), 0);
+                    true
+                } else if forget {
+                    cache.hashed.remove(name).is_some()
+                } else if print_paths {
+                    match cache.hashed.get(name) {
+                        Some(hashed) if names.len() > 1 => {
+                            self.display_result(format!("{name}\t{}", hashed.path.display()));
+                            true
+                        }
+                        Some(hashed) => {
+                            self.display_result(hashed.path.display().to_string());
+                            true
+                        }
+                        None => false,
+                    }
+                } else if BUILTIN_COMMAND_NAMES.contains(&name.as_str()) {
+                    true
+                } else {
+                    match Command::get_executable_path(name) {
+                        Some(found) => {
+                            cache.remember(name, found, 0);
+                            true
+                        }
+                        None => false,
+                    }
+                }
+            };
+            if !found {
+                self.display_error(format!("hash: {name}: not found"));
+                status = 1;
+            }
+        }
+        status
+    }
+
+    /// `hash` lists hit counts and paths; `hash -l` the commands that
+    /// would remember them again.
+    fn print_hashed(&self, as_commands: bool) {
+        let hashed: Vec<(String, Hashed)> = {
+            let mut cache = self.commands.borrow_mut();
+            cache.check_path();
+            cache
+                .hashed
+                .iter()
+                .map(|(name, hashed)| (name.clone(), hashed.clone()))
+                .collect()
+        };
+        if hashed.is_empty() {
+            self.display_result("hash: hash table empty".to_string());
+            return;
+        }
+
+        if !as_commands {
+            self.display_result("hits\tcommand".to_string());
+        }
+        for (name, Hashed { path, hits }) in hashed {
+            if as_commands {
+                self.display_result(format!("builtin hash -p {} {name}", path.display()));
+            } else {
+                self.display_result(format!("{hits:>4}\t{}", path.display()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{TempDir, args};
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn lookups_are_remembered_and_counted() {
+        let mut cache = CommandCache::default();
+        let path = cache.find("sh").expect("sh should be on PATH");
+        assert_eq!(Some(path.clone()), cache.find("sh"));
+        assert_eq!(Some(&Hashed { path, hits: 2 }), cache.hashed.get("sh"));
+        assert!(cache.names().is_sorted());
+
+        let generation = cache.generation();
+        cache.clear();
+        assert!(cache.hashed.is_empty());
+        assert_ne!(generation, cache.generation());
+    }
+
+    #[test]
+    fn hash_builtin_manages_the_table() {
+        let mut shell = Shell::new();
+        assert_eq!(0, shell.hash_builtin(&args(&["-p", "/bin/true", "yes"])));
+        assert_eq!(0, shell.hash_builtin(&args(&["-t", "yes"])));
+        assert_eq!(1, shell.hash_builtin(&args(&["surely-not-a-command"])));
+        assert_eq!(0, shell.hash_builtin(&args(&["-d", "yes"])));
+        assert_eq!(1, shell.hash_builtin(&args(&["-t", "yes"])));
+        assert_eq!(2, shell.hash_builtin(&args(&["-x"])));
+    }
+
+    #[test]
+    fn hashed_path_is_what_runs() {
+        let dir = TempDir::new("hashed");
+        let tool = dir.join("not-on-path");
+        fs::write(&tool, "#!/bin/sh\nexit 7\n").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut shell = Shell::new();
+        let tool = tool.display().to_string();
+        assert_eq!(0, shell.hash_builtin(&args(&["-p", &tool, "hashed-tool"])));
+        shell.run_line("hashed-tool");
+        assert_eq!(7, shell.last_status);
+    }
+}
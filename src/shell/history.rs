@@ -0,0 +1,1120 @@
+mod expand;
+mod log;
+pub mod picker;
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, SystemTime},
+};
+
+use rustyline::history::{History, SearchDirection, SearchResult};
+
+use crate::command::Lookup;
+use crate::shell::Shell;
+use crate::shell::dirs::normalize;
+use crate::shell::prompt::format_time;
+
+const DEFAULT_HISTFILE: &str = ".shell_history";
+const DEFAULT_HISTLOG: &str = ".shell_history.jsonl";
+const DEFAULT_HISTSIZE: usize = 500;
+
+/// A line in the history and what is known about running it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub line: String,
+    /// When the line was entered; unknown for lines read from a history
+    /// file without timestamps.
+    pub time: Option<SystemTime>,
+    /// How long it ran for, once it has finished.
+    pub duration: Option<Duration>,
+    /// The working directory it was entered in.
+    pub dir: Option<PathBuf>,
+    /// How it exited, once it has finished.
+    pub status: Option<i32>,
+    /// The shell session that ran it.
+    pub session: Option<String>,
+}
+
+impl Entry {
+    fn new(line: String) -> Entry {
+        Entry {
+            line,
+            time: None,
+            duration: None,
+            dir: None,
+            status: None,
+            session: None,
+        }
+    }
+}
+
+/// In-memory command history plus the settings that control it.
+#[derive(Debug)]
+pub struct HistoryStore {
+    entries: VecDeque<Entry>,
+    max_len: usize,
+    file_max_len: usize,
+    ignore_dups: bool,
+    ignore_space: bool,
+    erase_dups: bool,
+    file: Option<PathBuf>,
+    /// How many of the newest entries haven't been written to the history
+    /// file yet.
+    unwritten: usize,
+    /// Whether writing to the history file last failed, so the error isn't
+    /// repeated after every command.
+    file_failed: bool,
+    /// Where finished entries are logged with their details, from
+    /// `HISTLOG`.
+    log: Option<PathBuf>,
+    /// Identifies this shell's entries in the log.
+    session: String,
+    /// Whether the newest entry is still running and waiting for its exit
+    /// status.
+    running: bool,
+}
+
+impl Default for HistoryStore {
+    fn default() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_len: DEFAULT_HISTSIZE,
+            file_max_len: DEFAULT_HISTSIZE,
+            ignore_dups: false,
+            ignore_space: false,
+            erase_dups: false,
+            file: None,
+            unwritten: 0,
+            file_failed: false,
+            log: None,
+            session: log::session_id(),
+            running: false,
+        }
+    }
+}
+
+impl HistoryStore {
+    /// Adds `line` unless `HISTCONTROL` says to skip it. Returns whether the
+    /// line was stored.
+    pub fn add(&mut self, line: &str) -> bool {
+        if self.ignore(line) {
+            return false;
+        }
+        if self.erase_dups {
+            let written = self.entries.len() - self.unwritten;
+            let mut idx = 0;
+            self.entries.retain(|entry| {
+                let keep = entry.line != line;
+                if !keep && idx >= written {
+                    self.unwritten -= 1;
+                }
+                idx += 1;
+                keep
+            });
+        }
+        self.insert(line.to_string());
+        self.running = false;
+        true
+    }
+
+    /// Notes that the newest entry was just entered in `dir` and is about
+    /// to run.
+    fn record_start(&mut self, dir: &Path) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.time = Some(SystemTime::now());
+            entry.dir = Some(dir.to_path_buf());
+            entry.session = Some(self.session.clone());
+            self.running = true;
+        }
+    }
+
+    /// Notes how the newest entry exited, if it was the one running, and
+    /// returns it.
+    fn record_status(&mut self, status: i32) -> Option<&Entry> {
+        if !std::mem::take(&mut self.running) {
+            return None;
+        }
+        let entry = self.entries.back_mut()?;
+        entry.status = Some(status);
+        entry.duration = entry.time.and_then(|time| time.elapsed().ok());
+        Some(entry)
+    }
+
+    /// Fills in the details of entries read from the plain history file
+    /// from `logged`, the log's entries. The newest occurrences of a line
+    /// are matched up first.
+    fn annotate(&mut self, logged: Vec<Entry>) {
+        let mut by_line: HashMap<String, Vec<Entry>> = HashMap::new();
+        for entry in logged {
+            by_line.entry(entry.line.clone()).or_default().push(entry);
+        }
+        for entry in self.entries.iter_mut().rev() {
+            if entry.session.is_none()
+                && let Some(found) = by_line.get_mut(&entry.line).and_then(Vec::pop)
+            {
+                *entry = found;
+            }
+        }
+    }
+
+    /// The entries, oldest first.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    /// The newest single-line entry that extends `prefix`, preferring one
+    /// entered in `dir`.
+    pub fn suggest(&self, prefix: &str, dir: &Path) -> Option<&str> {
+        let mut candidates = self.entries.iter().rev().filter(|entry| {
+            entry.line.len() > prefix.len()
+                && entry.line.starts_with(prefix)
+                && !entry.line.contains('\n')
+        });
+        let newest = candidates.next()?;
+        let in_dir = |entry: &Entry| entry.dir.as_deref() == Some(dir);
+        if in_dir(newest) {
+            return Some(&newest.line);
+        }
+        let found = candidates.find(|entry| in_dir(entry)).unwrap_or(newest);
+        Some(&found.line)
+    }
+
+    fn ignore(&self, line: &str) -> bool {
+        if self.max_len == 0 || line.trim().is_empty() {
+            return true;
+        }
+        if self.ignore_space && line.starts_with(char::is_whitespace) {
+            return true;
+        }
+        self.ignore_dups && self.entries.back().is_some_and(|last| last.line == line)
+    }
+
+    fn insert(&mut self, line: String) {
+        self.entries.push_back(Entry::new(line));
+        self.unwritten += 1;
+        self.truncate();
+    }
+
+    fn truncate(&mut self) {
+        while self.entries.len() > self.max_len {
+            self.entries.pop_front();
+        }
+        self.unwritten = self.unwritten.min(self.entries.len());
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.unwritten = 0;
+    }
+
+    /// Removes the entry at `idx`.
+    fn remove(&mut self, idx: usize) {
+        if idx >= self.entries.len() - self.unwritten {
+            self.unwritten -= 1;
+        }
+        self.entries.remove(idx);
+    }
+
+    /// Applies a `HISTCONTROL` value: a colon-separated list of
+    /// `ignorespace`, `ignoredups`, `ignoreboth` and `erasedups`.
+    fn set_control(&mut self, control: &str) {
+        self.ignore_dups = false;
+        self.ignore_space = false;
+        self.erase_dups = false;
+        for item in control.split(':') {
+            match item {
+                "ignorespace" => self.ignore_space = true,
+                "ignoredups" => self.ignore_dups = true,
+                "ignoreboth" => {
+                    self.ignore_space = true;
+                    self.ignore_dups = true;
+                }
+                "erasedups" => self.erase_dups = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// Appends the entries not yet written to the history file at `path`.
+    /// The file is opened in append mode and locked for the write, so
+    /// several shells can share one file.
+    fn append_unwritten(&mut self, path: &Path) -> io::Result<()> {
+        if self.unwritten == 0 {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.lock()?;
+        let skip = self.entries.len() - self.unwritten;
+        for entry in self.entries.iter().skip(skip) {
+            write_record(&mut file, entry.time, &entry.line)?;
+        }
+        self.unwritten = 0;
+        Ok(())
+    }
+
+    /// Reads the history file into memory.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        file.lock_shared()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        for (time, line) in read_records(&contents) {
+            if !line.is_empty() {
+                self.insert(line);
+                if let Some(entry) = self.entries.back_mut() {
+                    entry.time = time;
+                }
+            }
+        }
+        self.unwritten = 0;
+        Ok(())
+    }
+
+    /// Overwrites the history file with the in-memory entries.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        // Truncate only once the lock is held so a concurrent append can't
+        // land in between.
+        file.lock()?;
+        file.set_len(0)?;
+        let skip = self.entries.len().saturating_sub(self.file_max_len);
+        for entry in self.entries.iter().skip(skip) {
+            write_record(&mut file, entry.time, &entry.line)?;
+        }
+        self.unwritten = 0;
+        Ok(())
+    }
+
+    /// Cuts the history file down to its newest `HISTFILESIZE` entries.
+    fn trim_file(&self) -> io::Result<()> {
+        match &self.file {
+            Some(path) => trim_records(path, self.file_max_len),
+            None => Ok(()),
+        }
+    }
+
+    /// Cuts the log down to its newest `HISTFILESIZE` records.
+    fn trim_log(&self) -> io::Result<()> {
+        match &self.log {
+            Some(path) => trim_lines(path, self.file_max_len),
+            None => Ok(()),
+        }
+    }
+
+    fn search_match<F>(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+        test: F,
+    ) -> Option<SearchResult<'_>>
+    where
+        F: Fn(&str) -> Option<usize>,
+    {
+        if term.is_empty() || start >= self.entries.len() {
+            return None;
+        }
+        let found = match dir {
+            SearchDirection::Reverse => (0..=start)
+                .rev()
+                .find_map(|idx| test(&self.entries[idx].line).map(|pos| (idx, pos))),
+            SearchDirection::Forward => (start..self.entries.len())
+                .find_map(|idx| test(&self.entries[idx].line).map(|pos| (idx, pos))),
+        };
+        found.map(|(idx, pos)| SearchResult {
+            entry: Cow::Owned(self.entries[idx].line.clone()),
+            idx,
+            pos,
+        })
+    }
+}
+
+/// Cuts the file at `path` down to its last `max_len` lines, under a lock
+/// so other shells don't append in the middle. A missing file is left
+/// alone.
+/// Writes an entry to the history file after a `#<seconds>` timestamp
+/// line, as bash does when `HISTTIMEFORMAT` is set. The timestamp marks
+/// where the entry starts, so one that spans several lines reads back
+/// whole. Entries of unknown time are stamped with the current one.
+fn write_record(file: &mut impl Write, time: Option<SystemTime>, line: &str) -> io::Result<()> {
+    let secs = time
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    writeln!(file, "#{secs}\n{line}")
+}
+
+/// Splits the contents of a history file into its entries and their
+/// times. A timestamp line starts an entry that takes in every line up to
+/// the next timestamp; lines before the first one are an entry each.
+fn read_records(contents: &str) -> Vec<(Option<SystemTime>, String)> {
+    let mut records: Vec<(Option<SystemTime>, Vec<&str>)> = Vec::new();
+    let mut in_record = false;
+    for line in contents.lines() {
+        if let Some(secs) = line.strip_prefix('#').and_then(|secs| secs.parse().ok()) {
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            records.push((Some(time), Vec::new()));
+            in_record = true;
+            continue;
+        }
+        match records.last_mut() {
+            Some((_, lines)) if in_record => lines.push(line),
+            _ => records.push((None, vec![line])),
+        }
+    }
+    records
+        .into_iter()
+        .map(|(time, lines)| (time, lines.join("\n")))
+        .collect()
+}
+
+/// Cuts the history file at `path` down to its newest `max_len` entries.
+fn trim_records(path: &Path, max_len: usize) -> io::Result<()> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    file.lock()?;
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let records = read_records(&contents);
+    if records.len() <= max_len {
+        return Ok(());
+    }
+
+    file.set_len(0)?;
+    file.rewind()?;
+    for (time, line) in &records[records.len() - max_len..] {
+        match time {
+            Some(_) => write_record(&mut file, *time, line)?,
+            None => writeln!(file, "{line}")?,
+        }
+    }
+    Ok(())
+}
+
+fn trim_lines(path: &Path, max_len: usize) -> io::Result<()> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    file.lock()?;
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    if lines.len() <= max_len {
+        return Ok(());
+    }
+
+    file.set_len(0)?;
+    file.rewind()?;
+    for line in &lines[lines.len() - max_len..] {
+        writeln!(file, "{line}")?;
+    }
+    Ok(())
+}
+
+/// A handle to the shell's history that can be given to the line editor
+/// while the shell keeps using it. It is `Send` so that key handlers, which
+/// the line editor requires to be, can hold one too.
+#[derive(Debug, Clone, Default)]
+pub struct SharedHistory(Arc<RwLock<HistoryStore>>);
+
+impl SharedHistory {
+    pub fn borrow(&self) -> RwLockReadGuard<'_, HistoryStore> {
+        self.0.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, HistoryStore> {
+        self.0.write().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl History for SharedHistory {
+    fn get(&self, index: usize, _: SearchDirection) -> rustyline::Result<Option<SearchResult<'_>>> {
+        Ok(self.borrow().entries.get(index).map(|entry| SearchResult {
+            entry: Cow::Owned(entry.line.clone()),
+            idx: index,
+            pos: 0,
+        }))
+    }
+
+    fn add(&mut self, line: &str) -> rustyline::Result<bool> {
+        Ok(self.borrow_mut().add(line))
+    }
+
+    fn add_owned(&mut self, line: String) -> rustyline::Result<bool> {
+        Ok(self.borrow_mut().add(&line))
+    }
+
+    fn len(&self) -> usize {
+        self.borrow().entries.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.borrow().entries.is_empty()
+    }
+
+    fn set_max_len(&mut self, len: usize) -> rustyline::Result<()> {
+        let mut store = self.borrow_mut();
+        store.max_len = len;
+        store.truncate();
+        Ok(())
+    }
+
+    fn ignore_dups(&mut self, yes: bool) -> rustyline::Result<()> {
+        self.borrow_mut().ignore_dups = yes;
+        Ok(())
+    }
+
+    fn ignore_space(&mut self, yes: bool) {
+        self.borrow_mut().ignore_space = yes;
+    }
+
+    fn save(&mut self, path: &Path) -> rustyline::Result<()> {
+        Ok(self.borrow_mut().save(path)?)
+    }
+
+    fn append(&mut self, path: &Path) -> rustyline::Result<()> {
+        Ok(self.borrow_mut().append_unwritten(path)?)
+    }
+
+    fn load(&mut self, path: &Path) -> rustyline::Result<()> {
+        Ok(self.borrow_mut().load(path)?)
+    }
+
+    fn clear(&mut self) -> rustyline::Result<()> {
+        self.borrow_mut().clear();
+        Ok(())
+    }
+
+    fn search(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        let store = self.borrow();
+        let found = store.search_match(term, start, dir, |entry| entry.find(term));
+        Ok(found.map(|result| SearchResult {
+            entry: Cow::Owned(result.entry.into_owned()),
+            ..result
+        }))
+    }
+
+    fn starts_with(
+        &self,
+        term: &str,
+        start: usize,
+        dir: SearchDirection,
+    ) -> rustyline::Result<Option<SearchResult<'_>>> {
+        let store = self.borrow();
+        let found = store.search_match(term, start, dir, |entry| {
+            entry.starts_with(term).then_some(term.len())
+        });
+        Ok(found.map(|result| SearchResult {
+            entry: Cow::Owned(result.entry.into_owned()),
+            ..result
+        }))
+    }
+}
+
+impl Shell {
+    /// The history shared with the line editor.
+    pub fn history(&self) -> SharedHistory {
+        self.history.clone()
+    }
+
+    /// Sets the defaults for the history variables that aren't already set.
+    pub(super) fn init_history_variables(&mut self) {
+        if self.variable("HISTFILE").is_none()
+            && let Some(home) = std::env::home_dir()
+        {
+            let path = home.join(DEFAULT_HISTFILE);
+            self.variables
+                .insert("HISTFILE".to_string(), path.display().to_string());
+        }
+        if self.variable("HISTLOG").is_none()
+            && let Some(home) = std::env::home_dir()
+        {
+            let path = home.join(DEFAULT_HISTLOG);
+            self.variables
+                .insert("HISTLOG".to_string(), path.display().to_string());
+        }
+        if self.variable("HISTSIZE").is_none() {
+            self.variables
+                .insert("HISTSIZE".to_string(), DEFAULT_HISTSIZE.to_string());
+        }
+    }
+
+    /// Applies `HISTFILE`, `HISTLOG`, `HISTSIZE`, `HISTFILESIZE` and
+    /// `HISTCONTROL`.
+    pub(super) fn configure_history(&mut self) {
+        let max_len = history_size(self.variable("HISTSIZE"));
+        let file_max_len = match self.variable("HISTFILESIZE") {
+            Some(value) => history_size(Some(value)),
+            None => max_len,
+        };
+        let file = self
+            .variable("HISTFILE")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let log = self
+            .variable("HISTLOG")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let control = self.variable("HISTCONTROL").unwrap_or_default();
+
+        let mut store = self.history.borrow_mut();
+        store.max_len = max_len;
+        store.file_max_len = file_max_len;
+        if store.file != file {
+            store.file_failed = false;
+        }
+        store.file = file;
+        store.log = log;
+        store.set_control(&control);
+        store.truncate();
+    }
+
+    /// Trims the history file and the log to `HISTFILESIZE` and loads them,
+    /// with the details the log has for each line.
+    pub fn load_history(&mut self) {
+        self.configure_history();
+        let result = {
+            let mut store = self.history.borrow_mut();
+            let loaded = match store.file.clone() {
+                Some(path) if path.is_file() => store.trim_file().and_then(|_| store.load(&path)),
+                _ => Ok(()),
+            };
+            let logged = match store.log.clone() {
+                Some(path) if path.is_file() => store
+                    .trim_log()
+                    .and_then(|_| log::read(&path))
+                    .map(|logged| store.annotate(logged)),
+                _ => Ok(()),
+            };
+            loaded.and(logged)
+        };
+        if let Err(err) = result {
+            self.display_error(format!("history: {err}"));
+        }
+    }
+
+    /// Records a line the user entered, appending it to the history file
+    /// straight away so other open shells can see it. A file that can't be
+    /// written is reported once, not after every command. Lines a script
+    /// feeds to a non-interactive shell aren't recorded.
+    pub fn add_history(&mut self, line: &str) {
+        if !self.interactive {
+            return;
+        }
+        let error = {
+            let mut store = self.history.borrow_mut();
+            if !store.add(line) {
+                return;
+            }
+            store.record_start(&self.working_dir);
+            let Some(path) = store.file.clone() else {
+                return;
+            };
+            match store.append_unwritten(&path) {
+                Ok(()) => {
+                    store.file_failed = false;
+                    None
+                }
+                Err(err) if !std::mem::replace(&mut store.file_failed, true) => {
+                    Some(format!("history: {}: {err}", path.display()))
+                }
+                Err(_) => None,
+            }
+        };
+        if let Some(error) = error {
+            self.display_error(error);
+        }
+    }
+
+    /// Records the exit status of the line last added to the history, now
+    /// that it has run, and logs it.
+    pub fn finish_history(&mut self) {
+        let result = {
+            let mut store = self.history.borrow_mut();
+            let log = store.log.clone();
+            match (store.record_status(self.last_status), log) {
+                (Some(entry), Some(path)) => log::append(&path, entry),
+                _ => Ok(()),
+            }
+        };
+        if let Err(err) = result {
+            self.display_error(format!("history: {err}"));
+        }
+    }
+}
+
+impl Shell {
+    /// Applies `!` history expansion to a line read from the terminal,
+    /// echoing the result to stderr when something was expanded. Lines a
+    /// non-interactive shell reads are left alone.
+    ///
+    /// Returns `None` when expansion failed; the error has been reported and
+    /// the line should not run.
+    pub fn expand_history(&mut self, line: &str) -> Option<String> {
+        if !self.interactive {
+            return Some(line.to_string());
+        }
+        let entries: Vec<String> = self
+            .history
+            .borrow()
+            .entries
+            .iter()
+            .map(|entry| entry.line.clone())
+            .collect();
+        match expand::expand(line, &entries) {
+            Ok(Some(expanded)) => {
+                eprintln!("{expanded}");
+                Some(expanded)
+            }
+            Ok(None) => Some(line.to_string()),
+            Err(msg) => {
+                self.display_error(msg);
+                self.last_status = 1;
+                None
+            }
+        }
+    }
+
+    /// `history [-c] [-d offset] [n]` and `history -r|-w [file]`. Listing
+    /// can be narrowed with `--cwd dir`, `--failed` and `--session`.
+    pub(super) fn history_builtin(&mut self, args: &[String]) -> i32 {
+        let mut filter = Filter::default();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next_if(|arg| arg.starts_with("--") && arg.len() > 2) {
+            match arg.as_str() {
+                "--cwd" => {
+                    let Some(dir) = args.next() else {
+                        self.display_error(
+                            "history: --cwd: option requires an argument".to_string(),
+                        );
+                        return 2;
+                    };
+                    // entries hold the logical directory, as cd leaves it
+                    filter.dir = Some(normalize(&self.working_dir.join(dir)));
+                }
+                "--failed" => filter.failed = true,
+                "--session" => filter.session = Some(self.history.borrow().session.clone()),
+                _ => {
+                    self.display_error(format!("history: {arg}: invalid option"));
+                    return 2;
+                }
+            }
+        }
+
+        let Some(arg) = args.next() else {
+            self.print_history(None, &filter);
+            return 0;
+        };
+
+        match arg.as_str() {
+            "-c" => {
+                self.history.borrow_mut().clear();
+                0
+            }
+            "-d" => match args.next().map(|offset| offset.parse::<i64>()) {
+                Some(Ok(offset)) => self.delete_history_entry(offset),
+                Some(Err(_)) | None => {
+                    self.display_error("history: -d: option requires a valid offset".to_string());
+                    2
+                }
+            },
+            "-r" | "-w" => {
+                let file = args
+                    .next()
+                    .map(PathBuf::from)
+                    .or_else(|| self.history.borrow().file.clone());
+                let Some(file) = file else {
+                    self.display_error("history: no history file".to_string());
+                    return 1;
+                };
+                let result = if arg == "-r" {
+                    self.history.borrow_mut().load(&file)
+                } else {
+                    self.history.borrow_mut().save(&file)
+                };
+                match result {
+                    Ok(()) => 0,
+                    Err(err) => {
+                        self.display_error(format!("history: {}: {err}", file.display()));
+                        1
+                    }
+                }
+            }
+            count => match count.parse::<usize>() {
+                Ok(count) => {
+                    self.print_history(Some(count), &filter);
+                    0
+                }
+                Err(_) if count.starts_with('-') => {
+                    self.display_error(format!("history: {count}: invalid option"));
+                    2
+                }
+                Err(_) => {
+                    self.display_error(format!("history: {count}: numeric argument required"));
+                    1
+                }
+            },
+        }
+    }
+
+    /// Lists the last `count` entries `filter` lets through, or all of
+    /// them, with their times in the format `HISTTIMEFORMAT` gives.
+    fn print_history(&self, count: Option<usize>, filter: &Filter) {
+        let time_format = self
+            .variable("HISTTIMEFORMAT")
+            .filter(|format| !format.is_empty());
+        let lines: Vec<String> = {
+            let store = self.history.borrow();
+            let shown: Vec<(usize, &Entry)> = store
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| filter.matches(entry))
+                .collect();
+            let skip = count.map_or(0, |count| shown.len().saturating_sub(count));
+            shown
+                .into_iter()
+                .skip(skip)
+                .map(|(idx, entry)| {
+                    let time = match (&time_format, entry.time) {
+                        (Some(format), Some(time)) => format_time(format, time),
+                        (Some(_), None) => "?? ".to_string(),
+                        (None, _) => String::new(),
+                    };
+                    format!("{:5}  {time}{}", idx + 1, entry.line)
+                })
+                .collect()
+        };
+        for line in lines {
+            self.display_result(line);
+        }
+    }
+
+    /// Deletes the entry at `offset`, counting from 1; negative offsets count
+    /// back from the end of the list.
+    fn delete_history_entry(&mut self, offset: i64) -> i32 {
+        let len = self.history.borrow().entries.len() as i64;
+        let idx = if offset < 0 { len + offset } else { offset - 1 };
+        if idx < 0 || idx >= len {
+            self.display_error(format!("history: {offset}: history position out of range"));
+            return 1;
+        }
+        self.history.borrow_mut().remove(idx as usize);
+        0
+    }
+}
+
+/// Which entries `history` lists.
+#[derive(Debug, Default)]
+struct Filter {
+    /// Only those entered in this directory.
+    dir: Option<PathBuf>,
+    /// Only those that exited with a non-zero status.
+    failed: bool,
+    /// Only those from this session.
+    session: Option<String>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        if let Some(dir) = &self.dir
+            && entry.dir.as_ref() != Some(dir)
+        {
+            return false;
+        }
+        if self.failed && entry.status.is_none_or(|status| status == 0) {
+            return false;
+        }
+        self.session.is_none() || entry.session == self.session
+    }
+}
+
+/// Parses a `HISTSIZE`-style value: negative means unlimited and anything
+/// unparseable falls back to the default.
+fn history_size(value: Option<String>) -> usize {
+    match value.map(|value| value.trim().parse::<i64>()) {
+        Some(Ok(size)) if size < 0 => usize::MAX,
+        Some(Ok(size)) => size as usize,
+        _ => DEFAULT_HISTSIZE,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::fs;
+
+    impl HistoryStore {
+        fn lines(&self) -> Vec<&str> {
+            self.entries
+                .iter()
+                .map(|entry| entry.line.as_str())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn histcontrol_filters_entries() {
+        let mut store = HistoryStore::default();
+        store.set_control("ignoredups:ignorespace");
+
+        assert!(store.add("ls"));
+        assert!(!store.add("ls"));
+        assert!(!store.add(" secret"));
+        assert!(!store.add("   "));
+        assert!(store.add("pwd"));
+        assert!(store.add("ls"));
+        assert_eq!(vec!["ls", "pwd", "ls"], store.lines());
+
+        store.set_control("erasedups");
+        assert!(store.add("ls"));
+        assert_eq!(vec!["pwd", "ls"], store.lines());
+    }
+
+    #[test]
+    fn histsize_limits_entries() {
+        let mut store = HistoryStore {
+            max_len: 2,
+            ..Default::default()
+        };
+        store.add("one");
+        store.add("two");
+        store.add("three");
+        assert_eq!(vec!["two", "three"], store.lines());
+
+        assert_eq!(usize::MAX, history_size(Some("-1".to_string())));
+        assert_eq!(0, history_size(Some("0".to_string())));
+        assert_eq!(DEFAULT_HISTSIZE, history_size(Some("lots".to_string())));
+    }
+
+    /// The entries in the history file at `path`.
+    fn file_entries(path: &Path) -> Vec<String> {
+        let contents = fs::read_to_string(path).unwrap();
+        read_records(&contents)
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect()
+    }
+
+    #[test]
+    fn file_is_appended_trimmed_and_loaded() {
+        let dir = TempDir::new("history-append");
+        let path = dir.join("history");
+
+        let mut store = HistoryStore {
+            file: Some(path.clone()),
+            file_max_len: 2,
+            ..Default::default()
+        };
+        for line in ["one", "two", "three"] {
+            store.add(line);
+            store.append_unwritten(&path).unwrap();
+        }
+        assert_eq!(vec!["one", "two", "three"], file_entries(&path));
+
+        store.trim_file().unwrap();
+        assert_eq!(vec!["two", "three"], file_entries(&path));
+
+        let mut loaded = HistoryStore::default();
+        loaded.load(&path).unwrap();
+        assert_eq!(vec!["two", "three"], loaded.lines());
+    }
+
+    #[test]
+    fn log_is_trimmed_to_the_file_size() {
+        let dir = TempDir::new("history-log-trim");
+        let path = dir.join("log");
+        fs::write(&path, "{\"a\":1}\n{\"b\":2}\n{\"c\":3}\n").unwrap();
+
+        let store = HistoryStore {
+            log: Some(path.clone()),
+            file_max_len: 2,
+            ..Default::default()
+        };
+        store.trim_log().unwrap();
+        assert_eq!("{\"b\":2}\n{\"c\":3}\n", fs::read_to_string(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+        store.trim_log().unwrap();
+    }
+
+    #[test]
+    fn only_unwritten_entries_are_appended() {
+        let dir = TempDir::new("history-unwritten");
+        let path = dir.join("history");
+        fs::write(&path, "other shell\n").unwrap();
+
+        let mut history = SharedHistory::default();
+        history.borrow_mut().set_control("erasedups");
+        for line in ["one", "two"] {
+            history.add(line).unwrap();
+        }
+        history.append(&path).unwrap();
+        history.append(&path).unwrap();
+        for line in ["three", "one", "three"] {
+            history.add(line).unwrap();
+        }
+        history.append(&path).unwrap();
+        assert_eq!(
+            vec!["other shell", "one", "two", "one", "three"],
+            file_entries(&path)
+        );
+    }
+
+    #[test]
+    fn multi_line_entries_round_trip() {
+        let dir = TempDir::new("history-multi-line");
+        let path = dir.join("history");
+        fs::write(&path, "ls\npwd\n").unwrap();
+
+        let mut store = HistoryStore {
+            file: Some(path.clone()),
+            file_max_len: 3,
+            ..Default::default()
+        };
+        for line in ["f() {\n  echo hi\n}", "echo \"a\n\nb\"", "#1 is not a time"] {
+            store.add(line);
+        }
+        store.append_unwritten(&path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("ls\npwd\n#"));
+        assert!(contents.contains("\nf() {\n  echo hi\n}\n#"));
+
+        store.trim_file().unwrap();
+        let mut loaded = HistoryStore::default();
+        loaded.load(&path).unwrap();
+        assert_eq!(
+            vec!["f() {\n  echo hi\n}", "echo \"a\n\nb\"", "#1 is not a time"],
+            loaded.lines()
+        );
+        assert!(loaded.entries().all(|entry| entry.time.is_some()));
+    }
+
+    #[test]
+    fn suggestions_prefer_the_current_directory() {
+        let mut store = HistoryStore::default();
+        let (home, src) = (Path::new("/home"), Path::new("/src"));
+        for (line, dir) in [("cargo build", src), ("cargo test", home), ("ls", src)] {
+            store.add(line);
+            store.record_start(dir);
+        }
+
+        assert_eq!(Some("cargo build"), store.suggest("car", src));
+        assert_eq!(Some("cargo test"), store.suggest("car", home));
+        assert_eq!(Some("cargo test"), store.suggest("car", Path::new("/tmp")));
+        assert_eq!(None, store.suggest("cargo test", home));
+        assert_eq!(None, store.suggest("git", src));
+    }
+
+    #[test]
+    fn status_is_recorded_for_the_running_entry() {
+        let mut store = HistoryStore::default();
+        store.add("false");
+        store.record_start(Path::new("/tmp"));
+        assert_eq!(
+            Some(1),
+            store.record_status(1).and_then(|entry| entry.status)
+        );
+        assert!(store.record_status(0).is_none());
+        let entry = store.entries().next_back().unwrap();
+        assert_eq!(Some(1), entry.status);
+        assert_eq!(Some(Path::new("/tmp")), entry.dir.as_deref());
+        assert!(entry.time.is_some() && entry.duration.is_some());
+        assert_eq!(Some(&store.session), entry.session.as_ref());
+
+        // a line that wasn't stored leaves the last entry alone
+        store.set_control("ignorespace");
+        store.add(" secret");
+        store.record_status(2);
+        assert_eq!(Some(1), store.entries().next_back().unwrap().status);
+    }
+
+    #[test]
+    fn log_details_fill_in_loaded_lines() {
+        let mut store = HistoryStore::default();
+        for line in ["make", "ls", "make"] {
+            store.add(line);
+        }
+        let logged = |dir: &str, status| Entry {
+            dir: Some(PathBuf::from(dir)),
+            status: Some(status),
+            time: Some(SystemTime::now()),
+            ..Entry::new("make".to_string())
+        };
+        store.annotate(vec![logged("/old", 2), logged("/new", 0)]);
+
+        let dirs: Vec<Option<&Path>> = store.entries().map(|entry| entry.dir.as_deref()).collect();
+        assert_eq!(
+            vec![Some(Path::new("/old")), None, Some(Path::new("/new"))],
+            dirs
+        );
+
+        let failed = Filter {
+            failed: true,
+            ..Default::default()
+        };
+        let in_new = Filter {
+            dir: Some(PathBuf::from("/new")),
+            ..Default::default()
+        };
+        let matching = |filter: &Filter| {
+            store
+                .entries()
+                .filter(|entry| filter.matches(entry))
+                .count()
+        };
+        assert_eq!(1, matching(&failed));
+        assert_eq!(1, matching(&in_new));
+        assert_eq!(3, matching(&Filter::default()));
+    }
+
+    #[test]
+    fn shared_history_searches() {
+        let mut history = SharedHistory::default();
+        for line in ["cargo build", "ls", "cargo test"] {
+            history.add(line).unwrap();
+        }
+
+        let found = history
+            .starts_with("cargo", 2, SearchDirection::Reverse)
+            .unwrap()
+            .unwrap();
+        assert_eq!(("cargo test", 2), (found.entry.as_ref(), found.idx));
+
+        let found = history
+            .search("build", 0, SearchDirection::Forward)
+            .unwrap()
+            .unwrap();
+        assert_eq!((0, 6), (found.idx, found.pos));
+
+        assert!(
+            history
+                .search("nothing", 2, SearchDirection::Reverse)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
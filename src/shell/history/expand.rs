@@ -0,0 +1,344 @@
+//! csh-style history expansion: `!!`, `!n`, `!-n`, `!prefix`, `!?substr?`,
+//! `^old^new` and the word designators that can follow them.
+
+/// Expands history references in `line` against `history`, oldest entry
+/// first.
+///
+/// Returns `Ok(None)` when the line contains nothing to expand, so callers
+/// can tell whether to echo the result.
+pub fn expand(line: &str, history: &[String]) -> Result<Option<String>, String> {
+    if let Some(rest) = line.strip_prefix('^') {
+        return quick_substitution(rest, history).map(Some);
+    }
+
+    let chars: Vec<char> = line.chars().collect();
+    let mut expanded = String::with_capacity(line.len());
+    let mut did_expand = false;
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        match c {
+            '\\' if chars.get(idx + 1) == Some(&'!') => {
+                expanded.push_str("\\!");
+                idx += 2;
+                continue;
+            }
+            // each kind of quote is an ordinary character inside the other
+            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+            '!' if !in_single_quotes && starts_expansion(chars.get(idx + 1)) => {
+                let (text, next_idx) = expand_reference(&chars, idx + 1, history)?;
+                expanded.push_str(&text);
+                did_expand = true;
+                idx = next_idx;
+                continue;
+            }
+            _ => {}
+        }
+        expanded.push(c);
+        idx += 1;
+    }
+
+    Ok(did_expand.then_some(expanded))
+}
+
+fn starts_expansion(next: Option<&char>) -> bool {
+    match next {
+        Some(c) => !c.is_whitespace() && !matches!(c, '=' | '(' | '"'),
+        None => false,
+    }
+}
+
+/// `^old^new^` is shorthand for substituting into the previous command.
+fn quick_substitution(rest: &str, history: &[String]) -> Result<String, String> {
+    let last = history
+        .last()
+        .ok_or_else(|| "!!: event not found".to_string())?;
+    let mut parts = rest.splitn(3, '^');
+    let old = parts.next().unwrap_or_default();
+    let new = parts.next().unwrap_or_default();
+    let tail = parts.next().unwrap_or_default();
+
+    if old.is_empty() || !last.contains(old) {
+        return Err(format!(":s^{rest}: substitution failed"));
+    }
+    Ok(last.replacen(old, new, 1) + tail)
+}
+
+/// Expands the reference whose event designator starts at `start`, just
+/// after the `!`. Returns the replacement text and the index of the first
+/// character after the reference.
+fn expand_reference(
+    chars: &[char],
+    start: usize,
+    history: &[String],
+) -> Result<(String, usize), String> {
+    let (entry_idx, mut idx) = event(chars, start, history)?;
+    let reference: String = chars[start - 1..idx].iter().collect();
+    let entry = entry_idx
+        .and_then(|entry_idx| history.get(entry_idx))
+        .ok_or_else(|| format!("{reference}: event not found"))?;
+
+    let designator_start = match chars.get(idx) {
+        Some(':') if chars.get(idx + 1).is_some_and(|c| starts_designator(*c)) => Some(idx + 1),
+        Some('^' | '$' | '*' | '-') => Some(idx),
+        _ => None,
+    };
+
+    let Some(designator_start) = designator_start else {
+        return Ok((entry.clone(), idx));
+    };
+
+    let words = split_words(entry);
+    let (range, next_idx) = word_range(chars, designator_start, words.len());
+    idx = next_idx;
+    match range {
+        Some((first, last)) if first <= last && last < words.len() => {
+            Ok((words[first..=last].join(" "), idx))
+        }
+        // `*` on a one-word command expands to nothing
+        Some((first, last)) if first > last && first == words.len() => Ok((String::new(), idx)),
+        _ => {
+            let reference: String = chars[start - 1..idx].iter().collect();
+            Err(format!("{reference}: bad word specifier"))
+        }
+    }
+}
+
+fn starts_designator(c: char) -> bool {
+    c.is_ascii_digit() || matches!(c, '^' | '$' | '*' | '-')
+}
+
+/// Parses an event designator. Returns the index of the history entry it
+/// refers to, if any, and the index just after the designator.
+fn event(
+    chars: &[char],
+    start: usize,
+    history: &[String],
+) -> Result<(Option<usize>, usize), String> {
+    let last = history.len().checked_sub(1);
+
+    match chars[start] {
+        '!' => Ok((last, start + 1)),
+        // `!$`, `!*`, `!^` and `!:n` refer to the previous command
+        '$' | '*' | '^' | ':' => Ok((last, start)),
+        '?' => {
+            let end = chars[start + 1..]
+                .iter()
+                .position(|c| *c == '?')
+                .map(|offset| start + 1 + offset);
+            let needle: String = chars[start + 1..end.unwrap_or(chars.len())]
+                .iter()
+                .collect();
+            let next_idx = end.map_or(chars.len(), |end| end + 1);
+            let found = history.iter().rposition(|entry| entry.contains(&needle));
+            Ok((found, next_idx))
+        }
+        c if c.is_ascii_digit()
+            || (c == '-' && chars.get(start + 1).is_some_and(char::is_ascii_digit)) =>
+        {
+            let digits_start = if c == '-' { start + 1 } else { start };
+            let end = digits_end(chars, digits_start);
+            let number: usize = chars[digits_start..end]
+                .iter()
+                .collect::<String>()
+                .parse()
+                .map_err(|_| "history expansion: number out of range".to_string())?;
+            let entry_idx = if c == '-' {
+                history.len().checked_sub(number)
+            } else {
+                number.checked_sub(1)
+            };
+            Ok((entry_idx, end))
+        }
+        _ => {
+            let end = chars[start..]
+                .iter()
+                .position(|c| c.is_whitespace() || matches!(c, ':' | ';' | '&' | '|'))
+                .map_or(chars.len(), |offset| start + offset);
+            let prefix: String = chars[start..end].iter().collect();
+            let found = history.iter().rposition(|entry| entry.starts_with(&prefix));
+            Ok((found, end))
+        }
+    }
+}
+
+fn digits_end(chars: &[char], start: usize) -> usize {
+    chars[start..]
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .map_or(chars.len(), |offset| start + offset)
+}
+
+/// Parses a word designator into an inclusive range of word indices.
+/// Returns `None` for the range when it can't be satisfied.
+fn word_range(chars: &[char], start: usize, word_count: usize) -> (Option<(usize, usize)>, usize) {
+    let last = word_count.checked_sub(1);
+    let parse_index = |from: usize| -> (Option<usize>, usize) {
+        match chars.get(from) {
+            Some('$') => (last, from + 1),
+            Some(c) if c.is_ascii_digit() => {
+                let end = digits_end(chars, from);
+                let index = chars[from..end].iter().collect::<String>().parse().ok();
+                (index, end)
+            }
+            _ => (None, from),
+        }
+    };
+
+    match chars[start] {
+        '^' => (Some((1, 1)), start + 1),
+        '$' => (last.map(|last| (last, last)), start + 1),
+        '*' => (Some((1, last.unwrap_or(0))), start + 1),
+        '-' => {
+            let (end, next_idx) = parse_index(start + 1);
+            (end.map(|end| (0, end)), next_idx)
+        }
+        _ => {
+            let (first, next_idx) = parse_index(start);
+            let Some(first) = first else {
+                return (None, next_idx);
+            };
+            match chars.get(next_idx) {
+                Some('*') => (Some((first, last.unwrap_or(0))), next_idx + 1),
+                Some('-') => match parse_index(next_idx + 1) {
+                    (Some(end), after) => (Some((first, end)), after),
+                    // `x-` is like `x*` but leaves out the last word
+                    (None, after) => (
+                        last.and_then(|last| last.checked_sub(1))
+                            .map(|end| (first, end)),
+                        after,
+                    ),
+                },
+                _ => (Some((first, first)), next_idx),
+            }
+        }
+    }
+}
+
+/// Splits a history entry into words, keeping quoted strings together and
+/// with their quotes, the way the designators in bash see them.
+fn split_words(entry: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for c in entry.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+        match (c, quote) {
+            ('\\', q) if q != Some('\'') => {
+                current.push(c);
+                escaped = true;
+            }
+            ('\'' | '"', None) => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (c, Some(q)) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (c, None) if c.is_whitespace() => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn history() -> Vec<String> {
+        [
+            "ls -la /tmp",
+            "echo \"a b\" c",
+            "apt install vim",
+            "cat notes.txt",
+        ]
+        .iter()
+        .map(|entry| entry.to_string())
+        .collect()
+    }
+
+    fn expanded(line: &str) -> String {
+        expand(line, &history()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn event_designators() {
+        assert_eq!("sudo cat notes.txt", expanded("sudo !!"));
+        assert_eq!("ls -la /tmp", expanded("!1"));
+        assert_eq!("apt install vim", expanded("!-2"));
+        assert_eq!("echo \"a b\" c", expanded("!ec"));
+        assert_eq!("apt install vim", expanded("!?inst?"));
+        assert_eq!("cat notes.txt x", expanded("!?notes? x"));
+        assert_eq!("echo \"it's cat notes.txt\"", expanded("echo \"it's !!\""));
+        assert_eq!("echo \"cat notes.txt\"'!!'", expanded("echo \"!!\"'!!'"));
+    }
+
+    #[test]
+    fn word_designators() {
+        assert_eq!("vim notes.txt", expanded("vim !$"));
+        assert_eq!("less notes.txt", expanded("less !*"));
+        assert_eq!("-la", expanded("!1:1"));
+        assert_eq!("\"a b\"", expanded("!ec:1"));
+        assert_eq!("/tmp", expanded("!l:$"));
+        assert_eq!("install vim", expanded("!apt:1-2"));
+        assert_eq!("apt install", expanded("!apt:0-"));
+        assert_eq!("install vim", expanded("!apt:1*"));
+        assert_eq!("notes.txt", expanded("!:1"));
+        assert_eq!("install", expanded("!-2^"));
+    }
+
+    #[test]
+    fn quick_substitution_replaces_in_last_command() {
+        assert_eq!("cat todo.txt", expanded("^notes^todo"));
+        assert_eq!("cat notes.md -n", expanded("^txt^md^ -n"));
+        assert!(expand("^nothere^x", &history()).is_err());
+    }
+
+    #[test]
+    fn lines_without_references_are_untouched() {
+        let history = history();
+        assert_eq!(None, expand("echo hello", &history).unwrap());
+        assert_eq!(None, expand("echo 'hi!!'", &history).unwrap());
+        assert_eq!(None, expand("echo \\!!", &history).unwrap());
+        assert_eq!(None, expand("echo wow! x", &history).unwrap());
+        assert_eq!(None, expand("[ ! -f x ]", &history).unwrap());
+        assert_eq!(None, expand("a!=b", &history).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        let history = history();
+        assert_eq!(
+            Err("!nope: event not found".to_string()),
+            expand("!nope", &history)
+        );
+        assert_eq!(
+            Err("!99: event not found".to_string()),
+            expand("!99", &history)
+        );
+        assert_eq!(
+            Err("!!:5: bad word specifier".to_string()),
+            expand("!!:5", &history)
+        );
+        assert_eq!(Err("!!: event not found".to_string()), expand("!!", &[]));
+    }
+}
@@ -0,0 +1,144 @@
+//! The history log: one JSON record per finished command, so what ran,
+//! where, when and how it went can be looked up long after `HISTSIZE` has
+//! dropped it. Like the history file, it is cut down to `HISTFILESIZE`
+//! records when a shell starts.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::shell::history::Entry;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    line: String,
+    /// Milliseconds since the epoch.
+    start: u64,
+    duration_ms: u64,
+    cwd: String,
+    status: i32,
+    session: String,
+}
+
+impl Record {
+    /// `None` for an entry that hasn't finished.
+    fn from_entry(entry: &Entry) -> Option<Record> {
+        let start = entry.time?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Record {
+            line: entry.line.clone(),
+            start: start.as_millis() as u64,
+            duration_ms: entry.duration?.as_millis() as u64,
+            cwd: entry.dir.as_ref()?.display().to_string(),
+            status: entry.status?,
+            session: entry.session.clone()?,
+        })
+    }
+
+    fn into_entry(self) -> Entry {
+        Entry {
+            line: self.line,
+            time: Some(UNIX_EPOCH + Duration::from_millis(self.start)),
+            duration: Some(Duration::from_millis(self.duration_ms)),
+            dir: Some(PathBuf::from(self.cwd)),
+            status: Some(self.status),
+            session: Some(self.session),
+        }
+    }
+}
+
+/// Appends the finished `entry` to the log at `path`, under a lock so
+/// several shells can share it. The log records where and when commands
+/// ran, so a new one is readable only by its owner. A line left
+/// unterminated by a crash is ended first so the new record starts on its
+/// own line.
+pub fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+    let Some(record) = Record::from_entry(entry) else {
+        return Ok(());
+    };
+    let mut line = serde_json::to_string(&record)?;
+    line.push('\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?;
+    file.lock()?;
+    if file.metadata()?.len() > 0 {
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            line.insert(0, '\n');
+        }
+    }
+    file.write_all(line.as_bytes())
+}
+
+/// Every entry in the log at `path`, oldest first. Lines that don't parse,
+/// such as one cut short by a crash, are skipped.
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let file = File::open(path)?;
+    file.lock_shared()?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(&file).lines() {
+        if let Ok(record) = serde_json::from_str::<Record>(&line?) {
+            entries.push(record.into_entry());
+        }
+    }
+    Ok(entries)
+}
+
+/// An id for this shell's entries in the log: when it started and its
+/// process id.
+pub fn session_id() -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    format!("{started}-{}", std::process::id())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn finished_entries_round_trip() {
+        let dir = TempDir::new("history-log");
+        let path = dir.join("log");
+
+        let entry = Entry {
+            line: "echo \"a\tb\"".to_string(),
+            time: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            duration: Some(Duration::from_millis(42)),
+            dir: Some(PathBuf::from("/tmp")),
+            status: Some(1),
+            session: Some("s1".to_string()),
+        };
+        append(&path, &entry).unwrap();
+        append(&path, &Entry::new("unfinished".to_string())).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"line\":\"cut")
+            .unwrap();
+        let after = Entry {
+            line: "after a crash".to_string(),
+            ..entry.clone()
+        };
+        append(&path, &after).unwrap();
+
+        assert_eq!(vec![entry, after], read(&path).unwrap());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+}
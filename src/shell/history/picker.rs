@@ -0,0 +1,504 @@
+//! The full-screen history picker bound to Ctrl-R.
+//!
+//! Typing filters past commands fuzzily, best match first. Each row shows
+//! how long ago the command ran, how it exited and where. Ctrl-R switches
+//! between all of history and only what ran in the current directory;
+//! Enter or Tab puts the choice on the line for editing and Esc leaves the
+//! line alone.
+
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, Movement, RepeatCount};
+use unicode_width::UnicodeWidthStr;
+
+use crate::command::completer::fuzzy;
+use crate::command::highlight::paint;
+use crate::shell::history::{Entry, SharedHistory};
+
+const DIR_WIDTH: usize = 24;
+
+/// The key handler. It copies the shell's history only when Ctrl-R opens
+/// the picker.
+#[derive(Debug, Clone)]
+pub struct HistoryPicker {
+    history: SharedHistory,
+    /// The shell's working and home directories as of the prompt.
+    dirs: Arc<Mutex<(PathBuf, Option<PathBuf>)>>,
+}
+
+impl HistoryPicker {
+    pub fn new(history: SharedHistory) -> Self {
+        Self {
+            history,
+            dirs: Arc::default(),
+        }
+    }
+
+    /// Sets the directory the next Ctrl-R searches from, and the home
+    /// directory shown as `~`.
+    pub fn set_dir(&self, dir: &Path, home: Option<PathBuf>) {
+        *self.dirs.lock().unwrap_or_else(|err| err.into_inner()) = (dir.to_path_buf(), home);
+    }
+}
+
+impl ConditionalEventHandler for HistoryPicker {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let entries: Vec<Entry> = self.history.borrow().entries().cloned().collect();
+        let (dir, home) = self
+            .dirs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        let mut picker = Picker::new(&entries, &dir, ctx.line());
+        match run(&mut picker, home.as_deref()) {
+            Ok(Some(line)) => Some(Cmd::Replace(Movement::WholeBuffer, Some(line))),
+            Ok(None) | Err(_) => Some(Cmd::Noop),
+        }
+    }
+}
+
+/// A key, as far as the picker cares.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Backspace,
+    ClearQuery,
+    Up,
+    Down,
+    ToggleDir,
+    Accept,
+    Cancel,
+    Other,
+}
+
+/// What the user is choosing from and what they have typed so far.
+struct Picker<'a> {
+    /// Each distinct line once, newest first.
+    entries: Vec<&'a Entry>,
+    dir: &'a Path,
+    query: String,
+    here_only: bool,
+    /// The entries shown, best first, with the characters the query
+    /// matched in each.
+    found: Vec<(&'a Entry, Vec<usize>)>,
+    selected: usize,
+}
+
+impl<'a> Picker<'a> {
+    fn new(entries: &'a [Entry], dir: &'a Path, query: &str) -> Picker<'a> {
+        let mut seen = HashSet::new();
+        let entries = entries
+            .iter()
+            .rev()
+            .filter(|entry| seen.insert(entry.line.as_str()))
+            .collect();
+        let mut picker = Picker {
+            entries,
+            dir,
+            query: query.to_string(),
+            here_only: false,
+            found: Vec::new(),
+            selected: 0,
+        };
+        picker.refilter();
+        picker
+    }
+
+    fn refilter(&mut self) {
+        let candidates = self
+            .entries
+            .iter()
+            .filter(|entry| !self.here_only || entry.dir.as_deref() == Some(self.dir));
+        self.found = if self.query.is_empty() {
+            candidates.map(|entry| (*entry, Vec::new())).collect()
+        } else {
+            let mut scored: Vec<_> = candidates
+                .filter_map(|entry| {
+                    fuzzy::subsequence(&self.query, &entry.line).map(|found| (entry, found))
+                })
+                .collect();
+            // stable, so equal scores stay newest first
+            scored.sort_by_key(|(_, found)| -found.score);
+            scored
+                .into_iter()
+                .map(|(entry, found)| (*entry, found.positions))
+                .collect()
+        };
+        self.selected = 0;
+    }
+
+    /// Handles `key`. Returns `Some` once the picker is done: with the
+    /// chosen line, or `None` if it was cancelled.
+    fn key(&mut self, key: Key) -> Option<Option<String>> {
+        match key {
+            Key::Char(c) => {
+                self.query.push(c);
+                self.refilter();
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.refilter();
+            }
+            Key::ClearQuery => {
+                self.query.clear();
+                self.refilter();
+            }
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => {
+                self.selected = (self.selected + 1).min(self.found.len().saturating_sub(1));
+            }
+            Key::ToggleDir => {
+                self.here_only = !self.here_only;
+                self.refilter();
+            }
+            Key::Accept => {
+                let chosen = self
+                    .found
+                    .get(self.selected)
+                    .map(|(entry, _)| entry.line.clone());
+                return Some(chosen);
+            }
+            Key::Cancel => return Some(None),
+            Key::Other => {}
+        }
+        None
+    }
+
+    /// The 1-based terminal column the cursor goes to, after the `> ` and
+    /// the query. Wide characters such as CJK and emoji take two columns.
+    fn cursor_column(&self) -> usize {
+        "> ".width() + self.query.width() + 1
+    }
+
+    /// The screen as `height` rows of at most `width` columns: the query,
+    /// the matches and a line of help.
+    fn render(
+        &self,
+        width: usize,
+        height: usize,
+        now: SystemTime,
+        home: Option<&Path>,
+    ) -> Vec<String> {
+        let rows = height.saturating_sub(2);
+        let scope = if self.here_only { "here" } else { "all" };
+        let count = format!("{}/{} [{scope}]", self.found.len(), self.entries.len());
+        let query = format!("> {}", self.query);
+        let pad = width.saturating_sub(query.width() + count.width());
+        let mut screen = vec![format!("{query}{}{count}", " ".repeat(pad))];
+
+        // keep the selection in view
+        let first = self.selected.saturating_sub(rows.saturating_sub(1));
+        for (idx, (entry, positions)) in self.found.iter().enumerate().skip(first).take(rows) {
+            screen.push(render_row(
+                entry,
+                positions,
+                idx == self.selected,
+                width,
+                now,
+                home,
+            ));
+        }
+        while screen.len() < height.saturating_sub(1) {
+            screen.push(String::new());
+        }
+        screen.push(paint(
+            &truncate(
+                "enter/tab: edit  ctrl-r: this directory or all  esc: cancel",
+                width,
+            ),
+            "90",
+        ));
+        screen
+    }
+}
+
+fn render_row(
+    entry: &Entry,
+    positions: &[usize],
+    selected: bool,
+    width: usize,
+    now: SystemTime,
+    home: Option<&Path>,
+) -> String {
+    let age = entry.time.map(|time| age(time, now)).unwrap_or_default();
+    let status = match entry.status {
+        Some(0) => paint("  0", "32"),
+        Some(status) => paint(&format!("{status:>3}"), "31"),
+        None => "   ".to_string(),
+    };
+    let dir = entry
+        .dir
+        .as_deref()
+        .map(|dir| shorten_dir(dir, home))
+        .unwrap_or_default();
+    let prefix = format!("{age:>4} {status}  {dir:<DIR_WIDTH$}  ");
+    let room = width.saturating_sub(4 + 1 + 3 + 2 + DIR_WIDTH + 2);
+
+    let mut line = String::new();
+    for (idx, c) in entry.line.chars().take(room).enumerate() {
+        let c = if c == '\n' { '↵' } else { c };
+        if positions.contains(&idx) {
+            line.push_str(&paint(&c.to_string(), "1"));
+        } else {
+            line.push(c);
+        }
+    }
+    if selected {
+        // reverse video survives the resets inside the row
+        let row = format!("{prefix}{line}").replace("\x1b[0m", "\x1b[0;7m");
+        return format!("\x1b[7m{row}\x1b[0m");
+    }
+    format!("{prefix}{line}")
+}
+
+/// How long ago `time` was, in the largest whole unit.
+fn age(time: SystemTime, now: SystemTime) -> String {
+    let secs = now.duration_since(time).unwrap_or(Duration::ZERO).as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// `dir` with the home directory as `~`, cut from the left to fit.
+fn shorten_dir(dir: &Path, home: Option<&Path>) -> String {
+    let text = match home.and_then(|home| dir.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => dir.display().to_string(),
+    };
+    let len = text.chars().count();
+    if len <= DIR_WIDTH {
+        return text;
+    }
+    let tail: String = text.chars().skip(len - (DIR_WIDTH - 1)).collect();
+    format!("…{tail}")
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Shows the picker on the alternate screen until the user is done. The
+/// line editor has the terminal in raw mode already.
+fn run(picker: &mut Picker, home: Option<&Path>) -> io::Result<Option<String>> {
+    let mut out = io::stdout();
+    out.write_all(b"\x1b[?1049h")?;
+    let result = (|| {
+        loop {
+            let (width, height) = terminal_size();
+            let screen = picker.render(width, height, SystemTime::now(), home);
+            let cursor = picker.cursor_column();
+            write!(out, "\x1b[H\x1b[2J{}\x1b[1;{cursor}H", screen.join("\r\n"))?;
+            out.flush()?;
+            if let Some(done) = picker.key(read_key()?) {
+                return Ok(done);
+            }
+        }
+    })();
+    out.write_all(b"\x1b[?1049l")?;
+    out.flush()?;
+    result
+}
+
+fn terminal_size() -> (usize, usize) {
+    // SAFETY: `size` is a valid out pointer for the duration of the call.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 && size.ws_row > 0 {
+        (size.ws_col as usize, size.ws_row as usize)
+    } else {
+        (80, 24)
+    }
+}
+
+fn read_byte() -> io::Result<u8> {
+    let mut byte = 0u8;
+    // SAFETY: reads at most one byte into `byte`.
+    let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+    match read {
+        1 => Ok(byte),
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Whether more input arrives within a few milliseconds, to tell Esc from
+/// the start of an escape sequence.
+fn input_pending() -> bool {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `fd` is a valid pollfd for the duration of the call.
+    unsafe { libc::poll(&mut fd, 1, 30) > 0 }
+}
+
+fn read_key() -> io::Result<Key> {
+    let key = match read_byte()? {
+        b'\r' | b'\n' | b'\t' => Key::Accept,
+        0x03 | 0x07 => Key::Cancel,
+        0x12 => Key::ToggleDir,
+        0x15 => Key::ClearQuery,
+        0x10 => Key::Up,
+        0x0e => Key::Down,
+        0x08 | 0x7f => Key::Backspace,
+        0x1b if !input_pending() => Key::Cancel,
+        0x1b => escape_key(read_byte)?,
+        byte if byte < 0x20 => Key::Other,
+        byte => {
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    };
+    Ok(key)
+}
+
+/// The key for an escape sequence, reading what follows Esc with `next`.
+/// A CSI sequence (`Esc [`) is read through to its final byte so none of
+/// it is left behind to be taken as typed characters.
+fn escape_key(mut next: impl FnMut() -> io::Result<u8>) -> io::Result<Key> {
+    let last = match next()? {
+        b'[' => loop {
+            let byte = next()?;
+            if (0x40..=0x7e).contains(&byte) {
+                break byte;
+            }
+        },
+        b'O' => next()?,
+        _ => return Ok(Key::Other),
+    };
+    Ok(match last {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        _ => Key::Other,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(line: &str, dir: &str, status: i32) -> Entry {
+        Entry {
+            line: line.to_string(),
+            time: None,
+            duration: None,
+            dir: Some(PathBuf::from(dir)),
+            status: Some(status),
+            session: None,
+        }
+    }
+
+    fn shown(picker: &Picker) -> Vec<String> {
+        picker
+            .found
+            .iter()
+            .map(|(entry, _)| entry.line.clone())
+            .collect()
+    }
+
+    #[test]
+    fn picker_filters_and_chooses() {
+        let entries = [
+            entry("cargo build", "/src", 0),
+            entry("ls", "/tmp", 0),
+            entry("cargo test", "/tmp", 101),
+            entry("cargo build", "/src", 0),
+        ];
+        let mut picker = Picker::new(&entries, Path::new("/src"), "");
+        assert_eq!(vec!["cargo build", "cargo test", "ls"], shown(&picker));
+
+        for c in "ctest".chars() {
+            assert_eq!(None, picker.key(Key::Char(c)));
+        }
+        assert_eq!(vec!["cargo test"], shown(&picker));
+
+        picker.key(Key::ClearQuery);
+        picker.key(Key::ToggleDir);
+        assert_eq!(vec!["cargo build"], shown(&picker));
+        picker.key(Key::ToggleDir);
+        picker.key(Key::Down);
+        assert_eq!(
+            Some(Some("cargo test".to_string())),
+            picker.key(Key::Accept)
+        );
+        assert_eq!(Some(None), picker.key(Key::Cancel));
+    }
+
+    #[test]
+    fn cursor_follows_the_width_of_the_query() {
+        let mut picker = Picker::new(&[], Path::new("/"), "");
+        assert_eq!(3, picker.cursor_column());
+        for c in "日本a🎉".chars() {
+            picker.key(Key::Char(c));
+        }
+        assert_eq!(10, picker.cursor_column());
+        let header = &picker.render(20, 3, SystemTime::now(), None)[0];
+        assert_eq!(20, header.width());
+    }
+
+    #[test]
+    fn escape_sequences_are_read_whole() {
+        let key = |sequence: &str| {
+            let mut bytes = sequence.bytes();
+            let key = escape_key(|| bytes.next().ok_or(io::ErrorKind::UnexpectedEof.into()));
+            (key.unwrap(), String::from_utf8(bytes.collect()).unwrap())
+        };
+        assert_eq!((Key::Up, "".to_string()), key("[A"));
+        assert_eq!((Key::Down, "".to_string()), key("OB"));
+        assert_eq!((Key::Up, "x".to_string()), key("[1;5Ax"));
+        assert_eq!((Key::Other, "x".to_string()), key("[3~x"));
+        assert_eq!((Key::Other, "x".to_string()), key("[200~x"));
+        assert_eq!((Key::Other, "x".to_string()), key("fx"));
+    }
+
+    #[test]
+    fn rows_show_age_status_and_directory() {
+        let now = SystemTime::now();
+        assert_eq!("59s", age(now - Duration::from_secs(59), now));
+        assert_eq!("2h", age(now - Duration::from_secs(7300), now));
+        assert_eq!(
+            "~/src",
+            shorten_dir(Path::new("/home/me/src"), Some(Path::new("/home/me")))
+        );
+        assert_eq!(
+            DIR_WIDTH,
+            shorten_dir(&Path::new("/a").join("b".repeat(40)), None)
+                .chars()
+                .count()
+        );
+
+        let entries = [entry("false", "/tmp", 1)];
+        let picker = Picker::new(&entries, Path::new("/tmp"), "");
+        let screen = picker.render(80, 5, now, None);
+        assert_eq!(5, screen.len());
+        assert!(screen[0].starts_with("> ") && screen[0].ends_with("1/1 [all]"));
+        assert!(screen[1].contains("\x1b[31m  1\x1b[0;7m  /tmp"));
+        assert!(screen[1].contains("false"));
+    }
+}
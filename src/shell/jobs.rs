@@ -0,0 +1,918 @@
+use std::{
+    ffi::CStr,
+    io::{self, Write},
+    mem,
+    os::unix::process::CommandExt,
+    process::{Command as StdProcCmd, Stdio},
+};
+
+use libc::{c_int, pid_t};
+
+use crate::command::Command;
+use crate::shell::Shell;
+use crate::shell::traps::{CatchInterrupts, interrupt_pending};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped(i32),
+    Done(i32),
+    Signaled(i32),
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        matches!(self, Self::Done(_) | Self::Signaled(_))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Running => "Running".to_string(),
+            Self::Stopped(_) => "Stopped".to_string(),
+            Self::Done(0) => "Done".to_string(),
+            Self::Done(code) => format!("Exit {code}"),
+            Self::Signaled(signal) => signal_description(*signal),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: usize,
+    pub pgid: pid_t,
+    pub command: String,
+    pub state: JobState,
+}
+
+/// The shell's background and stopped jobs. Every job is a single process
+/// whose pid doubles as its process group id.
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    current: Option<usize>,
+    previous: Option<usize>,
+    /// Set once the shell owns the terminal and runs jobs in their own
+    /// process groups.
+    job_control: bool,
+    shell_pgid: pid_t,
+    tty_modes: Option<libc::termios>,
+}
+
+impl JobTable {
+    pub(super) fn job_control(&self) -> bool {
+        self.job_control
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    fn add(&mut self, pgid: pid_t, command: String, state: JobState) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job {
+            id,
+            pgid,
+            command,
+            state,
+        });
+        self.make_current(id);
+        id
+    }
+
+    fn make_current(&mut self, id: usize) {
+        if self.current != Some(id) {
+            self.previous = self.current;
+            self.current = Some(id);
+        }
+    }
+
+    fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Job> {
+        let idx = self.jobs.iter().position(|job| job.id == id)?;
+        let job = self.jobs.remove(idx);
+
+        if self.current == Some(id) {
+            self.current = self.previous.take();
+        } else if self.previous == Some(id) {
+            self.previous = None;
+        }
+        // Fall back to the newest remaining jobs, like bash does
+        if self.current.is_none() {
+            self.current = self.jobs.last().map(|job| job.id);
+        }
+        if self.previous.is_none() {
+            self.previous = self
+                .jobs
+                .iter()
+                .rev()
+                .map(|job| job.id)
+                .find(|id| Some(*id) != self.current);
+        }
+        Some(job)
+    }
+
+    /// Resolves a job spec: `%n`, `%+`, `%%`, `%-`, `%prefix`, `%?substr` or
+    /// a bare job number. An empty spec means the current job.
+    fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let Some(spec) = spec else {
+            return self
+                .current
+                .ok_or_else(|| "current: no such job".to_string());
+        };
+
+        let body = spec.strip_prefix('%').unwrap_or(spec);
+        let found = match body {
+            "" | "+" | "%" => self.current,
+            "-" => self.previous.or(self.current),
+            _ => {
+                if let Ok(id) = body.parse::<usize>() {
+                    self.get(id).map(|job| job.id)
+                } else if let Some(needle) = body.strip_prefix('?') {
+                    self.find_unique(spec, |job| job.command.contains(needle))?
+                } else {
+                    self.find_unique(spec, |job| job.command.starts_with(body))?
+                }
+            }
+        };
+        found.ok_or_else(|| format!("{spec}: no such job"))
+    }
+
+    fn find_unique(
+        &self,
+        spec: &str,
+        predicate: impl Fn(&Job) -> bool,
+    ) -> Result<Option<usize>, String> {
+        let mut matches = self.jobs.iter().filter(|job| predicate(job));
+        let first = matches.next().map(|job| job.id);
+        if matches.next().is_some() {
+            return Err(format!("{spec}: ambiguous job spec"));
+        }
+        Ok(first)
+    }
+
+    fn marker(&self, id: usize) -> char {
+        if self.current == Some(id) {
+            '+'
+        } else if self.previous == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    fn format(&self, job: &Job, with_pid: bool) -> String {
+        let suffix = if job.state == JobState::Running {
+            " &"
+        } else {
+            ""
+        };
+        let pid = if with_pid {
+            format!("{} ", job.pgid)
+        } else {
+            " ".to_string()
+        };
+        format!(
+            "[{}]{} {}{:<24}{}{}",
+            job.id,
+            self.marker(job.id),
+            pid,
+            job.state.describe(),
+            job.command,
+            suffix
+        )
+    }
+
+    fn running_ids(&self) -> Vec<usize> {
+        self.jobs
+            .iter()
+            .filter(|job| job.state == JobState::Running)
+            .map(|job| job.id)
+            .collect()
+    }
+
+    /// Polls every unfinished job without blocking and records state changes.
+    /// Returns the ids of jobs whose state changed.
+    fn poll(&mut self) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for job in self.jobs.iter_mut().filter(|job| !job.state.is_finished()) {
+            let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+            if let Ok(Some(outcome)) = wait_pid(job.pgid, flags, false) {
+                let state = outcome.job_state();
+                if state != job.state {
+                    job.state = state;
+                    changed.push(job.id);
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// What `waitpid` reported for a child.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WaitOutcome {
+    Exited(i32),
+    Signaled(i32),
+    Stopped(i32),
+    Continued,
+}
+
+impl WaitOutcome {
+    fn job_state(self) -> JobState {
+        match self {
+            Self::Exited(code) => JobState::Done(code),
+            Self::Signaled(signal) => JobState::Signaled(signal),
+            Self::Stopped(signal) => JobState::Stopped(signal),
+            Self::Continued => JobState::Running,
+        }
+    }
+
+    /// The `$?` value for this outcome.
+    fn status(self) -> i32 {
+        match self {
+            Self::Exited(code) => code,
+            Self::Signaled(signal) | Self::Stopped(signal) => 128 + signal,
+            Self::Continued => 0,
+        }
+    }
+}
+
+/// Waits for `pid`, retrying on `EINTR`. Returns `None` when `WNOHANG` was
+/// given and the child hasn't changed state. An `interruptible` wait gives
+/// up with `ErrorKind::Interrupted` once Ctrl-C has been pressed instead.
+fn wait_pid(pid: pid_t, flags: c_int, interruptible: bool) -> io::Result<Option<WaitOutcome>> {
+    let mut status: c_int = 0;
+    loop {
+        // SAFETY: `status` is a valid out pointer for the duration of the call.
+        let result = unsafe { libc::waitpid(pid, &mut status, flags) };
+        if result == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted && !(interruptible && interrupt_pending()) {
+                continue;
+            }
+            return Err(err);
+        }
+        if result == 0 {
+            return Ok(None);
+        }
+        break;
+    }
+
+    let outcome = if libc::WIFEXITED(status) {
+        WaitOutcome::Exited(libc::WEXITSTATUS(status))
+    } else if libc::WIFSIGNALED(status) {
+        WaitOutcome::Signaled(libc::WTERMSIG(status))
+    } else if libc::WIFSTOPPED(status) {
+        WaitOutcome::Stopped(libc::WSTOPSIG(status))
+    } else {
+        WaitOutcome::Continued
+    };
+    Ok(Some(outcome))
+}
+
+fn signal_description(signal: i32) -> String {
+    // SAFETY: strsignal returns a pointer to a static or thread-local string
+    // that stays valid until the next call, and we copy it out immediately.
+    let description = unsafe { libc::strsignal(signal) };
+    if description.is_null() {
+        return format!("Signal {signal}");
+    }
+    // SAFETY: checked for null above; strsignal returns a C string.
+    unsafe { CStr::from_ptr(description) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Signals the shell ignores while interactive and children must get back.
+pub(super) const JOB_CONTROL_SIGNALS: [c_int; 3] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+/// Keyboard signals that must reach the foreground command but not the shell.
+const INTERRUPT_SIGNALS: [c_int; 2] = [libc::SIGINT, libc::SIGQUIT];
+
+/// Signals the shell may ignore or catch that children start out with the
+/// default disposition for.
+const CHILD_RESET_SIGNALS: [c_int; 6] = [
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+impl Shell {
+    /// Takes control of the terminal so jobs can be started, stopped and
+    /// moved between foreground and background. Does nothing when stdin is
+    /// not a terminal.
+    pub fn init_job_control(&mut self) {
+        // SAFETY: plain libc calls on the shell's own process and stdin.
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return;
+            }
+
+            // Wait until we are in the foreground, as bash does when started
+            // from a job-control shell as a background job
+            loop {
+                let pgid = libc::getpgrp();
+                if libc::tcgetpgrp(libc::STDIN_FILENO) == pgid {
+                    break;
+                }
+                libc::kill(-pgid, libc::SIGTTIN);
+            }
+
+            for signal in JOB_CONTROL_SIGNALS {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+
+            let pid = libc::getpid();
+            if libc::getpgrp() != pid {
+                libc::setpgid(0, 0);
+            }
+            libc::tcsetpgrp(libc::STDIN_FILENO, pid);
+
+            let mut modes: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut modes) == 0 {
+                self.jobs.tty_modes = Some(modes);
+            }
+            self.jobs.shell_pgid = pid;
+        }
+        self.jobs.job_control = true;
+    }
+
+    /// Records that the line being edited was abandoned with Ctrl-C.
+    pub fn line_interrupted(&mut self) {
+        self.last_status = 128 + libc::SIGINT;
+    }
+
+    /// Number of jobs in the job table, for `\j` in prompts.
+    pub fn job_count(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// The dispositions a child gets for the signals the shell ignores or
+    /// catches for its own sake. Signals ignored with `trap ''` stay
+    /// ignored.
+    fn child_signal_dispositions(&self, foreground: bool) -> Vec<(c_int, libc::sighandler_t)> {
+        CHILD_RESET_SIGNALS
+            .into_iter()
+            .map(|signal| {
+                // Without job control a background job shares our process
+                // group, so it has to ignore Ctrl-C itself
+                let background_interrupt =
+                    !foreground && !self.jobs.job_control && INTERRUPT_SIGNALS.contains(&signal);
+                let handler = if background_interrupt || self.traps.ignores(signal) {
+                    libc::SIG_IGN
+                } else {
+                    libc::SIG_DFL
+                };
+                (signal, handler)
+            })
+            .collect()
+    }
+
+    /// Starts an external command, in the background if the current line
+    /// ended with `&`, and returns its exit status.
+    pub(super) fn spawn_job(&mut self, mut cmd: StdProcCmd, name: &str) -> i32 {
+        let foreground = self.background_job.is_none();
+        let job_control = self.jobs.job_control;
+        let dispositions = self.child_signal_dispositions(foreground);
+
+        // SAFETY: the closure only makes async-signal-safe libc calls.
+        unsafe {
+            cmd.pre_exec(move || {
+                if job_control {
+                    libc::setpgid(0, 0);
+                    if foreground {
+                        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+                    }
+                }
+                for &(signal, handler) in &dispositions {
+                    libc::signal(signal, handler);
+                }
+                Ok(())
+            });
+        }
+        if !foreground {
+            cmd.stdin(Stdio::null());
+        }
+
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                self.display_error(format!("{name}: {err}"));
+                return 126;
+            }
+        };
+        let pid = child.id() as pid_t;
+        if job_control {
+            // Also set the group from the parent so there's no window where
+            // the child could still be in ours.
+            // SAFETY: plain libc call; failure just means the child got there
+            // first.
+            unsafe { libc::setpgid(pid, pid) };
+        }
+
+        match self.background_job.clone() {
+            Some(command) => self.add_background_job(pid, command),
+            None => {
+                let command = self.current_line.clone();
+                self.wait_for_foreground(pid, command, None)
+            }
+        }
+    }
+
+    /// Runs a builtin from a line that ended with `&` in a forked copy of
+    /// the shell, the way bash runs it in a subshell, so that `cd dir &` and
+    /// the like leave this shell alone.
+    pub(super) fn fork_builtin(
+        &mut self,
+        cmd: &Command,
+        env_overrides: &[(String, String)],
+    ) -> i32 {
+        let Some(command) = self.background_job.clone() else {
+            return self.exec_single(cmd, env_overrides);
+        };
+        let job_control = self.jobs.job_control;
+        let dispositions = self.child_signal_dispositions(false);
+        _ = io::stdout().flush();
+        _ = io::stderr().flush();
+
+        // SAFETY: the shell runs on a single thread, so the child is a
+        // complete copy of it and free to allocate. The unit tests run on
+        // several threads, so this path is only tested by running the binary.
+        match unsafe { libc::fork() } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                self.display_error(format!("fork: {err}"));
+                1
+            }
+            0 => {
+                // SAFETY: plain libc calls setting up the child's process
+                // group, signals and stdin.
+                unsafe {
+                    if job_control {
+                        libc::setpgid(0, 0);
+                    }
+                    for &(signal, handler) in &dispositions {
+                        libc::signal(signal, handler);
+                    }
+                    let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDONLY);
+                    if null >= 0 {
+                        libc::dup2(null, libc::STDIN_FILENO);
+                        libc::close(null);
+                    }
+                }
+                // Commands it starts are its own foreground, and the terminal
+                // stays with this shell
+                self.background_job = None;
+                self.jobs.job_control = false;
+                let status = self.exec_single(cmd, env_overrides);
+                _ = io::stdout().flush();
+                _ = io::stderr().flush();
+                // SAFETY: ends the child without running this shell's exit
+                // handlers or traps.
+                unsafe { libc::_exit(status) }
+            }
+            pid => {
+                if job_control {
+                    // SAFETY: as in `spawn_job`.
+                    unsafe { libc::setpgid(pid, pid) };
+                }
+                self.add_background_job(pid, command)
+            }
+        }
+    }
+
+    /// Adds the started `pid` to the job table and announces it.
+    fn add_background_job(&mut self, pid: pid_t, command: String) -> i32 {
+        let id = self.jobs.add(pid, command, JobState::Running);
+        self.display_error(format!("[{id}] {pid}"));
+        0
+    }
+
+    /// Hands the terminal to `pgid` and waits until it exits or stops.
+    fn wait_for_foreground(&mut self, pgid: pid_t, command: String, job: Option<usize>) -> i32 {
+        if self.jobs.job_control {
+            // SAFETY: plain libc call on stdin.
+            unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid) };
+        }
+
+        // Without job control the child is in our process group and Ctrl-C
+        // reaches us too, so ignore it until the child is done, like system(3)
+        let saved_handlers = (!self.jobs.job_control).then(|| {
+            // SAFETY: swapping signal dispositions; restored below.
+            INTERRUPT_SIGNALS.map(|signal| unsafe { libc::signal(signal, libc::SIG_IGN) })
+        });
+
+        let outcome = wait_pid(pgid, libc::WUNTRACED, false);
+
+        if let Some(handlers) = saved_handlers {
+            for (signal, handler) in INTERRUPT_SIGNALS.into_iter().zip(handlers) {
+                // SAFETY: restores the disposition saved above.
+                unsafe { libc::signal(signal, handler) };
+            }
+        }
+
+        if self.jobs.job_control {
+            // SAFETY: plain libc calls on stdin; `modes` was filled in by
+            // tcgetattr.
+            unsafe {
+                libc::tcsetpgrp(libc::STDIN_FILENO, self.jobs.shell_pgid);
+                if let Some(modes) = &self.jobs.tty_modes {
+                    libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, modes);
+                }
+            }
+        }
+
+        let outcome = match outcome {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) | Err(_) => {
+                if let Some(id) = job {
+                    self.jobs.remove(id);
+                }
+                return 127;
+            }
+        };
+
+        if let WaitOutcome::Stopped(signal) = outcome {
+            let id = match job {
+                Some(id) => {
+                    if let Some(job) = self.jobs.get_mut(id) {
+                        job.state = JobState::Stopped(signal);
+                    }
+                    self.jobs.make_current(id);
+                    id
+                }
+                None => self.jobs.add(pgid, command, JobState::Stopped(signal)),
+            };
+            if let Some(job) = self.jobs.get(id) {
+                let line = self.jobs.format(job, false);
+                self.display_error(format!("\n{line}"));
+            }
+        } else if let Some(id) = job {
+            self.jobs.remove(id);
+        }
+
+        match outcome {
+            // The terminal echoed ^C without a newline
+            WaitOutcome::Signaled(libc::SIGINT) => eprintln!(),
+            WaitOutcome::Signaled(libc::SIGPIPE) => {}
+            WaitOutcome::Signaled(signal) => self.display_error(signal_description(signal)),
+            _ => {}
+        }
+
+        outcome.status()
+    }
+
+    /// Reports jobs that finished or stopped since the last prompt, like the
+    /// `[1]+  Done` lines bash prints, and forgets finished ones.
+    pub fn notify_jobs(&mut self) {
+        for id in self.jobs.poll() {
+            if let Some(job) = self.jobs.get(id) {
+                let line = self.jobs.format(job, false);
+                self.display_error(line);
+                if job.state.is_finished() {
+                    self.jobs.remove(id);
+                }
+            }
+        }
+    }
+
+    /// `jobs [-l|-p] [jobspec...]`
+    pub(super) fn jobs_builtin(&mut self, args: &[String]) -> i32 {
+        let mut with_pid = false;
+        let mut pids_only = false;
+        let mut specs = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "-l" => with_pid = true,
+                "-p" => pids_only = true,
+                option if option.starts_with('-') && option.len() > 1 => {
+                    self.display_error(format!("jobs: {option}: invalid option"));
+                    return 2;
+                }
+                spec => specs.push(spec),
+            }
+        }
+
+        self.jobs.poll();
+        let ids: Vec<usize> = if specs.is_empty() {
+            self.jobs.jobs.iter().map(|job| job.id).collect()
+        } else {
+            let mut ids = Vec::new();
+            for spec in specs {
+                match self.jobs.find(Some(spec)) {
+                    Ok(id) => ids.push(id),
+                    Err(msg) => {
+                        self.display_error(format!("jobs: {msg}"));
+                        return 1;
+                    }
+                }
+            }
+            ids
+        };
+
+        for id in ids {
+            let Some(job) = self.jobs.get(id) else {
+                continue;
+            };
+            let line = if pids_only {
+                job.pgid.to_string()
+            } else {
+                self.jobs.format(job, with_pid)
+            };
+            let finished = job.state.is_finished();
+            self.display_result(line);
+            if finished {
+                self.jobs.remove(id);
+            }
+        }
+        0
+    }
+
+    /// `fg [jobspec]`: resumes a job in the foreground.
+    pub(super) fn fg_builtin(&mut self, args: &[String]) -> i32 {
+        if !self.jobs.job_control {
+            self.display_error("fg: no job control".to_string());
+            return 1;
+        }
+        let id = match self.jobs.find(args.first().map(String::as_str)) {
+            Ok(id) => id,
+            Err(msg) => {
+                self.display_error(format!("fg: {msg}"));
+                return 1;
+            }
+        };
+        let Some(job) = self.jobs.get_mut(id) else {
+            return 1;
+        };
+        job.state = JobState::Running;
+        let (pgid, command) = (job.pgid, job.command.clone());
+        self.display_result(command.clone());
+
+        // SAFETY: plain libc call signalling the job's process group.
+        unsafe { libc::kill(-pgid, libc::SIGCONT) };
+        self.wait_for_foreground(pgid, command, Some(id))
+    }
+
+    /// `bg [jobspec...]`: resumes stopped jobs in the background.
+    pub(super) fn bg_builtin(&mut self, args: &[String]) -> i32 {
+        if !self.jobs.job_control {
+            self.display_error("bg: no job control".to_string());
+            return 1;
+        }
+        let specs: Vec<Option<&str>> = if args.is_empty() {
+            vec![None]
+        } else {
+            args.iter().map(|arg| Some(arg.as_str())).collect()
+        };
+
+        let mut status = 0;
+        for spec in specs {
+            let id = match self.jobs.find(spec) {
+                Ok(id) => id,
+                Err(msg) => {
+                    self.display_error(format!("bg: {msg}"));
+                    status = 1;
+                    continue;
+                }
+            };
+            let Some(job) = self.jobs.get_mut(id) else {
+                continue;
+            };
+            if job.state == JobState::Running {
+                self.display_error(format!("bg: job {id} already in background"));
+                continue;
+            }
+            job.state = JobState::Running;
+            // SAFETY: plain libc call signalling the job's process group.
+            unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+            let command = job.command.clone();
+            let line = format!("[{id}]{} {command} &", self.jobs.marker(id));
+            self.display_result(line);
+        }
+        status
+    }
+
+    /// `wait [jobspec|pid...]`: waits for the given jobs, or for every
+    /// running job, and returns the status of the last one. A job that is or
+    /// becomes stopped stays in the table with status 128 plus the stop
+    /// signal. Ctrl-C stops the wait with status 130 and leaves the jobs
+    /// running.
+    pub(super) fn wait_builtin(&mut self, args: &[String]) -> i32 {
+        let ids: Vec<usize> = if args.is_empty() {
+            self.jobs.running_ids()
+        } else {
+            let mut ids = Vec::new();
+            for arg in args {
+                let found = match arg.parse::<pid_t>() {
+                    Ok(pid) if !arg.starts_with('%') => self
+                        .jobs
+                        .jobs
+                        .iter()
+                        .find(|job| job.pgid == pid)
+                        .map(|job| job.id)
+                        .ok_or_else(|| format!("pid {pid} is not a child of this shell")),
+                    _ => self.jobs.find(Some(arg)),
+                };
+                match found {
+                    Ok(id) => ids.push(id),
+                    Err(msg) => {
+                        self.display_error(format!("wait: {msg}"));
+                        return 127;
+                    }
+                }
+            }
+            ids
+        };
+
+        let _interrupts = CatchInterrupts::new();
+        let mut status = 0;
+        for id in ids {
+            let Some(job) = self.jobs.get(id) else {
+                continue;
+            };
+            let outcome = match job.state {
+                JobState::Done(code) => WaitOutcome::Exited(code),
+                JobState::Signaled(signal) => WaitOutcome::Signaled(signal),
+                JobState::Stopped(signal) => WaitOutcome::Stopped(signal),
+                JobState::Running => match wait_pid(job.pgid, libc::WUNTRACED, true) {
+                    Ok(Some(outcome)) => outcome,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                        return 128 + libc::SIGINT;
+                    }
+                    _ => {
+                        self.jobs.remove(id);
+                        status = 127;
+                        continue;
+                    }
+                },
+            };
+            status = outcome.status();
+            if let WaitOutcome::Stopped(signal) = outcome {
+                if let Some(job) = self.jobs.get_mut(id) {
+                    job.state = JobState::Stopped(signal);
+                }
+            } else {
+                self.jobs.remove(id);
+            }
+        }
+        status
+    }
+
+    /// `disown [-a] [-r] [jobspec...]`: forgets jobs without signalling them.
+    pub(super) fn disown_builtin(&mut self, args: &[String]) -> i32 {
+        let mut all = false;
+        let mut running_only = false;
+        let mut specs = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "-a" => all = true,
+                "-r" => running_only = true,
+                // We never send SIGHUP to jobs, so -h is the same as the default
+                "-h" => {}
+                option if option.starts_with('-') && option.len() > 1 => {
+                    self.display_error(format!("disown: {option}: invalid option"));
+                    return 2;
+                }
+                spec => specs.push(spec),
+            }
+        }
+
+        let ids: Vec<usize> = if all || (running_only && specs.is_empty()) {
+            self.jobs
+                .jobs
+                .iter()
+                .filter(|job| !running_only || job.state == JobState::Running)
+                .map(|job| job.id)
+                .collect()
+        } else if specs.is_empty() {
+            match self.jobs.find(None) {
+                Ok(id) => vec![id],
+                Err(msg) => {
+                    self.display_error(format!("disown: {msg}"));
+                    return 1;
+                }
+            }
+        } else {
+            let mut ids = Vec::new();
+            for spec in specs {
+                match self.jobs.find(Some(spec)) {
+                    Ok(id) => ids.push(id),
+                    Err(msg) => {
+                        self.display_error(format!("disown: {msg}"));
+                        return 1;
+                    }
+                }
+            }
+            ids
+        };
+
+        for id in ids {
+            self.jobs.remove(id);
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(commands: &[&str]) -> JobTable {
+        let mut table = JobTable::default();
+        for (idx, command) in commands.iter().enumerate() {
+            table.add(1000 + idx as pid_t, command.to_string(), JobState::Running);
+        }
+        table
+    }
+
+    #[test]
+    fn job_specs_resolve() {
+        let table = table(&["sleep 100", "vim notes.txt", "make -j8"]);
+
+        assert_eq!(Ok(3), table.find(None));
+        assert_eq!(Ok(3), table.find(Some("%+")));
+        assert_eq!(Ok(3), table.find(Some("%%")));
+        assert_eq!(Ok(2), table.find(Some("%-")));
+        assert_eq!(Ok(1), table.find(Some("%1")));
+        assert_eq!(Ok(1), table.find(Some("1")));
+        assert_eq!(Ok(2), table.find(Some("%vim")));
+        assert_eq!(Ok(3), table.find(Some("%?j8")));
+        assert!(table.find(Some("%4")).is_err());
+        assert!(table.find(Some("%?e")).is_err());
+    }
+
+    #[test]
+    fn removing_jobs_updates_current_and_previous() {
+        let mut table = table(&["one", "two", "three"]);
+        table.remove(3);
+        assert_eq!((Some(2), Some(1)), (table.current, table.previous));
+
+        table.remove(1);
+        assert_eq!((Some(2), None), (table.current, table.previous));
+
+        assert_eq!(
+            3,
+            table.add(2000, "four".to_string(), JobState::Stopped(libc::SIGTSTP))
+        );
+        assert_eq!('+', table.marker(3));
+        assert_eq!('-', table.marker(2));
+    }
+
+    #[test]
+    fn waiting_on_a_stopped_job_keeps_it() {
+        let mut shell = Shell::new();
+        shell.run_line("sleep 5 &");
+        let pgid = shell.jobs.get(1).unwrap().pgid;
+        // SAFETY: signals the child we just started.
+        unsafe { libc::kill(pgid, libc::SIGSTOP) };
+
+        let args = ["%1".to_string()];
+        assert_eq!(128 + libc::SIGSTOP, shell.wait_builtin(&args));
+        assert_eq!(
+            Some(JobState::Stopped(libc::SIGSTOP)),
+            shell.jobs.get(1).map(|job| job.state)
+        );
+        assert_eq!(128 + libc::SIGSTOP, shell.wait_builtin(&args));
+
+        // SAFETY: as above, then reaps it so no zombie is left behind.
+        unsafe {
+            libc::kill(pgid, libc::SIGKILL);
+            libc::waitpid(pgid, std::ptr::null_mut(), 0);
+        }
+    }
+
+    #[test]
+    fn jobs_are_formatted_like_bash() {
+        let mut table = table(&["sleep 100"]);
+        let job = table.get(1).unwrap();
+        assert_eq!(
+            "[1]+  Running                 sleep 100 &",
+            table.format(job, false)
+        );
+        assert_eq!(
+            "[1]+ 1000 Running                 sleep 100 &",
+            table.format(job, true)
+        );
+
+        table.get_mut(1).unwrap().state = JobState::Done(2);
+        let job = table.get(1).unwrap();
+        assert_eq!(
+            "[1]+  Exit 2                  sleep 100",
+            table.format(job, false)
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! The `set` and `shopt` builtins and the options they manage.
+
+use std::{env, fs};
+
+use crate::command::{Command, RedirectKind};
+use crate::shell::Shell;
+
+/// `set -o` option names and their single-letter flags, in the order
+/// `set -o` lists them.
+const SET_OPTIONS: &[(&str, Option<char>)] = &[
+    ("errexit", Some('e')),
+    ("noclobber", Some('C')),
+    ("noexec", Some('n')),
+    ("noglob", Some('f')),
+    ("nounset", Some('u')),
+    ("pipefail", None),
+    ("xtrace", Some('x')),
+];
+
+const SHOPT_OPTIONS: &[&str] = &["fuzzycomplete", "sourcepath"];
+
+/// Letters in `$-`, in the order bash prints them. `i` is not an option but
+/// marks an interactive shell.
+const FLAG_ORDER: &str = "efinuxC";
+
+/// Shell options. `pipefail` and `noglob` are tracked so scripts can set
+/// them, but the shell has no pipelines or pathname expansion for them to
+/// affect yet.
+#[derive(Debug)]
+pub struct Options {
+    pub errexit: bool,
+    pub noclobber: bool,
+    pub noexec: bool,
+    pub noglob: bool,
+    pub nounset: bool,
+    pub pipefail: bool,
+    pub xtrace: bool,
+    /// Command names complete ignoring case, and failing that from names
+    /// containing the typed characters in order.
+    pub fuzzycomplete: bool,
+    /// `source` searches `PATH` for names without a slash.
+    pub sourcepath: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            errexit: false,
+            noclobber: false,
+            noexec: false,
+            noglob: false,
+            nounset: false,
+            pipefail: false,
+            xtrace: false,
+            fuzzycomplete: false,
+            sourcepath: true,
+        }
+    }
+}
+
+impl Options {
+    fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "noclobber" => Some(&mut self.noclobber),
+            "noexec" => Some(&mut self.noexec),
+            "noglob" => Some(&mut self.noglob),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            "fuzzycomplete" => Some(&mut self.fuzzycomplete),
+            "sourcepath" => Some(&mut self.sourcepath),
+            _ => None,
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "noclobber" => Some(self.noclobber),
+            "noexec" => Some(self.noexec),
+            "noglob" => Some(self.noglob),
+            "nounset" => Some(self.nounset),
+            "pipefail" => Some(self.pipefail),
+            "xtrace" => Some(self.xtrace),
+            "fuzzycomplete" => Some(self.fuzzycomplete),
+            "sourcepath" => Some(self.sourcepath),
+            _ => None,
+        }
+    }
+
+    fn name_for_flag(flag: char) -> Option<&'static str> {
+        SET_OPTIONS
+            .iter()
+            .find(|(_, letter)| *letter == Some(flag))
+            .map(|(name, _)| *name)
+    }
+
+    /// The single-letter flags that are on, as in `$-`.
+    fn flags(&self, interactive: bool) -> String {
+        FLAG_ORDER
+            .chars()
+            .filter(|flag| match Self::name_for_flag(*flag) {
+                Some(name) => self.get(name) == Some(true),
+                None => *flag == 'i' && interactive,
+            })
+            .collect()
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+/// Quotes `word` for display only when it would otherwise be read back
+/// differently.
+fn quote_if_needed(word: &str) -> String {
+    let is_plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if is_plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+impl Shell {
+    /// The flags in `$-`.
+    pub(super) fn option_flags(&self) -> String {
+        self.options.flags(self.interactive)
+    }
+
+    /// Whether completion should match command names fuzzily.
+    pub fn fuzzy_completion(&self) -> bool {
+        self.options.fuzzycomplete
+    }
+
+    /// `set [-+efnuxC] [-+o option] [--] [arg ...]`. Arguments after the
+    /// options replace the positional parameters.
+    pub(super) fn set_builtin(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            self.print_variables();
+            return 0;
+        }
+
+        let mut args = args.iter();
+        let mut positional = None;
+        while let Some(arg) = args.next() {
+            let enable = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => {
+                    positional = Some(std::iter::once(arg).chain(args).cloned().collect());
+                    break;
+                }
+            };
+            if arg == "--" {
+                positional = Some(args.cloned().collect());
+                break;
+            }
+            if arg == "-" {
+                // `set -` ends the options and turns off -x
+                self.options.xtrace = false;
+                positional = Some(args.cloned().collect::<Vec<_>>()).filter(|p| !p.is_empty());
+                break;
+            }
+
+            for flag in arg.chars().skip(1) {
+                if flag == 'o' {
+                    let Some(name) = args.next() else {
+                        self.print_set_options(enable);
+                        continue;
+                    };
+                    match SET_OPTIONS.iter().find(|(option, _)| option == name) {
+                        Some((option, _)) => *self.options.get_mut(option).unwrap() = enable,
+                        None => {
+                            self.display_error(format!("set: {name}: invalid option name"));
+                            return 1;
+                        }
+                    }
+                    continue;
+                }
+                match Options::name_for_flag(flag) {
+                    Some(name) => *self.options.get_mut(name).unwrap() = enable,
+                    None => {
+                        let sign = if enable { '-' } else { '+' };
+                        self.display_error(format!("set: {sign}{flag}: invalid option"));
+                        self.display_error(
+                            "set: usage: set [-efnuxC] [-o option-name] [--] [arg ...]".to_string(),
+                        );
+                        return 2;
+                    }
+                }
+            }
+        }
+
+        if let Some(params) = positional {
+            self.positional_params = params;
+        }
+        0
+    }
+
+    /// `set -o` lists the options as a table; `set +o` as commands that
+    /// would recreate them.
+    fn print_set_options(&self, as_table: bool) {
+        for (name, _) in SET_OPTIONS {
+            let value = self.options.get(name).unwrap_or_default();
+            if as_table {
+                self.display_result(format!("{name:<15}\t{}", on_off(value)));
+            } else {
+                let sign = if value { '-' } else { '+' };
+                self.display_result(format!("set {sign}o {name}"));
+            }
+        }
+    }
+
+    /// Plain `set` lists every variable, shell and exported alike.
+    fn print_variables(&self) {
+        let mut variables: Vec<(String, String)> = env::vars().collect();
+        variables.extend(
+            self.variables
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        variables.sort();
+        for (name, value) in variables {
+            self.display_result(format!("{name}={}", quote_if_needed(&value)));
+        }
+    }
+
+    /// `shopt [-psuq] [-o] [optname ...]`.
+    pub(super) fn shopt_builtin(&mut self, args: &[String]) -> i32 {
+        let mut set = None;
+        let mut print = false;
+        let mut quiet = false;
+        let mut set_o = false;
+        let mut names = Vec::new();
+
+        for arg in args {
+            match arg.as_str() {
+                "-s" => set = Some(true),
+                "-u" => set = Some(false),
+                "-p" => print = true,
+                "-q" => quiet = true,
+                "-o" => set_o = true,
+                option if option.starts_with('-') => {
+                    self.display_error(format!("shopt: {option}: invalid option"));
+                    self.display_error(
+                        "shopt: usage: shopt [-pqsu] [-o] [optname ...]".to_string(),
+                    );
+                    return 2;
+                }
+                name => names.push(name),
+            }
+        }
+
+        let known: Vec<&str> = if set_o {
+            SET_OPTIONS.iter().map(|(name, _)| *name).collect()
+        } else {
+            SHOPT_OPTIONS.to_vec()
+        };
+        if names.is_empty() {
+            names = match set {
+                // `shopt -s` alone lists the options that are on
+                Some(value) => known
+                    .iter()
+                    .copied()
+                    .filter(|name| self.options.get(name) == Some(value))
+                    .collect(),
+                None => known.clone(),
+            };
+            set = None;
+        }
+
+        let mut status = 0;
+        for name in names {
+            if !known.contains(&name) {
+                self.display_error(format!("shopt: {name}: invalid shell option name"));
+                status = 1;
+                continue;
+            }
+            if let Some(value) = set {
+                *self.options.get_mut(name).unwrap() = value;
+                continue;
+            }
+
+            let value = self.options.get(name).unwrap_or_default();
+            if !value {
+                status = 1;
+            }
+            if quiet {
+                continue;
+            }
+            if print {
+                let flag = if value { 's' } else { 'u' };
+                let o = if set_o { " -o" } else { "" };
+                self.display_result(format!("shopt -{flag}{o} {name}"));
+            } else {
+                self.display_result(format!("{name:<15}\t{}", on_off(value)));
+            }
+        }
+        status
+    }
+
+    /// Prints `words` to stderr prefixed with the expanded `PS4`, for
+    /// `set -x`.
+    pub(super) fn trace(&mut self, words: &[String]) {
+        let prefix = self.expand_prompt_variable("PS4").text;
+        let line: Vec<String> = words.iter().map(|word| quote_if_needed(word)).collect();
+        self.display_error(format!("{prefix}{}", line.join(" ")));
+    }
+
+    /// With `set -C`, refuses a `>` redirection onto an existing regular
+    /// file.
+    pub(super) fn check_noclobber(&self, cmd: &Command) -> Result<(), String> {
+        let Command::EnviromentalModifier {
+            stdout_redirect,
+            stderr_redirect,
+        } = cmd
+        else {
+            return Ok(());
+        };
+        if !self.options.noclobber {
+            return Ok(());
+        }
+
+        for redirect in [stdout_redirect, stderr_redirect].into_iter().flatten() {
+            let exists = fs::metadata(&redirect.file_path).is_ok_and(|meta| meta.is_file());
+            if redirect.kind == RedirectKind::Truncate && exists {
+                return Err(format!(
+                    "{}: cannot overwrite existing file",
+                    redirect.file_path.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_follow_options() {
+        let mut options = Options::default();
+        assert_eq!("", options.flags(false));
+        assert_eq!("i", options.flags(true));
+
+        options.errexit = true;
+        options.nounset = true;
+        options.noclobber = true;
+        options.pipefail = true;
+        assert_eq!("eiuC", options.flags(true));
+    }
+
+    #[test]
+    fn words_are_quoted_for_display() {
+        assert_eq!("hello", quote_if_needed("hello"));
+        assert_eq!("--opt=a/b", quote_if_needed("--opt=a/b"));
+        assert_eq!("'a b'", quote_if_needed("a b"));
+        assert_eq!("''", quote_if_needed(""));
+        assert_eq!(r"'it'\''s'", quote_if_needed("it's"));
+    }
+}
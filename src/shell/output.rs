@@ -0,0 +1,731 @@
+//! `echo` and `printf`, and the backslash escapes they share.
+
+use crate::command::is_valid_name;
+use crate::shell::Shell;
+
+impl Shell {
+    /// `echo [-neE] [arg...]`. `-n` leaves off the newline, `-e` expands
+    /// backslash escapes and `-E` turns that back off. Only leading words
+    /// made up entirely of those letters are options.
+    pub(super) fn echo_builtin(&mut self, args: &[String]) -> i32 {
+        let mut newline = true;
+        let mut escapes = false;
+        let mut words = args.iter().peekable();
+        while let Some(arg) = words.next_if(|arg| is_echo_option(arg)) {
+            for flag in arg.chars().skip(1) {
+                match flag {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+        }
+
+        let mut text = words.map(String::as_str).collect::<Vec<_>>().join(" ");
+        if escapes {
+            let (expanded, stop) = unescape(&text, Escapes::Echo);
+            text = expanded;
+            newline &= !stop;
+        }
+        if newline {
+            text.push('\n');
+        }
+        self.display_raw(&text);
+        0
+    }
+
+    /// `printf [-v var] format [arg...]`. The format is reused until every
+    /// argument has been consumed; missing arguments count as empty strings
+    /// or zero. With `-v`, the output is assigned to `var` instead.
+    pub(super) fn printf_builtin(&mut self, args: &[String]) -> i32 {
+        let mut args = args;
+        let mut target = None;
+        if let Some(first) = args.first()
+            && first == "-v"
+        {
+            match args.get(1) {
+                Some(name) if is_valid_name(name) => target = Some(name.clone()),
+                Some(name) => {
+                    self.display_error(format!("printf: `{name}': not a valid identifier"));
+                    return 2;
+                }
+                None => {
+                    self.display_error("printf: -v: option requires an argument".to_string());
+                    self.display_error(PRINTF_USAGE.to_string());
+                    return 2;
+                }
+            }
+            args = &args[2..];
+        }
+        if args.first().is_some_and(|arg| arg == "--") {
+            args = &args[1..];
+        }
+        let Some((format, args)) = args.split_first() else {
+            self.display_error(PRINTF_USAGE.to_string());
+            return 2;
+        };
+
+        let mut formatter = Formatter::new(args);
+        formatter.run(format);
+        for error in &formatter.errors {
+            self.display_error(format!("printf: {error}"));
+        }
+        match target {
+            Some(name) => self.set_variable(&name, &formatter.output),
+            None => self.display_raw(&formatter.output),
+        }
+        if formatter.errors.is_empty() { 0 } else { 1 }
+    }
+}
+
+const PRINTF_USAGE: &str = "printf: usage: printf [-v var] format [arguments]";
+
+fn is_echo_option(arg: &str) -> bool {
+    arg.strip_prefix('-')
+        .is_some_and(|flags| !flags.is_empty() && flags.chars().all(|c| "neE".contains(c)))
+}
+
+/// Which backslash escapes are recognised.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escapes {
+    /// A `printf` format: octal is `\nnn`, and `\"` is a quote.
+    Format,
+    /// `echo -e`: octal is `\0nnn`, and `\c` ends the output.
+    Echo,
+    /// A `%b` argument: like `echo -e`, but `\nnn` is octal too.
+    Argument,
+}
+
+/// Expands the backslash escapes in `text`. Also returns whether a `\c`
+/// cut the text short. Unknown escapes are kept as they are.
+fn unescape(text: &str, escapes: Escapes) -> (String, bool) {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            out.push('\\');
+            break;
+        };
+        match escape {
+            'a' => out.push('\x07'),
+            'b' => out.push('\x08'),
+            'e' | 'E' => out.push('\x1b'),
+            'f' => out.push('\x0c'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'v' => out.push('\x0b'),
+            '\\' => out.push('\\'),
+            '"' if escapes == Escapes::Format => out.push('"'),
+            'c' if escapes != Escapes::Format => return (out, true),
+            '0'..='7' => {
+                let mut digits = match (escapes, escape) {
+                    (Escapes::Echo | Escapes::Argument, '0') => String::new(),
+                    (Escapes::Echo, _) => {
+                        out.push('\\');
+                        out.push(escape);
+                        continue;
+                    }
+                    _ => escape.to_string(),
+                };
+                while digits.len() < 3
+                    && let Some(digit) = chars.next_if(|c| c.is_digit(8))
+                {
+                    digits.push(digit);
+                }
+                let value = u32::from_str_radix(&digits, 8).unwrap_or_default();
+                out.push(char::from_u32(value & 0xff).unwrap_or_default());
+            }
+            'x' | 'u' | 'U' => {
+                let max = match escape {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let mut digits = String::new();
+                while digits.len() < max
+                    && let Some(digit) = chars.next_if(|c| c.is_ascii_hexdigit())
+                {
+                    digits.push(digit);
+                }
+                match u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    Some(c) => out.push(c),
+                    None => {
+                        out.push('\\');
+                        out.push(escape);
+                        out.push_str(&digits);
+                    }
+                }
+            }
+            other => {
+                out.push('\\');
+                out.push(other);
+            }
+        }
+    }
+    (out, false)
+}
+
+/// Runs a `printf` format over its arguments.
+struct Formatter<'a> {
+    args: &'a [String],
+    next: usize,
+    output: String,
+    errors: Vec<String>,
+    /// Set by `\c` in a `%b` argument, which ends all output.
+    stopped: bool,
+}
+
+/// A parsed `%` conversion.
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(args: &'a [String]) -> Self {
+        Self {
+            args,
+            next: 0,
+            output: String::new(),
+            errors: Vec::new(),
+            stopped: false,
+        }
+    }
+
+    /// Formats `format` once, then again while arguments remain, as long as
+    /// each pass uses some of them.
+    fn run(&mut self, format: &str) {
+        loop {
+            let before = self.next;
+            if !self.pass(format) || self.stopped {
+                return;
+            }
+            if self.next >= self.args.len() || self.next == before {
+                return;
+            }
+        }
+    }
+
+    /// One pass over `format`. Returns `false` if it has a bad conversion.
+    fn pass(&mut self, format: &str) -> bool {
+        let mut rest = format;
+        while let Some(percent) = rest.find('%') {
+            let (text, _) = unescape(&rest[..percent], Escapes::Format);
+            self.output.push_str(&text);
+            rest = &rest[percent + 1..];
+            if let Some(after) = rest.strip_prefix('%') {
+                self.output.push('%');
+                rest = after;
+                continue;
+            }
+
+            let Some((spec, conversion, after)) = self.parse_spec(rest) else {
+                return false;
+            };
+            rest = after;
+            let Some(conversion) = conversion else {
+                self.errors
+                    .push("`%': missing format character".to_string());
+                return false;
+            };
+            if !self.convert(&spec, conversion) {
+                return false;
+            }
+            if self.stopped {
+                return true;
+            }
+        }
+        let (text, _) = unescape(rest, Escapes::Format);
+        self.output.push_str(&text);
+        true
+    }
+
+    /// Reads the flags, width and precision after a `%`, taking `*` values
+    /// from the arguments, and the conversion character. Returns `None` if
+    /// the width or precision is out of range.
+    fn parse_spec<'f>(&mut self, text: &'f str) -> Option<(Spec, Option<char>, &'f str)> {
+        let mut spec = Spec::default();
+        let mut chars = text.char_indices().peekable();
+        while let Some((_, flag)) = chars.next_if(|(_, c)| "-+ #0".contains(*c)) {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                _ => spec.zero = true,
+            }
+        }
+
+        if chars.next_if(|(_, c)| *c == '*').is_some() {
+            let width = self.next_integer();
+            spec.left |= width < 0;
+            spec.width = self.field_size(&width.to_string(), width.unsigned_abs())?;
+        } else {
+            let digits = take_digits(text, &mut chars);
+            spec.width = self.field_size(digits, parse_digits(digits))?;
+        }
+        if chars.next_if(|(_, c)| *c == '.').is_some() {
+            spec.precision = if chars.next_if(|(_, c)| *c == '*').is_some() {
+                let precision = self.next_integer();
+                match u64::try_from(precision) {
+                    Ok(value) => Some(self.field_size(&precision.to_string(), value)?),
+                    Err(_) => None,
+                }
+            } else {
+                let digits = take_digits(text, &mut chars);
+                Some(self.field_size(digits, parse_digits(digits))?)
+            };
+        }
+
+        Some(match chars.next() {
+            Some((index, conversion)) => (
+                spec,
+                Some(conversion),
+                &text[index + conversion.len_utf8()..],
+            ),
+            None => (spec, None, ""),
+        })
+    }
+
+    /// Checks a width or precision against the largest C's `printf` takes,
+    /// reporting it as `text` when it is too large.
+    fn field_size(&mut self, text: &str, value: u64) -> Option<usize> {
+        if value > i32::MAX as u64 {
+            self.errors
+                .push(format!("{text}: Numerical result out of range"));
+            return None;
+        }
+        Some(value as usize)
+    }
+
+    /// Formats the next argument for `conversion`. Returns `false` if it
+    /// isn't a conversion `printf` knows.
+    fn convert(&mut self, spec: &Spec, conversion: char) -> bool {
+        let text = match conversion {
+            's' => {
+                let arg = self.next_arg();
+                truncate(arg, spec.precision)
+            }
+            'b' => {
+                let (arg, stop) = unescape(self.next_arg(), Escapes::Argument);
+                self.stopped = stop;
+                truncate(&arg, spec.precision)
+            }
+            'q' => quote(self.next_arg()),
+            'c' => self
+                .next_arg()
+                .chars()
+                .next()
+                .map(String::from)
+                .unwrap_or_default(),
+            'd' | 'i' => {
+                let value = self.next_integer();
+                let sign = sign(value < 0, spec);
+                self.pad_number(spec, sign, &value.unsigned_abs().to_string(), true);
+                return true;
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                let value = self.next_integer() as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (value.to_string(), ""),
+                    'x' => (format!("{value:x}"), "0x"),
+                    'X' => (format!("{value:X}"), "0X"),
+                    _ => (format!("{value:o}"), "0"),
+                };
+                let prefix = if spec.alternate && value != 0 {
+                    prefix
+                } else {
+                    ""
+                };
+                self.pad_number(spec, prefix, &digits, true);
+                return true;
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = self.next_float();
+                let sign = sign(value.is_sign_negative() && value != 0.0, spec);
+                let digits = format_float(value.abs(), conversion, spec);
+                self.pad_number(spec, sign, &digits, false);
+                return true;
+            }
+            other => {
+                self.errors
+                    .push(format!("`%{other}': missing format character"));
+                return false;
+            }
+        };
+        self.output.push_str(&pad(&text, spec.width, spec.left));
+        true
+    }
+
+    /// Pads a number to the width, with zeros after the sign for `0`
+    /// unless it is left-justified, not finite or, for integers, has a
+    /// precision.
+    fn pad_number(&mut self, spec: &Spec, sign: &str, digits: &str, integer: bool) {
+        let digits = match spec.precision {
+            Some(precision) if integer && digits.len() < precision => {
+                format!("{}{digits}", "0".repeat(precision - digits.len()))
+            }
+            Some(0) if integer && digits == "0" => String::new(),
+            _ => digits.to_string(),
+        };
+        let zero_fill = spec.zero
+            && !spec.left
+            && !(integer && spec.precision.is_some())
+            && digits.starts_with(|c: char| c.is_ascii_digit());
+        let len = sign.len() + digits.len();
+        let text = if zero_fill && len < spec.width {
+            format!("{sign}{}{digits}", "0".repeat(spec.width - len))
+        } else {
+            pad(&format!("{sign}{digits}"), spec.width, spec.left)
+        };
+        self.output.push_str(&text);
+    }
+
+    fn next_arg(&mut self) -> &'a str {
+        let arg = self.args.get(self.next).map_or("", String::as_str);
+        self.next += 1;
+        arg
+    }
+
+    fn next_integer(&mut self) -> i64 {
+        let arg = self.next_arg();
+        parse_integer(arg).unwrap_or_else(|value| {
+            self.errors.push(format!("{arg}: invalid number"));
+            value
+        })
+    }
+
+    fn next_float(&mut self) -> f64 {
+        let arg = self.next_arg();
+        let trimmed = arg.trim();
+        if trimmed.is_empty() {
+            return 0.0;
+        }
+        if let Some(code) = char_code(trimmed) {
+            return code as f64;
+        }
+        trimmed.parse().unwrap_or_else(|_| {
+            self.errors.push(format!("{arg}: invalid number"));
+            parse_integer(trimmed).unwrap_or_else(|value| value) as f64
+        })
+    }
+}
+
+/// Takes the digits at the front of `chars`, which walks over `text`.
+fn take_digits<'f>(
+    text: &'f str,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> &'f str {
+    let start = chars.peek().map_or(text.len(), |(index, _)| *index);
+    while chars.next_if(|(_, c)| c.is_ascii_digit()).is_some() {}
+    let end = chars.peek().map_or(text.len(), |(index, _)| *index);
+    &text[start..end]
+}
+
+/// The value of a run of decimal digits, saturating when it doesn't fit.
+fn parse_digits(digits: &str) -> u64 {
+    if digits.is_empty() {
+        return 0;
+    }
+    digits.parse().unwrap_or(u64::MAX)
+}
+
+fn sign(negative: bool, spec: &Spec) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+fn pad(text: &str, width: usize, left: bool) -> String {
+    let fill = " ".repeat(width.saturating_sub(text.chars().count()));
+    if left {
+        format!("{text}{fill}")
+    } else {
+        format!("{fill}{text}")
+    }
+}
+
+fn truncate(text: &str, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text.to_string(),
+    }
+}
+
+/// `'c` or `"c` stands for the character code of `c`.
+fn char_code(arg: &str) -> Option<i64> {
+    let rest = arg.strip_prefix(['\'', '"'])?;
+    Some(rest.chars().next().map_or(0, |c| c as i64))
+}
+
+/// Parses an integer argument the way C does: `0x` for hex, a leading `0`
+/// for octal, or a quoted character. On failure, returns the number the
+/// leading digits make up.
+fn parse_integer(arg: &str) -> Result<i64, i64> {
+    let text = arg.trim_start();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    if let Some(code) = char_code(text) {
+        return Ok(code);
+    }
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (radix, digits) = if let Some(hex) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    {
+        (16, hex)
+    } else if unsigned.len() > 1 && unsigned.starts_with('0') {
+        (8, &unsigned[1..])
+    } else {
+        (10, unsigned)
+    };
+
+    let valid = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let value = i64::from_str_radix(&digits[..valid], radix).unwrap_or(if valid == 0 {
+        0
+    } else {
+        i64::MAX
+    });
+    let value = if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    };
+    if valid == digits.len() && valid > 0 {
+        Ok(value)
+    } else {
+        Err(value)
+    }
+}
+
+/// Formats a non-negative float for `%f`, `%e` or `%g`, with C's exponent
+/// style.
+fn format_float(value: f64, conversion: char, spec: &Spec) -> String {
+    let upper = conversion.is_ascii_uppercase();
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        return if upper {
+            text.to_uppercase()
+        } else {
+            text.to_string()
+        };
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    let text = match conversion.to_ascii_lowercase() {
+        'f' => format!("{value:.precision$}"),
+        'e' => exponential(value, precision),
+        _ => {
+            let precision = precision.max(1);
+            let exponent = decimal_exponent(value, precision);
+            let text = if exponent < -4 || exponent >= precision as i32 {
+                exponential(value, precision - 1)
+            } else {
+                let decimals = (precision as i32 - 1 - exponent) as usize;
+                format!("{value:.decimals$}")
+            };
+            if spec.alternate {
+                text
+            } else {
+                trim_fraction(&text)
+            }
+        }
+    };
+    let text = if spec.alternate && !text.contains('.') && !text.contains('e') {
+        format!("{text}.")
+    } else {
+        text
+    };
+    if upper { text.to_uppercase() } else { text }
+}
+
+/// `value` as `d.ddde+XX`.
+fn exponential(value: f64, precision: usize) -> String {
+    let text = format!("{value:.precision$e}");
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exponent.abs())
+}
+
+/// The exponent `value` has once rounded to `precision` significant digits.
+fn decimal_exponent(value: f64, precision: usize) -> i32 {
+    if value == 0.0 {
+        return 0;
+    }
+    let text = format!("{value:.0$e}", precision - 1);
+    text.split_once('e')
+        .and_then(|(_, exponent)| exponent.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Drops trailing zeros after the decimal point, and the point if nothing
+/// is left after it, keeping any exponent.
+fn trim_fraction(text: &str) -> String {
+    let (number, exponent) = match text.find('e') {
+        Some(index) => text.split_at(index),
+        None => (text, ""),
+    };
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    };
+    format!("{number}{exponent}")
+}
+
+/// Quotes `text` so the shell would read it back as one word.
+fn quote(text: &str) -> String {
+    if text.is_empty() {
+        return "''".to_string();
+    }
+    if text.chars().any(|c| c.is_control()) {
+        let mut quoted = String::from("$'");
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\x1b' => quoted.push_str("\\E"),
+                '\'' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+
+    let mut quoted = String::with_capacity(text.len());
+    for (index, c) in text.chars().enumerate() {
+        let safe = c.is_alphanumeric() || "_./:=+@%,-".contains(c) || (c == '~' && index > 0);
+        if !safe {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn printf(format: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut formatter = Formatter::new(&args);
+        formatter.run(format);
+        formatter.output
+    }
+
+    #[test]
+    fn escapes_follow_echo_or_printf_rules() {
+        assert_eq!(
+            ("a\tb\n".to_string(), false),
+            unescape(r"a\tb\n", Escapes::Echo)
+        );
+        assert_eq!(("A".to_string(), false), unescape(r"\0101", Escapes::Echo));
+        assert_eq!(("A".to_string(), false), unescape(r"\101", Escapes::Format));
+        assert_eq!(
+            ("A".to_string(), false),
+            unescape(r"\101", Escapes::Argument)
+        );
+        assert_eq!(
+            (r"\101".to_string(), false),
+            unescape(r"\101", Escapes::Echo)
+        );
+        assert_eq!(("ab".to_string(), true), unescape(r"ab\cde", Escapes::Echo));
+        assert_eq!(
+            ("é\\q".to_string(), false),
+            unescape(r"é\q", Escapes::Format)
+        );
+        assert!(is_echo_option("-neE"));
+        assert!(!is_echo_option("-nx"));
+        assert!(!is_echo_option("-"));
+    }
+
+    #[test]
+    fn conversions_take_width_precision_and_flags() {
+        assert_eq!("[  abc|ab   ]", printf("[%5s|%-5.2s]", &["abc", "abc"]));
+        assert_eq!(
+            "-0042 +7 0x1f 017 FF",
+            printf("%05d %+d %#x %#o %X", &["-42", "7", "31", "15", "255"])
+        );
+        assert_eq!("   07|65", printf("%*.*d|%d", &["5", "2", "7", "'A"]));
+        assert_eq!(
+            "3.14 1.500000e+00 1e-05 100000 1.5E+06",
+            printf(
+                "%.2f %e %g %g %G",
+                &["3.14159", "1.5", "0.00001", "100000", "1500000"]
+            )
+        );
+        assert_eq!(r"x\ y it\'s ''", printf("%q %q %q", &["x y", "it's", ""]));
+        assert_eq!("c|%", printf("%c|%%", &["cat"]));
+    }
+
+    #[test]
+    fn format_is_reused_for_extra_arguments() {
+        assert_eq!(
+            "a=1\nb=2\nc=\n",
+            printf("%s=%s\\n", &["a", "1", "b", "2", "c"])
+        );
+        assert_eq!("hi\n", printf("hi\\n", &["unused"]));
+        assert_eq!("x", printf("%b%s", &["x\\cy", "z"]));
+    }
+
+    #[test]
+    fn bad_numbers_are_reported() {
+        let args = vec!["12abc".to_string()];
+        let mut formatter = Formatter::new(&args);
+        formatter.run("%d");
+        assert_eq!("12", formatter.output);
+        assert_eq!(vec!["12abc: invalid number".to_string()], formatter.errors);
+        assert_eq!(Ok(-16), parse_integer("-0x10"));
+        assert_eq!(Ok(8), parse_integer("010"));
+    }
+
+    #[test]
+    fn large_widths_are_padded_or_rejected() {
+        let padded = printf("%70000s|%-*d|", &["x", "-70000", "1"]);
+        assert_eq!(70000 + 1 + 70000 + 1, padded.len());
+        assert!(padded.starts_with("   ") && padded.contains("x|1 "));
+
+        for format in ["%3000000000s", "%.99999999999999999999s", "%*d"] {
+            let args = vec!["3000000000".to_string(), "1".to_string()];
+            let mut formatter = Formatter::new(&args);
+            formatter.run(format);
+            assert_eq!("", formatter.output, "{format}");
+            assert_eq!(1, formatter.errors.len(), "{format}");
+            assert!(formatter.errors[0].ends_with(": Numerical result out of range"));
+        }
+    }
+}
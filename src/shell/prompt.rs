@@ -0,0 +1,385 @@
+//! `PS1`, `PS2` and `PS4`: bash-style backslash escapes plus parameter and
+//! command substitution.
+
+use std::{
+    env,
+    ffi::{CStr, CString},
+    fs::{self, OpenOptions},
+    io, mem,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::command::{Lookup, RedirectInfo, RedirectKind};
+use crate::shell::Shell;
+
+const DEFAULT_PROMPTS: [(&str, &str); 3] = [("PS1", "$ "), ("PS2", "> "), ("PS4", "+ ")];
+
+/// An expanded prompt. `text` is what the line editor measures and lays out;
+/// `styled` also contains the non-printing parts wrapped in `\[...\]`, such
+/// as colour codes, and is what gets displayed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Prompt {
+    pub text: String,
+    pub styled: String,
+}
+
+impl Prompt {
+    fn push(&mut self, c: char, printing: bool) {
+        if printing {
+            self.text.push(c);
+        }
+        self.styled.push(c);
+    }
+
+    fn push_str(&mut self, s: &str, printing: bool) {
+        if printing {
+            self.text.push_str(s);
+        }
+        self.styled.push_str(s);
+    }
+}
+
+impl Shell {
+    /// Gives `PS1`, `PS2` and `PS4` their defaults unless they were
+    /// inherited.
+    pub(super) fn init_prompt_variables(&mut self) {
+        for (name, value) in DEFAULT_PROMPTS {
+            if self.variable(name).is_none() {
+                self.set_variable(name, value);
+            }
+        }
+    }
+
+    /// Runs `PROMPT_COMMAND` and expands `PS1`.
+    pub fn prompt(&mut self) -> Prompt {
+        if let Some(command) = self.variable("PROMPT_COMMAND") {
+            self.run_nested(&command);
+        }
+        self.expand_prompt_variable("PS1")
+    }
+
+    /// Expands `PS2`, shown while a line continues onto the next.
+    pub fn continuation_prompt(&mut self) -> Prompt {
+        self.expand_prompt_variable("PS2")
+    }
+
+    pub(super) fn expand_prompt_variable(&mut self, name: &str) -> Prompt {
+        let template = self.variable(name).unwrap_or_default();
+        self.expand_prompt(&template)
+    }
+
+    /// Expands the escapes and substitutions in a prompt string in a single
+    /// pass, so text produced by one is never expanded again.
+    fn expand_prompt(&mut self, template: &str) -> Prompt {
+        let mut prompt = Prompt::default();
+        let mut printing = true;
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let Some(escape) = chars.next() else {
+                        prompt.push('\\', printing);
+                        break;
+                    };
+                    match escape {
+                        '[' => printing = false,
+                        ']' => printing = true,
+                        'D' if chars.peek() == Some(&'{') => {
+                            chars.next();
+                            let format: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                            let format = if format.is_empty() { "%X" } else { &format };
+                            prompt.push_str(&format_time(format, SystemTime::now()), printing);
+                        }
+                        '0'..='7' => {
+                            let mut digits = escape.to_string();
+                            while digits.len() < 3 && chars.peek().is_some_and(|c| c.is_digit(8)) {
+                                digits.push(chars.next().unwrap());
+                            }
+                            let value = u32::from_str_radix(&digits, 8).unwrap_or_default();
+                            if let Some(c) = char::from_u32(value) {
+                                prompt.push(c, printing);
+                            }
+                        }
+                        other => match self.prompt_escape(other) {
+                            Some(text) => prompt.push_str(&text, printing),
+                            None => {
+                                prompt.push('\\', printing);
+                                prompt.push(other, printing);
+                            }
+                        },
+                    }
+                }
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    let mut depth = 1;
+                    let mut command = String::new();
+                    for c in chars.by_ref() {
+                        match c {
+                            '(' => depth += 1,
+                            ')' => depth -= 1,
+                            _ => {}
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                        command.push(c);
+                    }
+                    prompt.push_str(&self.capture_output(&command), printing);
+                }
+                '$' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    prompt.push_str(&self.variable(&name).unwrap_or_default(), printing);
+                }
+                '$' if chars
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') =>
+                {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    prompt.push_str(&self.variable(&name).unwrap_or_default(), printing);
+                }
+                '$' if chars
+                    .peek()
+                    .is_some_and(|c| matches!(c, '?' | '$' | '#' | '-') || c.is_ascii_digit()) =>
+                {
+                    let name = chars.next().unwrap().to_string();
+                    prompt.push_str(&self.variable(&name).unwrap_or_default(), printing);
+                }
+                other => prompt.push(other, printing),
+            }
+        }
+        prompt
+    }
+
+    /// The text for a single-letter prompt escape, or `None` if it isn't
+    /// one.
+    fn prompt_escape(&self, escape: char) -> Option<String> {
+        let text = match escape {
+            'a' => "\x07".to_string(),
+            'e' => "\x1b".to_string(),
+            'n' => "\n".to_string(),
+            'r' => "\r".to_string(),
+            '\\' => "\\".to_string(),
+            'u' => user_name(),
+            'h' => host_name()
+                .split('.')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            'H' => host_name(),
+            's' => shell_name(),
+            'v' | 'V' => env!("CARGO_PKG_VERSION").to_string(),
+            'w' => self.abbreviated_working_dir(),
+            'W' => {
+                let dir = self.abbreviated_working_dir();
+                match dir.rsplit_once('/') {
+                    Some((_, name)) if !name.is_empty() => name.to_string(),
+                    _ => dir,
+                }
+            }
+            'j' => self.job_count().to_string(),
+            '?' => self.last_status.to_string(),
+            '$' => prompt_sign().to_string(),
+            'd' => format_time("%a %b %d", SystemTime::now()),
+            't' => format_time("%H:%M:%S", SystemTime::now()),
+            'T' => format_time("%I:%M:%S", SystemTime::now()),
+            '@' => format_time("%I:%M %p", SystemTime::now()),
+            'A' => format_time("%H:%M", SystemTime::now()),
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    /// The working directory with `$HOME` shown as `~`.
+    fn abbreviated_working_dir(&self) -> String {
+        let dir = self.working_dir.display().to_string();
+        abbreviate_home(dir, self.variable("HOME").as_deref())
+    }
+
+    /// Runs `line` in this shell and returns what it wrote to stdout, minus
+    /// trailing newlines, like `$(...)`. Tracing is off while it runs, so a
+    /// substitution in `PS4` can't recurse.
+    pub(super) fn capture_output(&mut self, line: &str) -> String {
+        let Ok(path) = create_capture_file() else {
+            return String::new();
+        };
+
+        let outer_capture = self
+            .capture
+            .replace(RedirectInfo::new(path.clone(), RedirectKind::Append));
+        let xtrace = mem::replace(&mut self.options.xtrace, false);
+        self.run_nested(line);
+        self.options.xtrace = xtrace;
+        self.capture = outer_capture;
+
+        let output = fs::read_to_string(&path).unwrap_or_default();
+        _ = fs::remove_file(&path);
+        output.trim_end_matches('\n').to_string()
+    }
+}
+
+/// Creates an empty file for a capture that only this user can read, under
+/// a name no other capture uses, even a nested one. It is never an existing
+/// file or symlink someone else planted.
+fn create_capture_file() -> io::Result<PathBuf> {
+    static CAPTURES: AtomicUsize = AtomicUsize::new(0);
+    for _ in 0..100 {
+        let capture = CAPTURES.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("shell-capture-{}-{capture}", process::id()));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::ErrorKind::AlreadyExists.into())
+}
+
+pub(super) fn abbreviate_home(dir: String, home: Option<&str>) -> String {
+    match home {
+        Some(home) if !home.is_empty() && home != "/" => match dir.strip_prefix(home) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{rest}"),
+            _ => dir,
+        },
+        _ => dir,
+    }
+}
+
+/// `#` for root, `$` for everyone else.
+fn prompt_sign() -> &'static str {
+    // SAFETY: geteuid has no preconditions.
+    if unsafe { libc::geteuid() } == 0 {
+        "#"
+    } else {
+        "$"
+    }
+}
+
+fn user_name() -> String {
+    if let Ok(user) = env::var("USER") {
+        return user;
+    }
+    // SAFETY: getpwuid returns null or a pointer to a static record that
+    // stays valid until the next call; the name is copied out right away.
+    unsafe {
+        let entry = libc::getpwuid(libc::getuid());
+        if entry.is_null() {
+            return String::new();
+        }
+        CStr::from_ptr((*entry).pw_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer length is passed along, and it is zeroed so the
+    // result is NUL-terminated even if truncated.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) } != 0 {
+        return String::new();
+    }
+    CStr::from_bytes_until_nul(&buf)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn shell_name() -> String {
+    let arg0 = env::args().next().unwrap_or_default();
+    let name = arg0.rsplit('/').next().unwrap_or_default();
+    name.trim_start_matches('-').to_string()
+}
+
+/// Formats `time` as local time with `strftime`.
+pub(super) fn format_time(format: &str, time: SystemTime) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let mut buf = [0u8; 128];
+    // SAFETY: `tm` is filled in by localtime_r before strftime reads it,
+    // and strftime is given the buffer's length.
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs()) as libc::time_t;
+    let len = unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes_are_expanded() {
+        let mut shell = Shell::new();
+        shell.last_status = 3;
+        shell.set_variable("NAME", "world");
+
+        let prompt = shell.expand_prompt(r"[\?] hi $NAME ${NAME}! \\ \q\$ ");
+        let sign = prompt_sign();
+        assert_eq!(format!(r"[3] hi world world! \ \q{sign} "), prompt.text);
+    }
+
+    #[test]
+    fn non_printing_sequences_are_only_styled() {
+        let mut shell = Shell::new();
+        let prompt = shell.expand_prompt(r"\[\e[32m\]> \[\e[0m\]");
+        assert_eq!("> ", prompt.text);
+        assert_eq!("\x1b[32m> \x1b[0m", prompt.styled);
+    }
+
+    #[test]
+    fn home_is_abbreviated() {
+        let home = Some("/home/user");
+        assert_eq!(
+            "~/src/app",
+            abbreviate_home("/home/user/src/app".into(), home)
+        );
+        assert_eq!("~", abbreviate_home("/home/user".into(), home));
+        assert_eq!(
+            "/home/username",
+            abbreviate_home("/home/username".into(), home)
+        );
+        assert_eq!("/tmp", abbreviate_home("/tmp".into(), None));
+
+        let mut shell = Shell::new();
+        shell.working_dir = "/srv/app".into();
+        assert_eq!("app", shell.expand_prompt(r"\W").text);
+    }
+
+    #[test]
+    fn command_substitution_captures_stdout() {
+        let mut shell = Shell::new();
+        assert_eq!("<hi there>", shell.expand_prompt("<$(echo hi there)>").text);
+    }
+
+    #[test]
+    fn capture_files_are_private_and_unique() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let first = create_capture_file().unwrap();
+        let second = create_capture_file().unwrap();
+        assert_ne!(first, second);
+        let mode = fs::metadata(&first).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+    }
+}
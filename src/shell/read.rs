@@ -0,0 +1,576 @@
+//! `read`: one line of input, split on `IFS` into variables.
+//!
+//! Input is read a byte at a time straight from the file descriptor, so
+//! nothing past the delimiter is consumed and the rest is left for whatever
+//! reads next. The exception is stdin when it isn't a terminal: the line
+//! editor reads that through std's buffered `Stdin`, which may already hold
+//! the lines after the one being run, so `read` takes its bytes from there
+//! too.
+
+use std::{
+    io::{self, BufRead, IsTerminal, StdinLock, Write},
+    mem,
+    time::{Duration, Instant},
+};
+
+use libc::c_int;
+
+use crate::command::{Lookup, is_valid_name};
+use crate::shell::{
+    Shell,
+    dirs::describe,
+    traps::{CatchInterrupts, interrupt_pending},
+};
+
+const READ_USAGE: &str = "read: usage: read [-rs] [-a array] [-d delim] [-n nchars] [-p prompt] [-t timeout] [-u fd] [name ...]";
+
+/// The status when `-t` runs out, as if killed by `SIGALRM`.
+const TIMEOUT_STATUS: i32 = 128 + libc::SIGALRM;
+
+impl Shell {
+    /// `read [-rs] [-a array] [-d delim] [-n nchars] [-p prompt] [-t timeout]
+    /// [-u fd] [name...]`. Each name gets one field of the line and the last
+    /// gets whatever is left; without names the whole line goes to `REPLY`.
+    /// With `-a`, every field becomes an element of `array` instead.
+    /// Returns 1 at end of input, so `read` can end a loop. `IFS` is taken
+    /// from `env_overrides` first, so `IFS=: read` works.
+    pub(super) fn read_builtin(
+        &mut self,
+        args: &[String],
+        env_overrides: &[(String, String)],
+    ) -> i32 {
+        let options = match ReadOptions::parse(args) {
+            Ok(options) => options,
+            Err((message, status)) => {
+                self.display_error(format!("read: {message}"));
+                if status == 2 {
+                    self.display_error(READ_USAGE.to_string());
+                }
+                return status;
+            }
+        };
+        if let Some(name) = options
+            .array
+            .iter()
+            .chain(&options.names)
+            .find(|name| !is_valid_name(name))
+        {
+            self.display_error(format!("read: `{name}': not a valid identifier"));
+            return 1;
+        }
+
+        // SAFETY: fcntl and isatty only look at the descriptor.
+        let (open, terminal) = unsafe {
+            (
+                libc::fcntl(options.fd, libc::F_GETFD) != -1,
+                libc::isatty(options.fd) == 1,
+            )
+        };
+        if !open {
+            self.display_error(format!(
+                "read: {}: invalid file descriptor: Bad file descriptor",
+                options.fd
+            ));
+            return 1;
+        }
+        if options.timeout == Some(Duration::ZERO) {
+            return if wait_for_input(options.fd, Some(Instant::now())).is_ok() {
+                0
+            } else {
+                1
+            };
+        }
+        if terminal && let Some(prompt) = &options.prompt {
+            let mut stderr = io::stderr();
+            _ = stderr.write_all(prompt.as_bytes());
+            _ = stderr.flush();
+        }
+
+        let (line, end) = {
+            let unbuffered = options.count.is_some() || options.delimiter != b'\n';
+            let _mode = terminal
+                .then(|| TerminalMode::set(options.fd, options.silent, unbuffered))
+                .flatten();
+            let _interrupts = CatchInterrupts::new();
+            read_line(&options)
+        };
+        let status = match end {
+            End::Delimiter => 0,
+            End::Eof => 1,
+            End::TimedOut => TIMEOUT_STATUS,
+            End::Interrupted => {
+                if terminal {
+                    _ = io::stderr().write_all(b"\n");
+                }
+                return 130;
+            }
+            End::Failed(err) => {
+                self.display_error(format!(
+                    "read: read error: {}: {}",
+                    options.fd,
+                    describe(&err)
+                ));
+                return 1;
+            }
+        };
+
+        let ifs = env_overrides
+            .iter()
+            .rev()
+            .find(|(name, _)| name == "IFS")
+            .map(|(_, value)| value.clone())
+            .or_else(|| self.variable("IFS"))
+            .unwrap_or_else(|| " \t\n".to_string());
+        if let Some(array) = &options.array {
+            self.set_array(array, split_fields(&line, ifs.as_bytes(), None));
+        } else if options.names.is_empty() {
+            self.set_variable("REPLY", &text(&line));
+        } else {
+            let fields = split_fields(&line, ifs.as_bytes(), Some(options.names.len()));
+            for (name, value) in options.names.iter().zip(fields) {
+                self.set_variable(name, &value);
+            }
+        }
+        status
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct ReadOptions {
+    raw: bool,
+    silent: bool,
+    array: Option<String>,
+    prompt: Option<String>,
+    timeout: Option<Duration>,
+    count: Option<usize>,
+    delimiter: u8,
+    fd: c_int,
+    names: Vec<String>,
+}
+
+impl ReadOptions {
+    /// Parses the options, which may be grouped as in `-rp prompt` or have
+    /// their value attached as in `-d:`. Errors come with their status.
+    fn parse(args: &[String]) -> Result<Self, (String, i32)> {
+        let mut options = Self {
+            raw: false,
+            silent: false,
+            array: None,
+            prompt: None,
+            timeout: None,
+            count: None,
+            delimiter: b'\n',
+            fd: libc::STDIN_FILENO,
+            names: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        let mut rest = args.as_slice();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                rest = args.as_slice();
+                break;
+            }
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                break;
+            };
+            for (index, flag) in flags.char_indices() {
+                match flag {
+                    'r' => options.raw = true,
+                    's' => options.silent = true,
+                    'a' | 'd' | 'n' | 'p' | 't' | 'u' => {
+                        let attached = &flags[index + 1..];
+                        let value = if attached.is_empty() {
+                            args.next().ok_or_else(|| {
+                                (format!("-{flag}: option requires an argument"), 2)
+                            })?
+                        } else {
+                            attached
+                        };
+                        options.set(flag, value)?;
+                        break;
+                    }
+                    _ => return Err((format!("-{flag}: invalid option"), 2)),
+                }
+            }
+            rest = args.as_slice();
+        }
+        options.names = rest.to_vec();
+        Ok(options)
+    }
+
+    fn set(&mut self, flag: char, value: &str) -> Result<(), (String, i32)> {
+        match flag {
+            'a' => self.array = Some(value.to_string()),
+            'd' => self.delimiter = value.bytes().next().unwrap_or(0),
+            'p' => self.prompt = Some(value.to_string()),
+            'n' => {
+                let count = value
+                    .parse()
+                    .map_err(|_| (format!("{value}: invalid number"), 1))?;
+                self.count = Some(count);
+            }
+            't' => {
+                let timeout = value
+                    .parse()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(|| (format!("{value}: invalid timeout specification"), 1))?;
+                self.timeout = Some(timeout);
+            }
+            _ => {
+                self.fd = value.parse().ok().filter(|fd| *fd >= 0).ok_or_else(|| {
+                    (format!("{value}: invalid file descriptor specification"), 1)
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why reading stopped.
+#[derive(Debug)]
+enum End {
+    /// The delimiter was read, or `-n` characters were.
+    Delimiter,
+    Eof,
+    TimedOut,
+    Interrupted,
+    Failed(io::Error),
+}
+
+/// Reads up to the delimiter. Unless `-r` was given, a backslash quotes the
+/// byte after it, which is marked so it can't split fields, and a
+/// backslash-newline pair is dropped.
+fn read_line(options: &ReadOptions) -> (Vec<(u8, bool)>, End) {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut line = Vec::new();
+    let mut chars = 0;
+    // Bytes still to come for the last character, so `-n` never stops in
+    // the middle of one.
+    let mut pending: usize = 0;
+    let mut escaped = false;
+    let mut input = Input::new(options.fd);
+    loop {
+        if pending == 0 && !escaped && options.count.is_some_and(|count| chars >= count) {
+            return (line, End::Delimiter);
+        }
+        let byte = match input.read_byte(deadline) {
+            Ok(Some(byte)) => byte,
+            Ok(None) => return (line, End::Eof),
+            Err(end) => return (line, end),
+        };
+
+        let quoted = mem::take(&mut escaped);
+        if quoted && byte == b'\n' {
+            continue;
+        } else if !quoted && byte == options.delimiter {
+            return (line, End::Delimiter);
+        } else if !quoted && byte == b'\\' && !options.raw {
+            escaped = true;
+            continue;
+        }
+        line.push((byte, quoted));
+        if byte & 0xc0 == 0x80 {
+            pending = pending.saturating_sub(1);
+        } else {
+            chars += 1;
+            pending = (byte.leading_ones() as usize).saturating_sub(1);
+        }
+    }
+}
+
+/// Where `read` takes its bytes from.
+enum Input {
+    Fd(c_int),
+    /// Stdin when it isn't a terminal, shared with the line editor. The
+    /// descriptor is non-blocking while it's held, so an empty buffer can
+    /// wait in `poll` for the deadline or a Ctrl-C like any other fd.
+    Stdin {
+        stdin: StdinLock<'static>,
+        flags: c_int,
+    },
+}
+
+impl Input {
+    fn new(fd: c_int) -> Self {
+        let stdin = io::stdin();
+        if fd != libc::STDIN_FILENO || stdin.is_terminal() {
+            return Self::Fd(fd);
+        }
+        // SAFETY: fcntl only changes the descriptor's status flags; they're
+        // restored on drop.
+        let flags = unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            flags
+        };
+        Self::Stdin {
+            stdin: stdin.lock(),
+            flags,
+        }
+    }
+
+    /// Reads one byte, or `None` at end of input.
+    fn read_byte(&mut self, deadline: Option<Instant>) -> Result<Option<u8>, End> {
+        match self {
+            Self::Fd(fd) => read_byte(*fd, deadline),
+            Self::Stdin { stdin, .. } => loop {
+                match stdin.fill_buf() {
+                    Ok(buf) => {
+                        let Some(&byte) = buf.first() else {
+                            return Ok(None);
+                        };
+                        stdin.consume(1);
+                        return Ok(Some(byte));
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        wait_for_input(libc::STDIN_FILENO, deadline)?;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(End::Failed(err)),
+                }
+            },
+        }
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        if let Self::Stdin { flags, .. } = self {
+            // SAFETY: restores the flags saved in `new`.
+            unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_SETFL, *flags) };
+        }
+    }
+}
+
+fn read_byte(fd: c_int, deadline: Option<Instant>) -> Result<Option<u8>, End> {
+    loop {
+        wait_for_input(fd, deadline)?;
+        let mut byte = 0u8;
+        // SAFETY: reads at most one byte into `byte`.
+        match unsafe { libc::read(fd, (&raw mut byte).cast(), 1) } {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(End::Failed(err));
+                }
+            }
+        }
+    }
+}
+
+/// Waits until `fd` has input or `deadline` passes. A Ctrl-C gives up
+/// waiting, which a plain blocking read wouldn't since signals restart it.
+fn wait_for_input(fd: c_int, deadline: Option<Instant>) -> Result<(), End> {
+    loop {
+        let timeout_ms = match deadline {
+            None => -1,
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                left.as_micros().div_ceil(1000).min(c_int::MAX as u128) as c_int
+            }
+        };
+        let mut poll_fd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll_fd` is a single valid pollfd.
+        match unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } {
+            0 => return Err(End::TimedOut),
+            ready if ready > 0 => return Ok(()),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(End::Failed(err));
+                }
+                if interrupt_pending() {
+                    return Err(End::Interrupted);
+                }
+            }
+        }
+    }
+}
+
+/// Terminal settings for `-s` and for reads that mustn't wait for a whole
+/// line, restored when dropped.
+struct TerminalMode {
+    fd: c_int,
+    saved: libc::termios,
+}
+
+impl TerminalMode {
+    fn set(fd: c_int, silent: bool, unbuffered: bool) -> Option<Self> {
+        if !silent && !unbuffered {
+            return None;
+        }
+        // SAFETY: `saved` is filled in by tcgetattr before it is used.
+        unsafe {
+            let mut saved: libc::termios = mem::zeroed();
+            if libc::tcgetattr(fd, &mut saved) != 0 {
+                return None;
+            }
+            let mut mode = saved;
+            if silent {
+                mode.c_lflag &= !libc::ECHO;
+            }
+            if unbuffered {
+                mode.c_lflag &= !libc::ICANON;
+                mode.c_cc[libc::VMIN] = 1;
+                mode.c_cc[libc::VTIME] = 0;
+            }
+            libc::tcsetattr(fd, libc::TCSANOW, &mode);
+            Some(Self { fd, saved })
+        }
+    }
+}
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        // SAFETY: restores settings tcgetattr returned for the same fd.
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+fn text(bytes: &[(u8, bool)]) -> String {
+    let bytes: Vec<u8> = bytes.iter().map(|(byte, _)| *byte).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Splits `line` into `count` fields the way bash's `read` does, or into
+/// as many as there are without a `count`. Runs of `IFS` whitespace
+/// separate fields and are trimmed from both ends; any other `IFS`
+/// character separates fields on its own. With a `count`, the last field
+/// gets the rest of the line, minus a single trailing separator.
+fn split_fields(line: &[(u8, bool)], ifs: &[u8], count: Option<usize>) -> Vec<String> {
+    let is_separator = |(byte, escaped): &(u8, bool)| !escaped && ifs.contains(byte);
+    let is_space = |entry: &(u8, bool)| is_separator(entry) && b" \t\n".contains(&entry.0);
+    let skip_separator = |rest: &[(u8, bool)]| -> usize {
+        let mut index = rest.iter().take_while(|entry| is_space(entry)).count();
+        if rest
+            .get(index)
+            .is_some_and(|entry| is_separator(entry) && !is_space(entry))
+        {
+            index += 1;
+            index += rest[index..]
+                .iter()
+                .take_while(|entry| is_space(entry))
+                .count();
+        }
+        index
+    };
+
+    let start = line.iter().take_while(|entry| is_space(entry)).count();
+    let end = line.len()
+        - line[start..]
+            .iter()
+            .rev()
+            .take_while(|entry| is_space(entry))
+            .count();
+    let mut rest = &line[start..end];
+    let mut fields = Vec::new();
+    while count.is_none_or(|count| fields.len() + 1 < count) && !rest.is_empty() {
+        let field_end = rest.iter().position(is_separator).unwrap_or(rest.len());
+        fields.push(text(&rest[..field_end]));
+        rest = &rest[field_end..];
+        rest = &rest[skip_separator(rest)..];
+    }
+
+    let Some(count) = count else {
+        return fields;
+    };
+    if fields.len() < count {
+        let field_end = rest.iter().position(is_separator).unwrap_or(rest.len());
+        let last = if field_end + skip_separator(&rest[field_end..]) == rest.len() {
+            &rest[..field_end]
+        } else {
+            rest
+        };
+        fields.push(text(last));
+    }
+    fields.resize(count, String::new());
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unescaped(line: &str) -> Vec<(u8, bool)> {
+        line.bytes().map(|byte| (byte, false)).collect()
+    }
+
+    #[test]
+    fn fields_split_like_bash() {
+        let ws = b" \t\n";
+        assert_eq!(
+            vec!["x", "y  z"],
+            split_fields(&unescaped("  x   y  z  "), ws, Some(2))
+        );
+        assert_eq!(
+            vec!["x", "y", ""],
+            split_fields(&unescaped("x y"), ws, Some(3))
+        );
+        assert_eq!(
+            vec!["x", "y"],
+            split_fields(&unescaped("x:y:"), b":", Some(2))
+        );
+        assert_eq!(
+            vec!["x", "y::"],
+            split_fields(&unescaped("x:y::"), b":", Some(2))
+        );
+        assert_eq!(
+            vec!["1", "2", ",3"],
+            split_fields(&unescaped("1 , 2,,3"), b", ", Some(3))
+        );
+        assert_eq!(
+            vec![" p q ", ""],
+            split_fields(&unescaped(" p q "), b"", Some(2))
+        );
+
+        let mut quoted = unescaped("a b");
+        quoted[1].1 = true;
+        assert_eq!(vec!["a b", ""], split_fields(&quoted, ws, Some(2)));
+
+        assert_eq!(
+            vec!["x", "y", "z"],
+            split_fields(&unescaped(" x y  z "), ws, None)
+        );
+        assert_eq!(
+            vec!["x", "", "y"],
+            split_fields(&unescaped("x::y:"), b":", None)
+        );
+        assert!(split_fields(&unescaped("  "), ws, None).is_empty());
+    }
+
+    #[test]
+    fn options_group_and_take_values() {
+        let args: Vec<String> = ["-rsp>", "-d", "", "-n3", "-t", "1.5", "a", "-b"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = ReadOptions::parse(&args).unwrap();
+        assert!(options.raw && options.silent);
+        assert_eq!(Some(">".to_string()), options.prompt);
+        assert_eq!(0, options.delimiter);
+        assert_eq!(Some(3), options.count);
+        assert_eq!(Some(Duration::from_millis(1500)), options.timeout);
+        assert_eq!(vec!["a", "-b"], options.names);
+
+        let bad = ReadOptions::parse(&["-t".to_string(), "soon".to_string()]);
+        assert_eq!(
+            Err(("soon: invalid timeout specification".to_string(), 1)),
+            bad
+        );
+        let missing = ReadOptions::parse(&["-u".to_string()]);
+        assert_eq!(
+            Err(("-u: option requires an argument".to_string(), 2)),
+            missing
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+};
+
+use crate::shell::Shell;
+
+const SYSTEM_PROFILE: &str = "/etc/shell_profile";
+const USER_PROFILE: &str = ".shell_profile";
+const SYSTEM_RC: &str = "/etc/shellrc";
+const USER_RC: &str = ".shellrc";
+
+/// Overrides the per-user rc file when set, like `ENV` in POSIX shells.
+const RC_OVERRIDE_VAR: &str = "ENV";
+
+/// How the shell was invoked, as far as startup files are concerned.
+#[derive(Debug, Default, PartialEq)]
+pub struct StartupOptions {
+    pub login: bool,
+    pub norc: bool,
+    pub noprofile: bool,
+}
+
+impl StartupOptions {
+    /// Parses the process arguments, including `argv[0]`.
+    ///
+    /// A leading `-` in `argv[0]` marks a login shell, the same convention
+    /// `login(1)` and terminal emulators use.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        if let Some(arg0) = args.next() {
+            options.login = arg0.starts_with('-');
+        }
+
+        for arg in args {
+            match arg.as_str() {
+                "-l" | "--login" => options.login = true,
+                "--norc" => options.norc = true,
+                "--noprofile" => options.noprofile = true,
+                other if other.starts_with('-') => return Err(format!("{other}: invalid option")),
+                other => {
+                    return Err(format!(
+                        "{other}: script files can't be run by name; pipe the script to the shell instead"
+                    ));
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// The files to source, in order: the profiles for a login shell and
+    /// the rc files for an `interactive` one. Files that don't exist are
+    /// skipped by the caller.
+    fn files(&self, interactive: bool) -> Vec<PathBuf> {
+        let home = env::home_dir();
+        let mut files = Vec::new();
+
+        if self.login && !self.noprofile {
+            files.push(PathBuf::from(SYSTEM_PROFILE));
+            if let Some(home) = &home {
+                files.push(home.join(USER_PROFILE));
+            }
+        }
+
+        if interactive && !self.norc {
+            files.push(PathBuf::from(SYSTEM_RC));
+            match env::var(RC_OVERRIDE_VAR) {
+                Ok(path) if !path.is_empty() => files.push(expand_tilde(&path, home.as_deref())),
+                _ => {
+                    if let Some(home) = &home {
+                        files.push(home.join(USER_RC));
+                    }
+                }
+            }
+        }
+
+        files
+    }
+}
+
+impl Shell {
+    /// Sources the profile and rc files selected by `options`. The rc files
+    /// are only read when stdin is a terminal, which is what makes this an
+    /// interactive shell.
+    ///
+    /// Missing files are silently skipped; anything else that goes wrong is
+    /// reported and startup carries on with the next file.
+    pub fn run_startup_files(&mut self, options: &StartupOptions) {
+        for file in options.files(io::stdin().is_terminal()) {
+            if !file.is_file() {
+                continue;
+            }
+            if let Err(err) = self.source_file(&file) {
+                self.display_error(format!("{}: {}", file.display(), err));
+            }
+        }
+    }
+}
+
+fn expand_tilde(path: &str, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::args;
+
+    #[test]
+    fn dash_in_argv0_marks_login_shell() {
+        let options = StartupOptions::from_args(args(&["-shell"])).unwrap();
+        assert!(options.login);
+
+        let options = StartupOptions::from_args(args(&["shell"])).unwrap();
+        assert!(!options.login);
+    }
+
+    #[test]
+    fn flags_are_parsed() {
+        let options =
+            StartupOptions::from_args(args(&["shell", "-l", "--norc", "--noprofile"])).unwrap();
+        assert_eq!(
+            StartupOptions {
+                login: true,
+                norc: true,
+                noprofile: true
+            },
+            options
+        );
+
+        assert_eq!(
+            Err("--bogus: invalid option".to_string()),
+            StartupOptions::from_args(args(&["shell", "--bogus"]))
+        );
+        let err = StartupOptions::from_args(args(&["shell", "script.sh"])).unwrap_err();
+        assert!(err.starts_with("script.sh: script files can't be run by name"));
+    }
+
+    #[test]
+    fn profiles_are_only_read_by_login_shells() {
+        let options = StartupOptions::default();
+        assert!(!options.files(true).contains(&PathBuf::from(SYSTEM_PROFILE)));
+
+        let options = StartupOptions {
+            login: true,
+            ..Default::default()
+        };
+        assert!(options.files(true).contains(&PathBuf::from(SYSTEM_PROFILE)));
+
+        let options = StartupOptions {
+            login: true,
+            norc: true,
+            noprofile: true,
+        };
+        assert!(options.files(true).is_empty());
+    }
+
+    #[test]
+    fn rc_files_are_only_read_by_interactive_shells() {
+        let options = StartupOptions {
+            login: true,
+            ..Default::default()
+        };
+        assert!(options.files(true).contains(&PathBuf::from(SYSTEM_RC)));
+        assert!(!options.files(false).contains(&PathBuf::from(SYSTEM_RC)));
+        assert!(
+            options
+                .files(false)
+                .contains(&PathBuf::from(SYSTEM_PROFILE))
+        );
+    }
+
+    #[test]
+    fn tilde_is_expanded_in_rc_override() {
+        let home = Path::new("/home/user");
+        assert_eq!(
+            PathBuf::from("/home/user/.myrc"),
+            expand_tilde("~/.myrc", Some(home))
+        );
+        assert_eq!(
+            PathBuf::from("~other/rc"),
+            expand_tilde("~other/rc", Some(home))
+        );
+        assert_eq!(
+            PathBuf::from("/etc/rc"),
+            expand_tilde("/etc/rc", Some(home))
+        );
+    }
+}
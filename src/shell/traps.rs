@@ -0,0 +1,390 @@
+//! `trap` and the shell's own signal dispositions.
+//!
+//! Signal handlers only record that a signal arrived. The trap commands run
+//! later from `Shell::run_pending_traps`, between commands, where it is safe
+//! to touch the shell's state.
+
+use std::{
+    collections::BTreeMap,
+    mem, ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use libc::{c_int, sighandler_t};
+
+use crate::shell::Shell;
+use crate::shell::jobs::JOB_CONTROL_SIGNALS;
+
+/// One more than the highest signal number on Linux.
+const SIGNAL_LIMIT: usize = 65;
+
+static PENDING: [AtomicBool; SIGNAL_LIMIT] = [const { AtomicBool::new(false) }; SIGNAL_LIMIT];
+
+extern "C" fn note_signal(signal: c_int) {
+    if let Some(flag) = PENDING.get(signal as usize) {
+        flag.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Whether a Ctrl-C has arrived that hasn't been handled yet, for builtins
+/// that wait on input and should give up.
+pub(super) fn interrupt_pending() -> bool {
+    PENDING[libc::SIGINT as usize].load(Ordering::SeqCst)
+}
+
+const SIGNAL_NAMES: &[(&str, c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Signals an interactive shell ignores so that only its children act on
+/// them.
+const INTERACTIVE_IGNORED: [c_int; 3] = [libc::SIGQUIT, libc::SIGTERM, libc::SIGTTOU];
+
+/// A condition `trap` can attach a command to: a real signal or one of the
+/// shell's pseudo-signals. The variant order is the order `trap -p` lists
+/// them in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trap {
+    Exit,
+    Signal(c_int),
+    Debug,
+    Err,
+    Return,
+}
+
+impl Trap {
+    /// Parses a signal spec: a name with or without `SIG`, in any case, or a
+    /// signal number.
+    fn parse(spec: &str) -> Option<Self> {
+        if let Ok(number) = spec.parse::<c_int>() {
+            return match number {
+                0 => Some(Self::Exit),
+                n if SIGNAL_NAMES.iter().any(|(_, signal)| *signal == n) => Some(Self::Signal(n)),
+                _ => None,
+            };
+        }
+
+        let upper = spec.to_ascii_uppercase();
+        match upper.as_str() {
+            "EXIT" => return Some(Self::Exit),
+            "DEBUG" => return Some(Self::Debug),
+            "ERR" => return Some(Self::Err),
+            "RETURN" => return Some(Self::Return),
+            _ => {}
+        }
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        SIGNAL_NAMES
+            .iter()
+            .find(|(signal_name, _)| *signal_name == name)
+            .map(|(_, signal)| Self::Signal(*signal))
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::Exit => "EXIT".to_string(),
+            Self::Debug => "DEBUG".to_string(),
+            Self::Err => "ERR".to_string(),
+            Self::Return => "RETURN".to_string(),
+            Self::Signal(signal) => SIGNAL_NAMES
+                .iter()
+                .find(|(_, number)| number == signal)
+                .map_or_else(|| signal.to_string(), |(name, _)| format!("SIG{name}")),
+        }
+    }
+}
+
+/// The commands set with `trap`. An empty command means the signal is
+/// ignored.
+#[derive(Debug, Default)]
+pub struct TrapTable {
+    actions: BTreeMap<Trap, String>,
+    /// Set while a trap command runs, so traps don't fire inside traps.
+    running: bool,
+}
+
+impl TrapTable {
+    /// Whether `trap '' signal` is in effect. Children inherit ignored
+    /// signals.
+    pub(super) fn ignores(&self, signal: c_int) -> bool {
+        self.actions
+            .get(&Trap::Signal(signal))
+            .is_some_and(String::is_empty)
+    }
+}
+
+fn set_disposition(signal: c_int, handler: sighandler_t) {
+    set_action(signal, handler, libc::SA_RESTART);
+}
+
+fn set_action(signal: c_int, handler: sighandler_t, flags: c_int) {
+    // SAFETY: `handler` is SIG_DFL, SIG_IGN or `note_signal`, which only
+    // stores to an atomic.
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = flags;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+}
+
+/// Catches Ctrl-C with the shell's own handler until dropped. The line
+/// editor installs a SIGINT handler of its own for as long as it exists,
+/// which would hide the signal from `interrupt_pending`. Blocking calls
+/// aren't restarted meanwhile, so they fail with `EINTR` and the caller can
+/// give up.
+pub(super) struct CatchInterrupts(Option<libc::sigaction>);
+
+impl CatchInterrupts {
+    pub(super) fn new() -> Self {
+        // SAFETY: only reads the current action; `previous` is zeroed, which
+        // is a valid sigaction.
+        let previous = unsafe {
+            let mut previous: libc::sigaction = mem::zeroed();
+            libc::sigaction(libc::SIGINT, ptr::null(), &mut previous);
+            previous
+        };
+        if matches!(previous.sa_sigaction, libc::SIG_DFL | libc::SIG_IGN) {
+            return Self(None);
+        }
+        set_action(libc::SIGINT, catcher(), 0);
+        Self(Some(previous))
+    }
+}
+
+impl Drop for CatchInterrupts {
+    fn drop(&mut self) {
+        if let Some(previous) = &self.0 {
+            // SAFETY: puts back the action read in `new`.
+            unsafe {
+                libc::sigaction(libc::SIGINT, previous, ptr::null_mut());
+            }
+        }
+    }
+}
+
+fn catcher() -> sighandler_t {
+    note_signal as extern "C" fn(c_int) as sighandler_t
+}
+
+/// Quotes `text` for `trap -p` so the output can be read back in.
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+impl Shell {
+    /// Sets up the signal dispositions of an interactive shell: Ctrl-C is
+    /// caught so it never kills the shell, and job-control and quit signals
+    /// are ignored. Does nothing when stdin is not a terminal.
+    pub fn init_signals(&mut self) {
+        // SAFETY: plain libc call on stdin.
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+            return;
+        }
+        self.interactive = true;
+        set_disposition(libc::SIGINT, catcher());
+        for signal in INTERACTIVE_IGNORED {
+            set_disposition(signal, libc::SIG_IGN);
+        }
+    }
+
+    /// The disposition a signal has when no trap is set for it.
+    fn untrapped_disposition(&self, signal: c_int) -> sighandler_t {
+        if !self.interactive {
+            libc::SIG_DFL
+        } else if signal == libc::SIGINT {
+            catcher()
+        } else if INTERACTIVE_IGNORED.contains(&signal)
+            || (self.jobs.job_control() && JOB_CONTROL_SIGNALS.contains(&signal))
+        {
+            libc::SIG_IGN
+        } else {
+            libc::SIG_DFL
+        }
+    }
+
+    fn set_trap(&mut self, trap: Trap, action: Option<String>) {
+        if let Trap::Signal(signal) = trap {
+            let handler = match action.as_deref() {
+                None => self.untrapped_disposition(signal),
+                Some("") => libc::SIG_IGN,
+                Some(_) => catcher(),
+            };
+            PENDING[signal as usize].store(false, Ordering::SeqCst);
+            set_disposition(signal, handler);
+        }
+
+        match action {
+            Some(action) => self.traps.actions.insert(trap, action),
+            None => self.traps.actions.remove(&trap),
+        };
+    }
+
+    /// `trap [-lp] [[action] signal_spec ...]`.
+    pub(super) fn trap_builtin(&mut self, args: &[String]) -> i32 {
+        let mut args = args;
+        match args.first().map(String::as_str) {
+            None => return self.print_traps(&[]),
+            Some("-p") => return self.print_traps(&args[1..]),
+            Some("-l") => {
+                for (name, number) in SIGNAL_NAMES {
+                    self.display_result(format!("{number:2}) SIG{name}"));
+                }
+                return 0;
+            }
+            Some("--") => args = &args[1..],
+            Some(option) if option.len() > 1 && option.starts_with('-') => {
+                self.display_error(format!("trap: {option}: invalid option"));
+                self.display_error("trap: usage: trap [-lp] [[arg] signal_spec ...]".to_string());
+                return 2;
+            }
+            _ => {}
+        }
+
+        // A lone signal spec, or `-` as the action, resets to the default
+        let (action, specs) = match args {
+            [] => return self.print_traps(&[]),
+            [dash, specs @ ..] if dash == "-" => (None, specs),
+            [_] => (None, args),
+            [action, specs @ ..] => (Some(action.clone()), specs),
+        };
+
+        let mut status = 0;
+        for spec in specs {
+            match Trap::parse(spec) {
+                Some(trap) => self.set_trap(trap, action.clone()),
+                None => {
+                    self.display_error(format!("trap: {spec}: invalid signal specification"));
+                    status = 1;
+                }
+            }
+        }
+        status
+    }
+
+    /// Prints the traps named by `specs`, or all of them, as `trap`
+    /// commands.
+    fn print_traps(&self, specs: &[String]) -> i32 {
+        let mut status = 0;
+        let mut traps = Vec::new();
+        if specs.is_empty() {
+            traps.extend(self.traps.actions.keys().copied());
+        }
+        for spec in specs {
+            match Trap::parse(spec) {
+                Some(trap) => traps.push(trap),
+                None => {
+                    self.display_error(format!("trap: {spec}: invalid signal specification"));
+                    status = 1;
+                }
+            }
+        }
+
+        for trap in traps {
+            if let Some(action) = self.traps.actions.get(&trap) {
+                self.display_result(format!("trap -- {} {}", single_quote(action), trap.name()));
+            }
+        }
+        status
+    }
+
+    /// Runs the command set for `trap`, if any.
+    pub(super) fn run_trap(&mut self, trap: Trap) {
+        if self.traps.running {
+            return;
+        }
+        if let Some(action) = self.traps.actions.get(&trap).cloned() {
+            self.run_trap_action(&action);
+        }
+    }
+
+    /// Runs a trap command, with other traps held off until it is done.
+    fn run_trap_action(&mut self, action: &str) {
+        if action.is_empty() {
+            return;
+        }
+        let outer_running = mem::replace(&mut self.traps.running, true);
+        self.run_nested(action);
+        self.traps.running = outer_running;
+    }
+
+    /// Runs the traps for any signals that arrived since the last call.
+    pub fn run_pending_traps(&mut self) {
+        if self.traps.running {
+            return;
+        }
+        for (signal, flag) in PENDING.iter().enumerate() {
+            if flag.swap(false, Ordering::SeqCst) {
+                self.run_trap(Trap::Signal(signal as c_int));
+            }
+        }
+    }
+
+    /// Runs the `EXIT` trap. It is cleared first, so an `exit` inside it
+    /// doesn't run it again.
+    pub fn run_exit_trap(&mut self) {
+        if let Some(action) = self.traps.actions.remove(&Trap::Exit) {
+            self.run_trap_action(&action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signal_specs_parse() {
+        assert_eq!(Some(Trap::Signal(libc::SIGINT)), Trap::parse("INT"));
+        assert_eq!(Some(Trap::Signal(libc::SIGINT)), Trap::parse("sigint"));
+        assert_eq!(Some(Trap::Signal(libc::SIGTERM)), Trap::parse("15"));
+        assert_eq!(Some(Trap::Exit), Trap::parse("0"));
+        assert_eq!(Some(Trap::Exit), Trap::parse("exit"));
+        assert_eq!(Some(Trap::Err), Trap::parse("ERR"));
+        assert_eq!(None, Trap::parse("SIGFOO"));
+        assert_eq!(None, Trap::parse("99"));
+    }
+
+    #[test]
+    fn traps_are_listed_in_order() {
+        let mut traps = [
+            Trap::Return,
+            Trap::Signal(libc::SIGTERM),
+            Trap::Debug,
+            Trap::Exit,
+            Trap::Signal(libc::SIGINT),
+        ];
+        traps.sort();
+        let names: Vec<String> = traps.iter().map(Trap::name).collect();
+        assert_eq!(vec!["EXIT", "SIGINT", "SIGTERM", "DEBUG", "RETURN"], names);
+        assert_eq!("'rm -f '\\''a b'\\'''", single_quote("rm -f 'a b'"));
+    }
+}
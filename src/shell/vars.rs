@@ -0,0 +1,219 @@
+use std::{env, path::PathBuf};
+
+use crate::command::{self, Command, Lookup, MetaSymbolExpander};
+use crate::shell::Shell;
+
+impl Lookup for Shell {
+    fn variable(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "#" => Some(self.positional_params.len().to_string()),
+            "@" | "*" => Some(self.positional_params.join(" ")),
+            "-" => Some(self.option_flags()),
+            "0" => env::args().next(),
+            _ => {
+                if let Ok(idx) = name.parse::<usize>() {
+                    return idx
+                        .checked_sub(1)
+                        .and_then(|i| self.positional_params.get(i))
+                        .cloned();
+                }
+                if let Some((array, subscript)) = split_subscript(name) {
+                    return self.array_element(array, subscript);
+                }
+                if let Some(values) = self.arrays.get(name) {
+                    return values.first().cloned();
+                }
+                self.variables
+                    .get(name)
+                    .cloned()
+                    .or_else(|| env::var(name).ok())
+            }
+        }
+    }
+
+    fn command_path(&self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return Command::find_executable(name, &self.working_dir);
+        }
+        self.commands.borrow_mut().find(name)
+    }
+
+    fn directory_stack_entry(&self, spec: &str) -> Option<PathBuf> {
+        self.stack_entry(spec)
+    }
+
+    fn is_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    fn unset_variable(&self, name: &str) {
+        if self.options.nounset {
+            self.unbound_variable
+                .borrow_mut()
+                .get_or_insert_with(|| name.to_string());
+        }
+    }
+}
+
+impl Shell {
+    /// The names of the shell's variables and the exported ones, sorted.
+    pub fn variable_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .variables
+            .keys()
+            .chain(self.arrays.keys())
+            .cloned()
+            .chain(env::vars_os().filter_map(|(name, _)| name.into_string().ok()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Assigns a variable, keeping it exported if it already was. For an
+    /// array, the first element is assigned, as in bash.
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        if let Some(values) = self.arrays.get_mut(name) {
+            match values.first_mut() {
+                Some(first) => *first = value.to_string(),
+                None => values.push(value.to_string()),
+            }
+            return;
+        }
+        if env::var_os(name).is_some() {
+            set_env_var(name, value);
+        } else {
+            self.variables.insert(name.to_string(), value.to_string());
+        }
+        if name.starts_with("HIST") {
+            self.configure_history();
+        }
+    }
+
+    /// `export [name[=value]...]`. Without arguments, lists the exported
+    /// variables.
+    pub(super) fn export_builtin(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            let mut exported: Vec<(String, String)> = env::vars().collect();
+            exported.sort();
+            for (name, value) in exported {
+                self.display_result(format!("declare -x {name}=\"{value}\""));
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match command::split_assignment(arg) {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.clone(), None),
+            };
+            if !command::is_valid_name(&name) {
+                self.display_error(format!("export: `{arg}': not a valid identifier"));
+                status = 1;
+                continue;
+            }
+
+            let value = value.or_else(|| self.variables.get(&name).cloned());
+            self.variables.remove(&name);
+            if let Some(value) = value {
+                set_env_var(&name, &value);
+            }
+            if name.starts_with("HIST") {
+                self.configure_history();
+            }
+        }
+        status
+    }
+}
+
+impl Shell {
+    /// Makes `name` an array holding `values`, replacing any variable of
+    /// that name.
+    pub(super) fn set_array(&mut self, name: &str, values: Vec<String>) {
+        self.variables.remove(name);
+        self.arrays.insert(name.to_string(), values);
+    }
+
+    /// `name=(word ...)`: assigns the expanded words to the array `name`.
+    /// Returns `false` if `line` isn't an array assignment.
+    pub(super) fn assign_array(&mut self, line: &str) -> bool {
+        let Some((name, elements)) = command::array_assignment(line) else {
+            return false;
+        };
+        let values = MetaSymbolExpander::with_lookup(elements.chars(), &*self).collect();
+        self.set_array(&name, values);
+        self.last_status = 0;
+        true
+    }
+
+    /// `${array[subscript]}`: one element, counting from the end when
+    /// negative, or all of them joined with spaces for `@` and `*`. A plain
+    /// variable counts as an array of one.
+    fn array_element(&self, array: &str, subscript: &str) -> Option<String> {
+        let values = match self.arrays.get(array) {
+            Some(values) => values.clone(),
+            None => self.variable(array).into_iter().collect(),
+        };
+        if matches!(subscript, "@" | "*") {
+            return Some(values.join(" "));
+        }
+        let index: isize = subscript.parse().ok()?;
+        let index = if index < 0 {
+            values.len().checked_sub(index.unsigned_abs())?
+        } else {
+            index as usize
+        };
+        values.get(index).cloned()
+    }
+}
+
+/// Splits `name[subscript]` into its parts.
+fn split_subscript(name: &str) -> Option<(&str, &str)> {
+    let (array, subscript) = name.strip_suffix(']')?.split_once('[')?;
+    command::is_valid_name(array).then_some((array, subscript))
+}
+
+fn set_env_var(name: &str, value: &str) {
+    // SAFETY: the shell is single-threaded, so nothing can read the
+    // environment while it is being modified.
+    unsafe { env::set_var(name, value) };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn positional_parameters_are_looked_up_by_number() {
+        let mut shell = Shell::new();
+        shell.positional_params = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(Some("a".to_string()), shell.variable("1"));
+        assert_eq!(Some("b".to_string()), shell.variable("02"));
+        assert_eq!(None, shell.variable("3"));
+        assert_eq!(None, shell.variable("00"));
+
+        let words: Vec<String> = Command::parse_with("echo ${00}x", &shell).words;
+        assert_eq!(vec!["echo", "x"], words);
+    }
+
+    #[test]
+    fn array_elements_are_looked_up_by_subscript() {
+        let mut shell = Shell::new();
+        shell.run_line("ARR=(a 'b' \"c\")");
+        let words = Command::parse_with(
+            "echo $ARR ${ARR[1]} ${ARR[-1]} ${ARR[5]}x ${ARR[@]}",
+            &shell,
+        )
+        .words;
+        assert_eq!(vec!["echo", "a", "b", "c", "x", "a b c"], words);
+
+        shell.set_variable("ARR", "z");
+        assert_eq!(Some("z b c".to_string()), shell.variable("ARR[*]"));
+        shell.set_variable("PLAIN", "p");
+        assert_eq!(Some("p".to_string()), shell.variable("PLAIN[0]"));
+        assert_eq!(None, shell.variable("PLAIN[1]"));
+    }
+}
@@ -0,0 +1,14 @@
+mod common;
+
+use common::run_script;
+
+#[test]
+fn background_builtins_run_in_a_copy_of_the_shell() {
+    let output = run_script("cd / &\nwait\npwd\nf() { echo $$; }\nf &\nwait\necho $$\n");
+    let mut lines = output.lines();
+    let dir = std::env::current_dir().unwrap();
+    assert_eq!(Some(dir.to_str().unwrap()), lines.next());
+    let (child, shell) = (lines.next(), lines.next());
+    assert!(shell.is_some_and(|pid| pid.parse::<u32>().is_ok()));
+    assert_eq!(shell, child);
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::run_script;
+
+#[test]
+fn commands_are_separated_by_list_operators() {
+    let output = run_script(
+        "echo a; echo b\n\
+         false && echo no || echo yes\n\
+         f() { echo in f; }; f\n\
+         echo 'x;y' # not run; echo z\n\
+         ls | wc\n\
+         echo $?\n",
+    );
+    assert_eq!("a\nb\nyes\nin f\nx;y\n2\nCTRL-D\n", output);
+}
+
+#[test]
+fn errexit_ignores_tested_commands() {
+    let output = run_script(
+        "set -e\n\
+         false || echo recovered\n\
+         false && echo never\n\
+         echo still here\n\
+         false\n\
+         echo not reached\n",
+    );
+    assert_eq!("recovered\nstill here\n", output);
+}
@@ -0,0 +1,10 @@
+mod common;
+
+use common::run_script;
+
+#[test]
+fn read_takes_the_next_line_of_a_piped_script() {
+    let output =
+        run_script("read x\nhello world\necho \"[$x]\"\nread -r a b\none two three\necho \"$b\"\n");
+    assert_eq!("[hello world]\ntwo three\nCTRL-D\n", output);
+}